@@ -1,7 +1,5 @@
-use std::io::Read;
-
 use clap::{crate_authors, crate_version, App, Arg};
-use headscratcher::parser::header;
+use headscratcher::parser::stream;
 
 fn main() -> std::io::Result<()> {
     let libv = crate_version!();
@@ -21,7 +19,7 @@ fn main() -> std::io::Result<()> {
             Arg::with_name("dimensions")
                 .short("d")
                 .long("dimension")
-                .value_name("INDEX")
+                .value_name("INDEX|NAME")
                 .takes_value(true)
                 .multiple(true)
                 .help("Print all information about a dimension (i.e. coordinate variable)"),
@@ -59,29 +57,29 @@ fn main() -> std::io::Result<()> {
         )
         .get_matches();
 
+    // only the header is read, however large the file
     let mut file = std::fs::File::open(matches.value_of("INPUT").unwrap())?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).unwrap();
-
-    let (_, h) = header(buffer.as_slice()).unwrap();
-    match matches.values_of("variables") {
-        Some(variables) => {
-            let vars = h.vars.unwrap();
-            for v in variables.into_iter() {
-                println!("{:#?}", vars[v])
-            }
+    let (h, _) = stream::header(&mut file).unwrap();
+    if let Some(variables) = matches.values_of("variables") {
+        let vars = h.vars.as_ref().unwrap();
+        for v in variables.into_iter() {
+            println!("{:#?}", vars[v])
         }
-        _ => (),
     }
-    match matches.values_of("dimensions") {
-        Some(dimensions) => {
-            let dims = h.dims.unwrap();
-            for v in dimensions.into_iter() {
-                let value: usize = v.parse().unwrap();
-                println!("{:#?}", dims[&value])
+    if let Some(dimensions) = matches.values_of("dimensions") {
+        for v in dimensions.into_iter() {
+            let dim = match v.parse::<usize>() {
+                Ok(id) => &h.dims.as_ref().unwrap()[&id],
+                Err(_) => h.dimension(v).unwrap(),
+            };
+            println!("{:#?}", dim);
+            if h.is_unlimited(&dim.name()).unwrap() {
+                match h.dimension_length(&dim.name()) {
+                    Ok(records) => println!("records: {}", records),
+                    Err(_) => println!("records: unknown (streaming)"),
+                }
             }
         }
-        _ => (),
     }
     if matches.is_present("global") {
         let gattrs = h.attrs.unwrap();
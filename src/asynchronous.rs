@@ -0,0 +1,179 @@
+//! Async reader for tokio
+//!
+//! [`AsyncNetCDF`] parses the header and reads slabs of classic files over
+//! `tokio::io::AsyncRead + AsyncSeek`, so reads never block the runtime. It is
+//! available with the `async` feature. netCDF-4 files are not supported, as
+//! their B-trees are only walked synchronously.
+use crate::data::NetCDFData;
+use crate::error::HeadScratcherError as HSE;
+use crate::parser::stream::Stream;
+use crate::parser::NetCDFHeader;
+use crate::selection::Selection;
+use std::io::SeekFrom;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+
+/// Classic netCDF file read asynchronously
+#[derive(Debug)]
+pub struct AsyncNetCDF<F: AsyncRead + AsyncSeek + Unpin> {
+    file: F,
+    header: NetCDFHeader,
+}
+
+impl<F: AsyncRead + AsyncSeek + Unpin> AsyncNetCDF<F> {
+    pub async fn new_from_file(mut file: F) -> Result<Self, HSE<String>> {
+        let header = header(&mut file).await?;
+        Ok(AsyncNetCDF { file, header })
+    }
+
+    pub fn header(&self) -> &NetCDFHeader {
+        &self.header
+    }
+
+    /// Fill `buffer` with the raw bytes of a variable from `start` on
+    ///
+    /// See [`crate::NetCDF::update_buffer`].
+    pub async fn update_buffer(
+        &mut self,
+        variable: String,
+        start: &[usize],
+        buffer: &mut [u8],
+    ) -> Result<(), HSE<String>> {
+        let runs = crate::range_runs(&self.header, variable, start, buffer.len())?;
+        self.read_runs(&runs, buffer).await
+    }
+
+    /// Fill `buffer` with the raw bytes of a selection, in selection order
+    pub async fn update_buffer_selection(
+        &mut self,
+        variable: String,
+        selection: &Selection,
+        buffer: &mut [u8],
+    ) -> Result<(), HSE<String>> {
+        let runs = crate::selection_runs(&self.header, variable, selection, buffer.len())?;
+        self.read_runs(&runs, buffer).await
+    }
+
+    /// Fill `buffer` with the byte runs `(offset, length)`, one after another
+    async fn read_runs(
+        &mut self,
+        runs: &[(u64, usize)],
+        buffer: &mut [u8],
+    ) -> Result<(), HSE<String>> {
+        let mut pos = 0;
+        for (offset, len) in runs {
+            self.file.seek(SeekFrom::Start(*offset)).await?;
+            self.file.read_exact(&mut buffer[pos..pos + len]).await?;
+            pos += len;
+        }
+        Ok(())
+    }
+
+    /// Read the slab `start..start + count` of a variable as native values
+    pub async fn read_slab<T: NetCDFData>(
+        &mut self,
+        variable: String,
+        start: &[usize],
+        count: &[usize],
+    ) -> Result<Vec<T>, HSE<String>> {
+        let selection = Selection::new(start.to_vec(), count.to_vec());
+        self.read_selection(variable, &selection).await
+    }
+
+    /// Read a (strided) selection of a variable as native values
+    pub async fn read_selection<T: NetCDFData>(
+        &mut self,
+        variable: String,
+        selection: &Selection,
+    ) -> Result<Vec<T>, HSE<String>> {
+        crate::check_type::<T>(&self.header, &variable)?;
+        self.header.check_selection(&variable, selection)?;
+        let mut buffer = vec![0u8; selection.bytes(T::NC_TYPE.extsize())?];
+        self.update_buffer_selection(variable, selection, &mut buffer)
+            .await?;
+        Ok(T::decode(&buffer))
+    }
+}
+
+impl AsyncNetCDF<tokio::fs::File> {
+    pub async fn new(filename: String) -> Result<Self, HSE<String>> {
+        AsyncNetCDF::new_from_file(tokio::fs::File::open(&filename).await?).await
+    }
+}
+
+/// Parse the header at the start of `file`
+async fn header<F: AsyncRead + AsyncSeek + Unpin>(
+    file: &mut F,
+) -> Result<NetCDFHeader, HSE<String>> {
+    file.seek(SeekFrom::Start(0)).await?;
+    let mut s = Stream::new();
+    while let Some(missing) = s.advance()? {
+        (&mut *file)
+            .take(missing as u64)
+            .read_to_end(s.input())
+            .await?;
+    }
+    Ok(s.finish().0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NetCDF;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn test_async_read() {
+        let filename = "assets/sresa1b_ncar_ccsm3-example.nc".to_string();
+        let mut netcdf = NetCDF::new(filename.clone()).unwrap();
+        let mut reader = AsyncNetCDF::new(filename).await.unwrap();
+        assert_eq!(reader.header(), netcdf.header());
+
+        let mut buffer = vec![0u8; 4];
+        reader
+            .update_buffer("tas".to_string(), &[0, 0, 0], &mut buffer)
+            .await
+            .unwrap();
+        assert_eq!(buffer, vec![67, 87, 228, 188]);
+        let selection = Selection::new(vec![0, 10, 3], vec![1, 5, 20]).with_stride(vec![1, 1, 4]);
+        let expected: Vec<f32> = netcdf
+            .read_selection("tas".to_string(), &selection)
+            .unwrap();
+        let tas: Vec<f32> = reader
+            .read_selection("tas".to_string(), &selection)
+            .await
+            .unwrap();
+        assert_eq!(tas, expected);
+        let e = reader
+            .read_slab::<f64>("tas".to_string(), &[0, 0, 0], &[1, 1, 1])
+            .await
+            .unwrap_err();
+        assert!(matches!(e, HSE::TypeMismatch(_)));
+    }
+
+    #[tokio::test]
+    async fn test_async_header() {
+        let bytes = std::fs::read("assets/sresa1b_ncar_ccsm3-example.3_nc64.nc").unwrap();
+        let (rest, expected) = crate::parser::header(&bytes).unwrap();
+        let size = (bytes.len() - rest.len()) as u64;
+        let reader = AsyncNetCDF::new_from_file(Cursor::new(bytes))
+            .await
+            .unwrap();
+        // nothing after the header is read
+        assert_eq!(reader.file.position(), size);
+        // NaN fill values compare unequal, their debug output does not
+        assert_eq!(
+            format!("{:?}", reader.header().vars),
+            format!("{:?}", expected.vars)
+        );
+
+        let e = AsyncNetCDF::new_from_file(Cursor::new(b"CDF\x01"))
+            .await
+            .unwrap_err();
+        assert_eq!(e, HSE::InvalidFile);
+        let bytes = std::fs::read("assets/sresa1b_ncar_ccsm3-example.4.nc").unwrap();
+        let e = AsyncNetCDF::new_from_file(Cursor::new(bytes))
+            .await
+            .unwrap_err();
+        assert_eq!(e, HSE::NetCDF4NotSupported);
+    }
+}
@@ -0,0 +1,364 @@
+//! Creation of new netCDF-3 files
+//!
+//! [`NetCDFBuilder`] collects dimensions, attributes and variables, works out
+//! the data layout and writes a header followed by a data section filled with
+//! the fill value of every variable.
+use crate::error::HeadScratcherError as HSE;
+use crate::parser::components::{
+    AttributeHM, DimensionHM, NetCDFAttribute, NetCDFDimension, NetCDFType, NetCDFVariable,
+    NetCDFVersion, NumberOfRecords, VariableHM,
+};
+use crate::parser::NetCDFHeader;
+use crate::writer;
+use crate::NetCDF;
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Builder for new netCDF-3 files
+#[derive(Debug)]
+pub struct NetCDFBuilder {
+    version: NetCDFVersion,
+    dims: Vec<NetCDFDimension>,
+    attrs: AttributeHM,
+    vars: Vec<(String, Vec<u32>, NetCDFType, AttributeHM)>,
+}
+
+impl NetCDFBuilder {
+    /// Start an empty file of the given format
+    pub fn new(version: NetCDFVersion) -> Self {
+        NetCDFBuilder {
+            version,
+            dims: Vec::new(),
+            attrs: AttributeHM::new(),
+            vars: Vec::new(),
+        }
+    }
+
+    /// Add a dimension of fixed length
+    pub fn add_dimension(&mut self, name: &str, length: usize) -> Result<&mut Self, HSE<String>> {
+        if length == 0 {
+            let msg = format!("Dimension {} has length 0", name);
+            return Err(HSE::InvalidDefinition(msg));
+        }
+        self.push_dimension(name, length)
+    }
+
+    /// Add the unlimited (record) dimension
+    pub fn add_unlimited_dimension(&mut self, name: &str) -> Result<&mut Self, HSE<String>> {
+        if self.dims.iter().any(|d| d.length == 0) {
+            let msg = format!("Second unlimited dimension {}", name);
+            return Err(HSE::InvalidDefinition(msg));
+        }
+        self.push_dimension(name, 0)
+    }
+
+    fn push_dimension(&mut self, name: &str, length: usize) -> Result<&mut Self, HSE<String>> {
+        if self.dims.iter().any(|d| d.name == name) {
+            let msg = format!("Dimension {} already defined", name);
+            return Err(HSE::InvalidDefinition(msg));
+        }
+        self.dims
+            .push(NetCDFDimension::new(name.to_string(), length));
+        Ok(self)
+    }
+
+    /// Add a global attribute
+    pub fn add_attribute(&mut self, attr: NetCDFAttribute) -> Result<&mut Self, HSE<String>> {
+        self.check_type(attr.nc_type(), &attr.name())?;
+        self.attrs.insert(attr.name(), attr);
+        Ok(self)
+    }
+
+    /// Add a variable over the named dimensions
+    ///
+    /// The unlimited dimension may only be used as the first dimension.
+    pub fn add_variable(
+        &mut self,
+        name: &str,
+        dims: &[&str],
+        nc_type: NetCDFType,
+    ) -> Result<&mut Self, HSE<String>> {
+        self.check_type(&nc_type, name)?;
+        if self.vars.iter().any(|v| v.0 == name) {
+            let msg = format!("Variable {} already defined", name);
+            return Err(HSE::InvalidDefinition(msg));
+        }
+        let mut ids = Vec::new();
+        for (k, dim) in dims.iter().enumerate() {
+            let id = match self.dims.iter().position(|d| d.name == *dim) {
+                Some(id) => id,
+                None => return Err(HSE::CouldNotFindDimension(dim.to_string())),
+            };
+            if k > 0 && self.dims[id].length == 0 {
+                let msg = format!("Unlimited dimension {} is not first in {}", dim, name);
+                return Err(HSE::InvalidDefinition(msg));
+            }
+            ids.push(id as u32);
+        }
+        self.vars
+            .push((name.to_string(), ids, nc_type, AttributeHM::new()));
+        Ok(self)
+    }
+
+    /// Add an attribute to a variable
+    pub fn add_variable_attribute(
+        &mut self,
+        variable: &str,
+        attr: NetCDFAttribute,
+    ) -> Result<&mut Self, HSE<String>> {
+        self.check_type(attr.nc_type(), &format!("{}:{}", variable, attr.name()))?;
+        match self.vars.iter_mut().find(|v| v.0 == variable) {
+            Some(v) => {
+                v.3.insert(attr.name(), attr);
+                Ok(self)
+            }
+            None => Err(HSE::VariableNotFound(variable.to_string())),
+        }
+    }
+
+    /// Check that the format version holds values of a type
+    ///
+    /// The unsigned and 64-bit integer types are only part of CDF-5.
+    fn check_type(&self, nc_type: &NetCDFType, name: &str) -> Result<(), HSE<String>> {
+        let extended = matches!(
+            nc_type,
+            NetCDFType::NC_UBYTE
+                | NetCDFType::NC_USHORT
+                | NetCDFType::NC_UINT
+                | NetCDFType::NC_INT64
+                | NetCDFType::NC_UINT64
+        );
+        if extended && self.version != NetCDFVersion::Data64 {
+            let msg = format!("{} of type {:?} needs CDF-5", name, nc_type);
+            return Err(HSE::InvalidDefinition(msg));
+        }
+        Ok(())
+    }
+
+    /// Header with the data layout worked out
+    pub fn header(&self) -> Result<NetCDFHeader, HSE<String>> {
+        let dims: DimensionHM = self
+            .dims
+            .iter()
+            .enumerate()
+            .map(|(k, d)| (k, d.clone()))
+            .collect();
+        let vars: VariableHM = self
+            .vars
+            .iter()
+            .map(|(n, d, t, a)| {
+                let attrs = non_empty(a.clone());
+                let var = NetCDFVariable::new(n.clone(), d.clone(), attrs, *t, 0, 0);
+                (n.clone(), var)
+            })
+            .collect();
+        let (attrs, dims, vars) = (
+            non_empty(self.attrs.clone()),
+            non_empty(dims),
+            non_empty(vars),
+        );
+        let nor = NumberOfRecords::NonNegative(0);
+        let mut h = NetCDFHeader::new(self.version, nor, attrs, dims, vars, None, 0);
+        h.layout()?;
+        Ok(h)
+    }
+
+    /// Create the file at `path` and open it for reading and writing
+    ///
+    /// All fixed size variables are filled with their `_FillValue` attribute or
+    /// the default fill value of their type. The file starts without records.
+    pub fn create<P: AsRef<Path>>(&self, path: P) -> Result<NetCDF<File>, HSE<String>> {
+        if self.version == NetCDFVersion::NetCDF4 {
+            let msg = "netCDF-4 files cannot be created".to_string();
+            return Err(HSE::InvalidDefinition(msg));
+        }
+        let header = self.header()?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.write_all(&header.to_bytes()?)?;
+        if let Some(vars) = &header.vars {
+            for v in vars.values() {
+                if v.is_record() {
+                    continue;
+                }
+                file.seek(SeekFrom::Start(v.begin))?;
+                file.write_all(&fill_bytes(v, v.vsize()))?;
+            }
+        }
+        file.flush()?;
+        Ok(NetCDF {
+            file,
+            header,
+            storage: HashMap::new(),
+        })
+    }
+}
+
+/// `len` bytes of repeated fill values of a variable
+pub(crate) fn fill_bytes(var: &NetCDFVariable, len: usize) -> Vec<u8> {
+    let fill = var
+        .attributes()
+        .as_ref()
+        .and_then(|a| a.get("_FillValue"))
+        .filter(|a| *a.nc_type() == var.nc_type)
+        .map(|a| writer::values(a.value()))
+        .filter(|v| v.len() == var.nc_type.extsize())
+        .unwrap_or_else(|| var.nc_type.fill_value());
+    fill.iter().cycle().take(len).copied().collect()
+}
+
+/// Empty lists are absent in the header
+fn non_empty<K, V>(map: IndexMap<K, V>) -> Option<IndexMap<K, V>> {
+    if map.is_empty() {
+        None
+    } else {
+        Some(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::components::NetCDFTypeInstance;
+    use std::io::Read;
+
+    fn temp_file(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("headscratcher-{}-{}.nc", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_create_small() {
+        let path = temp_file("small");
+        let mut builder = NetCDFBuilder::new(NetCDFVersion::Classic);
+        builder.add_dimension("dim", 5).unwrap();
+        builder
+            .add_variable("vx", &["dim"], NetCDFType::NC_SHORT)
+            .unwrap();
+        let mut netcdf = builder.create(&path).unwrap();
+        netcdf
+            .write_slab("vx".to_string(), &[0], &[5], &[3i16, 1, 4, 1, 5])
+            .unwrap();
+        drop(netcdf);
+
+        let mut created = Vec::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_end(&mut created)
+            .unwrap();
+        let mut expected = Vec::new();
+        File::open("assets/small.nc")
+            .unwrap()
+            .read_to_end(&mut expected)
+            .unwrap();
+        assert_eq!(created, expected);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_create_fill_values() {
+        let path = temp_file("fill");
+        let mut builder = NetCDFBuilder::new(NetCDFVersion::Offset64);
+        builder.add_unlimited_dimension("time").unwrap();
+        builder.add_dimension("lat", 2).unwrap();
+        builder.add_dimension("lon", 3).unwrap();
+        builder
+            .add_variable("lat", &["lat"], NetCDFType::NC_DOUBLE)
+            .unwrap()
+            .add_variable("tas", &["time", "lat", "lon"], NetCDFType::NC_FLOAT)
+            .unwrap()
+            .add_variable("mask", &["lat", "lon"], NetCDFType::NC_INT)
+            .unwrap();
+        let fill = NetCDFTypeInstance::INTS(vec![-1]);
+        let fill = NetCDFAttribute::from_value("_FillValue".to_string(), fill);
+        builder.add_variable_attribute("mask", fill).unwrap();
+        let title = NetCDFTypeInstance::STRING("builder test".to_string());
+        builder
+            .add_attribute(NetCDFAttribute::from_value("title".to_string(), title))
+            .unwrap();
+        let mut netcdf = builder.create(&path).unwrap();
+
+        let lat: Vec<f64> = netcdf.read_slab("lat".to_string(), &[0], &[2]).unwrap();
+        assert_eq!(lat, vec![9.969209968386869e36; 2]);
+        let mask: Vec<i32> = netcdf
+            .read_slab("mask".to_string(), &[0, 0], &[2, 3])
+            .unwrap();
+        assert_eq!(mask, vec![-1; 6]);
+        netcdf
+            .write_slab("mask".to_string(), &[1, 1], &[1, 2], &[7, 8])
+            .unwrap();
+        drop(netcdf);
+
+        let mut netcdf = NetCDF::new(path.to_str().unwrap().to_string()).unwrap();
+        let mask: Vec<i32> = netcdf
+            .read_slab("mask".to_string(), &[0, 0], &[2, 3])
+            .unwrap();
+        assert_eq!(mask, vec![-1, -1, -1, -1, 7, 8]);
+        let vars = netcdf.header().vars.as_ref().unwrap();
+        assert!(vars["tas"].is_record());
+        assert_eq!(netcdf.header().recsize, 24);
+        let title = netcdf.header().attrs.as_ref().unwrap()["title"].as_string();
+        assert_eq!(title, Some("builder test".to_string()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_definitions() {
+        let mut builder = NetCDFBuilder::new(NetCDFVersion::Classic);
+        builder.add_unlimited_dimension("time").unwrap();
+        builder.add_dimension("x", 3).unwrap();
+        assert!(builder.add_unlimited_dimension("time2").is_err());
+        assert!(builder.add_dimension("x", 4).is_err());
+        assert!(builder.add_dimension("y", 0).is_err());
+        assert!(builder
+            .add_variable("v", &["x", "time"], NetCDFType::NC_INT)
+            .is_err());
+        assert!(builder
+            .add_variable("v", &["y"], NetCDFType::NC_INT)
+            .is_err());
+        let attr = NetCDFAttribute::from_value(
+            "units".to_string(),
+            NetCDFTypeInstance::STRING("K".to_string()),
+        );
+        assert!(builder.add_variable_attribute("v", attr).is_err());
+
+        // unsigned and 64-bit integers need CDF-5
+        for version in [NetCDFVersion::Classic, NetCDFVersion::Offset64] {
+            let mut builder = NetCDFBuilder::new(version);
+            builder.add_dimension("x", 3).unwrap();
+            let e = builder
+                .add_variable("u", &["x"], NetCDFType::NC_UINT)
+                .unwrap_err();
+            assert!(matches!(e, HSE::InvalidDefinition(_)));
+            builder
+                .add_variable("v", &["x"], NetCDFType::NC_INT)
+                .unwrap();
+            let attr = NetCDFAttribute::from_value(
+                "valid_max".to_string(),
+                NetCDFTypeInstance::INT64S(vec![7]),
+            );
+            let e = builder
+                .add_variable_attribute("v", attr.clone())
+                .unwrap_err();
+            assert!(matches!(e, HSE::InvalidDefinition(_)));
+            let e = builder.add_attribute(attr).unwrap_err();
+            assert!(matches!(e, HSE::InvalidDefinition(_)));
+        }
+        let mut builder = NetCDFBuilder::new(NetCDFVersion::Data64);
+        builder.add_dimension("x", 3).unwrap();
+        builder
+            .add_variable("u", &["x"], NetCDFType::NC_UINT64)
+            .unwrap();
+        let attr = NetCDFAttribute::from_value(
+            "valid_max".to_string(),
+            NetCDFTypeInstance::UBYTES(vec![7]),
+        );
+        builder.add_variable_attribute("u", attr).unwrap();
+    }
+}
@@ -0,0 +1,238 @@
+//! Block cache
+//!
+//! [`BlockCache`] keeps the most recently used fixed size blocks of a source
+//! in memory. Reads that touch cached blocks cost no request, and missing
+//! blocks that follow each other are read together.
+use crate::source::ByteRangeSource;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+/// Counters of a [`BlockCache`]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CacheStats {
+    /// Blocks found in the cache
+    pub hits: usize,
+    /// Blocks read from the source
+    pub misses: usize,
+    /// Ranges read from the source
+    pub reads: usize,
+    /// Bytes read from the source
+    pub bytes_read: u64,
+}
+
+/// Source with an LRU cache of its blocks
+#[derive(Debug)]
+pub struct BlockCache<S: ByteRangeSource> {
+    source: S,
+    size: u64,
+    block: usize,
+    capacity: usize,
+    /// Cached blocks by index, with their last use
+    blocks: HashMap<u64, (Vec<u8>, u64)>,
+    /// Block indices by last use
+    uses: BTreeMap<u64, u64>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl<S: ByteRangeSource> BlockCache<S> {
+    /// Cache up to `capacity` blocks of `block` bytes of `source`
+    pub fn new(mut source: S, block: usize, capacity: usize) -> io::Result<Self> {
+        let size = source.size()?;
+        Ok(BlockCache {
+            source,
+            size,
+            block: block.max(1),
+            capacity: capacity.max(1),
+            blocks: HashMap::new(),
+            uses: BTreeMap::new(),
+            tick: 0,
+            stats: CacheStats::default(),
+        })
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
+    /// Read the missing blocks among `needed`, runs of blocks at once
+    fn load(&mut self, needed: &[u64]) -> io::Result<HashMap<u64, Vec<u8>>> {
+        let missing: Vec<u64> = needed
+            .iter()
+            .filter(|b| !self.blocks.contains_key(b))
+            .copied()
+            .collect();
+        self.stats.hits += needed.len() - missing.len();
+        self.stats.misses += missing.len();
+        let mut runs: Vec<(u64, u64)> = Vec::new();
+        for b in missing.iter() {
+            match runs.last_mut() {
+                Some(last) if last.1 == *b => last.1 += 1,
+                _ => runs.push((*b, b + 1)),
+            }
+        }
+        let block = self.block as u64;
+        let ranges: Vec<(u64, usize)> = runs
+            .iter()
+            .map(|(first, end)| {
+                let offset = first * block;
+                (offset, ((end * block).min(self.size) - offset) as usize)
+            })
+            .collect();
+        let mut bytes = vec![0u8; ranges.iter().map(|(_, len)| len).sum()];
+        self.source.read_ranges(&ranges, &mut bytes)?;
+        self.stats.reads += ranges.len();
+        self.stats.bytes_read += bytes.len() as u64;
+        Ok(missing
+            .into_iter()
+            .zip(bytes.chunks(self.block))
+            .map(|(b, bytes)| (b, bytes.to_vec()))
+            .collect())
+    }
+
+    /// Mark a block as used, caching it if it is new
+    fn touch(&mut self, b: u64, bytes: Option<Vec<u8>>) {
+        self.tick += 1;
+        match (self.blocks.get_mut(&b), bytes) {
+            (Some(cached), _) => {
+                self.uses.remove(&cached.1);
+                cached.1 = self.tick;
+            }
+            (None, Some(bytes)) => {
+                self.blocks.insert(b, (bytes, self.tick));
+            }
+            (None, None) => return,
+        }
+        self.uses.insert(self.tick, b);
+        while self.blocks.len() > self.capacity {
+            if let Some((_, oldest)) = self.uses.pop_first() {
+                self.blocks.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl<S: ByteRangeSource> ByteRangeSource for BlockCache<S> {
+    fn read_range(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.read_ranges(&[(offset, buf.len())], buf)
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self.size)
+    }
+
+    fn read_ranges(&mut self, ranges: &[(u64, usize)], buffer: &mut [u8]) -> io::Result<()> {
+        let block = self.block as u64;
+        if ranges.iter().any(|(o, len)| o + *len as u64 > self.size) {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let mut needed: Vec<u64> = ranges
+            .iter()
+            .filter(|(_, len)| *len > 0)
+            .flat_map(|(o, len)| o / block..=(o + *len as u64 - 1) / block)
+            .collect();
+        needed.sort_unstable();
+        needed.dedup();
+        let mut loaded = self.load(&needed)?;
+
+        let mut pos = 0;
+        for (offset, len) in ranges {
+            let end = offset + *len as u64;
+            let mut at = *offset;
+            while at < end {
+                let b = at / block;
+                let bytes = match loaded.get(&b) {
+                    Some(bytes) => bytes,
+                    None => &self.blocks[&b].0,
+                };
+                let k = (at - b * block) as usize;
+                let n = (bytes.len() - k).min((end - at) as usize);
+                buffer[pos..pos + n].copy_from_slice(&bytes[k..k + n]);
+                pos += n;
+                at += n as u64;
+            }
+        }
+        for b in needed {
+            self.touch(b, loaded.remove(&b));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NetCDF;
+    use std::fs::File;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_block_cache() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let mut cache = BlockCache::new(Cursor::new(bytes.clone()), 16, 4).unwrap();
+        let mut buf = [0u8; 20];
+        cache.read_range(10, &mut buf).unwrap();
+        assert_eq!(buf[..], bytes[10..30]);
+        let expected = CacheStats {
+            hits: 0,
+            misses: 2,
+            reads: 1,
+            bytes_read: 32,
+        };
+        assert_eq!(cache.stats(), expected);
+        cache.read_range(20, &mut buf).unwrap();
+        assert_eq!(buf[..], bytes[20..40]);
+        assert_eq!((cache.stats().hits, cache.stats().misses), (1, 3));
+
+        // blocks 0, 1 and 2 are cached, 15 and 8 push out the least recent 0
+        let mut buffer = [0u8; 8];
+        cache
+            .read_ranges(&[(250, 4), (130, 2), (40, 2)], &mut buffer)
+            .unwrap();
+        assert_eq!(buffer, [250, 251, 252, 253, 130, 131, 40, 41]);
+        assert_eq!(cache.stats().reads, 2 + 2);
+        cache.reset_stats();
+        cache.read_range(0, &mut buf[..1]).unwrap();
+        assert_eq!(cache.stats().misses, 1);
+        cache.read_range(16, &mut buf[..1]).unwrap();
+        assert_eq!(cache.stats().misses, 2);
+        let e = cache.read_range(250, &mut buf).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_cached_netcdf() {
+        let filename = "assets/sresa1b_ncar_ccsm3-example.nc";
+        let cache = BlockCache::new(File::open(filename).unwrap(), 4096, 64).unwrap();
+        let mut netcdf = NetCDF::new_from_file(cache).unwrap();
+        let mut local = NetCDF::new(filename.to_string()).unwrap();
+        assert_eq!(netcdf.header(), local.header());
+
+        // neighbouring points share their blocks
+        netcdf.file.reset_stats();
+        for lon in 0..64 {
+            let tas: Vec<f32> = netcdf
+                .read_slab("tas".to_string(), &[0, 20, lon], &[1, 1, 1])
+                .unwrap();
+            let expected: Vec<f32> = local
+                .read_slab("tas".to_string(), &[0, 20, lon], &[1, 1, 1])
+                .unwrap();
+            assert_eq!(tas, expected);
+        }
+        let stats = netcdf.file().stats();
+        assert_eq!((stats.hits, stats.misses, stats.bytes_read), (63, 1, 4096));
+
+        // planned batches are served from the cache as well
+        let requests: Vec<_> = (0..64)
+            .map(|lon| ("tas".to_string(), vec![0, 20, lon], vec![1, 1, 1]))
+            .collect();
+        let slabs = netcdf.read_batch(&requests, 0).unwrap();
+        assert_eq!(slabs.len(), 64);
+        let stats = netcdf.file().stats();
+        assert_eq!((stats.hits, stats.misses, stats.reads), (64, 1, 1));
+    }
+}
@@ -0,0 +1,245 @@
+//! CF conventions
+//!
+//! Packed variables store integers together with `scale_factor` and
+//! `add_offset` attributes, the unpacked value being
+//! `packed * scale_factor + add_offset`. Following CF, the unpacked type is
+//! the type of the packing attributes.
+//!
+//! Missing data is marked by `_FillValue`, `missing_value` or values outside
+//! `valid_min`/`valid_max`/`valid_range`, see [`Mask`].
+use crate::data::{self, NetCDFData};
+use crate::error::HeadScratcherError as HSE;
+use crate::parser::components::{NetCDFAttribute, NetCDFType, NetCDFVariable};
+
+/// Packing attributes of a variable
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Packing {
+    pub scale_factor: f64,
+    pub add_offset: f64,
+    /// Type of the unpacked values, `NC_FLOAT` or `NC_DOUBLE`
+    pub unpacked_type: NetCDFType,
+}
+
+impl Packing {
+    /// Packing of a variable, `None` if it has neither `scale_factor` nor `add_offset`
+    pub fn from_variable(var: &NetCDFVariable) -> Result<Option<Self>, HSE<String>> {
+        let scale = var.attribute("scale_factor");
+        let offset = var.attribute("add_offset");
+        let unpacked_type = match (scale, offset) {
+            (None, None) => return Ok(None),
+            (Some(s), Some(o)) if s.nc_type() != o.nc_type() => {
+                let msg = format!(
+                    "scale_factor ({:?}) and add_offset ({:?}) of {} differ in type",
+                    s.nc_type(),
+                    o.nc_type(),
+                    var.name()
+                );
+                return Err(HSE::TypeMismatch(msg));
+            }
+            (Some(a), _) | (_, Some(a)) => *a.nc_type(),
+        };
+        if !matches!(unpacked_type, NetCDFType::NC_FLOAT | NetCDFType::NC_DOUBLE) {
+            let msg = format!(
+                "Packing attributes of {} are {:?}, not float or double",
+                var.name(),
+                unpacked_type
+            );
+            return Err(HSE::TypeMismatch(msg));
+        }
+        let value = |a: Option<&NetCDFAttribute>, default| {
+            a.and_then(|a| a.as_f64_vec())
+                .and_then(|v| v.first().copied())
+                .unwrap_or(default)
+        };
+        Ok(Some(Packing {
+            scale_factor: value(scale, 1.0),
+            add_offset: value(offset, 0.0),
+            unpacked_type,
+        }))
+    }
+
+    /// Unpack values (already widened to `f64`)
+    pub fn unpack<T: NetCDFData>(&self, packed: &[f64]) -> Vec<T> {
+        packed
+            .iter()
+            .map(|v| T::from_f64(v * self.scale_factor + self.add_offset))
+            .collect()
+    }
+
+    /// Unpack values (already widened to `f64`), missing ones as NaN
+    ///
+    /// `mask` is checked against the packed values, so that fill values are
+    /// never scaled into plausible data.
+    pub fn unpack_masked<T: NetCDFData>(&self, packed: &[f64], mask: &Mask) -> Vec<T> {
+        packed
+            .iter()
+            .map(|v| match mask.is_missing(*v) {
+                true => T::from_f64(f64::NAN),
+                false => T::from_f64(v * self.scale_factor + self.add_offset),
+            })
+            .collect()
+    }
+}
+
+/// Rules for marking values of a variable as missing
+#[derive(Debug, PartialEq, Clone)]
+pub struct Mask {
+    /// `_FillValue`, or the default fill value of the type
+    pub fill: Option<f64>,
+    /// Values of `missing_value`
+    pub missing: Vec<f64>,
+    pub valid_min: Option<f64>,
+    pub valid_max: Option<f64>,
+}
+
+impl Mask {
+    /// Masking rules of a variable, in the units of its stored (packed) values
+    ///
+    /// `valid_range` takes precedence over `valid_min` and `valid_max`. Without
+    /// `_FillValue` the default fill value of the type is used, except for
+    /// byte types where every value is considered valid data.
+    pub fn from_variable(var: &NetCDFVariable) -> Self {
+        let values = |name: &str| {
+            var.attribute(name)
+                .and_then(|a| a.as_f64_vec())
+                .unwrap_or_default()
+        };
+        let fill = match var.attribute("_FillValue") {
+            Some(_) => values("_FillValue").first().copied(),
+            None => match var.nc_type {
+                NetCDFType::NC_BYTE | NetCDFType::NC_UBYTE | NetCDFType::NC_CHAR => None,
+                t => data::decode_f64(&t, &t.fill_value()).and_then(|v| v.first().copied()),
+            },
+        };
+        let (valid_min, valid_max) = match values("valid_range").as_slice() {
+            [min, max] => (Some(*min), Some(*max)),
+            _ => (
+                values("valid_min").first().copied(),
+                values("valid_max").first().copied(),
+            ),
+        };
+        Mask {
+            fill,
+            missing: values("missing_value"),
+            valid_min,
+            valid_max,
+        }
+    }
+
+    /// Whether a (widened) value is missing
+    pub fn is_missing(&self, value: f64) -> bool {
+        let equal = |m: &f64| *m == value || (m.is_nan() && value.is_nan());
+        self.fill.iter().any(equal)
+            || self.missing.iter().any(equal)
+            || self.valid_min.is_some_and(|min| value < min)
+            || self.valid_max.is_some_and(|max| value > max)
+    }
+
+    /// Replace missing values with `None`
+    pub fn apply<T: NetCDFData>(&self, values: Vec<T>) -> Vec<Option<T>> {
+        values
+            .into_iter()
+            .map(|v| {
+                if self.is_missing(v.to_f64()) {
+                    None
+                } else {
+                    Some(v)
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::components::{AttributeHM, NetCDFTypeInstance};
+
+    fn variable(attrs: Vec<(&str, NetCDFTypeInstance)>) -> NetCDFVariable {
+        let attrs: AttributeHM = attrs
+            .into_iter()
+            .map(|(n, v)| (n.to_string(), NetCDFAttribute::from_value(n.to_string(), v)))
+            .collect();
+        NetCDFVariable::new(
+            "p".to_string(),
+            vec![0],
+            Some(attrs),
+            NetCDFType::NC_SHORT,
+            0,
+            0,
+        )
+    }
+
+    #[test]
+    fn test_packing() {
+        let var = variable(vec![("units", NetCDFTypeInstance::STRING("K".to_string()))]);
+        assert_eq!(Packing::from_variable(&var).unwrap(), None);
+
+        let var = variable(vec![
+            ("scale_factor", NetCDFTypeInstance::FLOATS(vec![0.5])),
+            ("add_offset", NetCDFTypeInstance::FLOATS(vec![100.0])),
+        ]);
+        let packing = Packing::from_variable(&var).unwrap().unwrap();
+        assert_eq!(packing.unpacked_type, NetCDFType::NC_FLOAT);
+        assert_eq!(packing.unpack::<f32>(&[-2.0, 4.0]), vec![99.0, 102.0]);
+
+        let var = variable(vec![("add_offset", NetCDFTypeInstance::DOUBLES(vec![1.5]))]);
+        let packing = Packing::from_variable(&var).unwrap().unwrap();
+        assert_eq!(packing.scale_factor, 1.0);
+        assert_eq!(packing.unpacked_type, NetCDFType::NC_DOUBLE);
+
+        let var = variable(vec![
+            ("scale_factor", NetCDFTypeInstance::FLOATS(vec![0.5])),
+            ("add_offset", NetCDFTypeInstance::DOUBLES(vec![100.0])),
+        ]);
+        assert!(matches!(
+            Packing::from_variable(&var),
+            Err(HSE::TypeMismatch(_))
+        ));
+        let var = variable(vec![("scale_factor", NetCDFTypeInstance::INTS(vec![2]))]);
+        assert!(matches!(
+            Packing::from_variable(&var),
+            Err(HSE::TypeMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_mask() {
+        let mask = Mask::from_variable(&variable(vec![]));
+        assert_eq!(mask.fill, Some(-32767.0));
+        assert_eq!(
+            mask.apply(vec![1i16, -32767, 3]),
+            vec![Some(1), None, Some(3)]
+        );
+
+        let var = variable(vec![
+            ("_FillValue", NetCDFTypeInstance::SHORTS(vec![-999])),
+            ("missing_value", NetCDFTypeInstance::SHORTS(vec![-1, -2])),
+            ("valid_min", NetCDFTypeInstance::SHORTS(vec![-500])),
+            ("valid_range", NetCDFTypeInstance::SHORTS(vec![-10, 100])),
+        ]);
+        let mask = Mask::from_variable(&var);
+        let values = vec![-999i16, -32767, -2, -1, 0, 100, 101, -11, -10];
+        let expected = vec![
+            None,
+            None,
+            None,
+            None,
+            Some(0),
+            Some(100),
+            None,
+            None,
+            Some(-10),
+        ];
+        assert_eq!(mask.apply(values), expected);
+
+        let var = variable(vec![
+            ("_FillValue", NetCDFTypeInstance::FLOATS(vec![f32::NAN])),
+            ("valid_max", NetCDFTypeInstance::FLOATS(vec![1e30])),
+        ]);
+        let mask = Mask::from_variable(&var);
+        assert!(mask.is_missing(f64::NAN));
+        assert!(mask.is_missing(9.969209968386869e36));
+        assert!(!mask.is_missing(1.5));
+    }
+}
@@ -3,7 +3,9 @@
 
 // Constants
 pub const STREAMING:    u32 = 0xFF_FF_FF_FF;
+pub const STREAMING64:  u64 = 0xFF_FF_FF_FF_FF_FF_FF_FF;
 pub const ZERO:         u32 = 0x00_00_00_00;
+pub const HDF5_SIGNATURE: &[u8] = b"\x89HDF\r\n\x1a\n";
 
 pub const NC_DIMENSION: u32 = 0x00_00_00_0A;
 pub const NC_VARIABLE:  u32 = 0x00_00_00_0B;
@@ -15,20 +17,30 @@ pub const NC_SHORT:     u32 = 0x00_00_00_03;
 pub const NC_INT:       u32 = 0x00_00_00_04;
 pub const NC_FLOAT:     u32 = 0x00_00_00_05;
 pub const NC_DOUBLE:    u32 = 0x00_00_00_06;
+pub const NC_UBYTE:     u32 = 0x00_00_00_07;
+pub const NC_USHORT:    u32 = 0x00_00_00_08;
+pub const NC_UINT:      u32 = 0x00_00_00_09;
+pub const NC_INT64:     u32 = 0x00_00_00_0A;
+pub const NC_UINT64:    u32 = 0x00_00_00_0B;
 
 pub const FILL_BYTE:    u8 = 0x81;
 pub const FILL_CHAR:    u8 = 0x00;
 pub const FILL_SHORT:  u16 = 0x80_01;
 pub const FILL_INT:    u32 = 0x80_00_00_01;
 pub const FILL_FLOAT:  u32 = 0x7C_F0_00_00;
-pub const FILL_DOUBLE: u64 = 0x47_9E_00_00_00_00;
+pub const FILL_DOUBLE: u64 = 0x47_9E_00_00_00_00_00_00;
+pub const FILL_UBYTE:   u8 = 0xFF;
+pub const FILL_USHORT: u16 = 0xFF_FF;
+pub const FILL_UINT:   u32 = 0xFF_FF_FF_FF;
+pub const FILL_INT64:  u64 = 0x80_00_00_00_00_00_00_02;
+pub const FILL_UINT64: u64 = 0xFF_FF_FF_FF_FF_FF_FF_FE;
 
 // Types
-pub type NON_NEG = u32;
+pub type NON_NEG = u64;
 
 // Naming standards
 pub const LONGITUDE_CANDIDATES: &[&str] = &["lon", "longitude"];
 pub const LATITUDE_CANDIDATES:  &[&str] = &["lat", "latitude"];
 pub const NCELLS_CANDIDATES:    &[&str] = &["ncells"];
-pub const ALTITUDE_CANDIDATES:  &[&str] = &["lev", "level", "alt", "height"];
+pub const ALTITUDE_CANDIDATES:  &[&str] = &["lev", "level", "plev", "alt", "height"];
 pub const TIME_CANDIDATES:      &[&str] = &["time"];
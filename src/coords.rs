@@ -0,0 +1,419 @@
+//! Selection by coordinate value
+//!
+//! Coordinate variables are the 1-D variables named like their dimension,
+//! e.g. `lat(lat)`. An [`Axis`] holds their values and maps a value to the
+//! index of the nearest one, or a range of values to the indices inside it.
+//! Axes may be ascending or descending. Longitudes wrap around, so values
+//! in -180..180 select from axes in 0..360 and the other way round.
+use crate::constants_and_types::{
+    ALTITUDE_CANDIDATES, LATITUDE_CANDIDATES, LONGITUDE_CANDIDATES, TIME_CANDIDATES,
+};
+use crate::data::{self, NetCDFData};
+use crate::error::HeadScratcherError as HSE;
+use crate::selection::Selection;
+use crate::source::ByteRangeSource;
+use crate::NetCDF;
+
+/// Full turn of a longitude axis in degrees
+const TURN: f64 = 360.0;
+
+/// Selection along one axis by coordinate value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Coord {
+    /// Index of the value nearest to the given one
+    Nearest(f64),
+    /// Indices of the values between the given ones, inclusive
+    ///
+    /// On longitude axes a range from a higher to a lower value crosses the
+    /// seam, e.g. `Range(350.0, 10.0)`; on other axes the bounds are swapped.
+    Range(f64, f64),
+}
+
+/// Values of a coordinate variable
+#[derive(Debug, Clone, PartialEq)]
+pub struct Axis {
+    pub name: String,
+    pub values: Vec<f64>,
+    /// Values are longitudes and wrap around
+    pub wrap: bool,
+}
+
+impl Axis {
+    pub fn new(name: String, values: Vec<f64>, wrap: bool) -> Self {
+        Axis { name, values, wrap }
+    }
+
+    /// Index of the value nearest to `value`, the first one on ties
+    pub fn nearest(&self, value: f64) -> Option<usize> {
+        self.values
+            .iter()
+            .map(|v| self.distance(*v, value))
+            .enumerate()
+            .filter(|(_, d)| !d.is_nan())
+            .fold(None, |best: Option<(usize, f64)>, (k, d)| match best {
+                Some((_, b)) if b <= d => best,
+                _ => Some((k, d)),
+            })
+            .map(|(k, _)| k)
+    }
+
+    /// Contiguous pieces `(start, count)` of the indices with values in
+    /// `low..=high`
+    ///
+    /// Indices run in axis order. A longitude range across the end of the
+    /// axis is split in two pieces, ordered from `low` to `high`. With `low`
+    /// above `high` a longitude range runs eastward across the seam, any
+    /// other range is taken as `high..=low`.
+    pub fn range(&self, low: f64, high: f64) -> Vec<(usize, usize)> {
+        let (low, high) = match (self.wrap, low > high) {
+            (true, true) => (low, low + (high - low).rem_euclid(TURN)),
+            (false, true) => (high, low),
+            _ => (low, high),
+        };
+        let from_low = |k: usize| (self.values[k] - low).rem_euclid(TURN);
+        // On a wrapping axis a piece also ends where it crosses the seam at `low`
+        let seam = |k: usize| self.wrap && (from_low(k) - from_low(k - 1)).abs() > TURN / 2.0;
+        let mut pieces: Vec<(usize, usize)> = Vec::new();
+        for (k, v) in self.values.iter().enumerate() {
+            if !self.contains(low, high, *v) {
+                continue;
+            }
+            match pieces.last_mut() {
+                Some((start, count)) if *start + *count == k && !seam(k) => *count += 1,
+                _ => pieces.push((k, 1)),
+            }
+        }
+        if self.wrap {
+            pieces.sort_by(|(a, _), (b, _)| from_low(*a).total_cmp(&from_low(*b)));
+        }
+        pieces
+    }
+
+    /// Pieces `(start, count)` of the indices selected by `coord`
+    pub fn select(&self, coord: Coord) -> Result<Vec<(usize, usize)>, HSE<String>> {
+        let pieces = match coord {
+            Coord::Nearest(value) => self.nearest(value).map(|k| (k, 1)).into_iter().collect(),
+            Coord::Range(low, high) => self.range(low, high),
+        };
+        if pieces.is_empty() {
+            let msg = format!("No values of {} selected by {:?}", self.name, coord);
+            return Err(HSE::InvalidSelection(msg));
+        }
+        Ok(pieces)
+    }
+
+    fn distance(&self, a: f64, b: f64) -> f64 {
+        match self.wrap {
+            true => {
+                let d = (a - b).rem_euclid(TURN);
+                d.min(TURN - d)
+            }
+            false => (a - b).abs(),
+        }
+    }
+
+    fn contains(&self, low: f64, high: f64, value: f64) -> bool {
+        match self.wrap {
+            true if high - low >= TURN => true,
+            true => (value - low).rem_euclid(TURN) <= high - low,
+            false => low <= value && value <= high,
+        }
+    }
+}
+
+/// Names a dimension may go by, through the candidate lists
+fn aliases(name: &str) -> Vec<&str> {
+    let lists = [
+        LONGITUDE_CANDIDATES,
+        LATITUDE_CANDIDATES,
+        ALTITUDE_CANDIDATES,
+        TIME_CANDIDATES,
+    ];
+    match lists.iter().find(|l| l.contains(&name)) {
+        Some(list) => list.to_vec(),
+        None => vec![name],
+    }
+}
+
+impl<F: ByteRangeSource> NetCDF<F> {
+    /// Values of the coordinate variable of a dimension
+    ///
+    /// `dim` may be any name of the candidate lists, e.g. `latitude` finds the
+    /// coordinate variable `lat`.
+    pub fn axis(&mut self, dim: &str) -> Result<Axis, HSE<String>> {
+        let aliases = aliases(dim);
+        let name = match aliases.iter().find(|a| self.header.dimension_id(a).is_ok()) {
+            Some(name) => name.to_string(),
+            None => return Err(HSE::CouldNotFindDimension(dim.to_string())),
+        };
+        let var = self.variable(&name)?;
+        if var.dims.len() != 1 {
+            let msg = format!("{} is not a coordinate variable", name);
+            return Err(HSE::InvalidDefinition(msg));
+        }
+        let nc_type = var.nc_type;
+        let selection = Selection::all(&[self.header.dimension_length(&name)?]);
+        let mut buffer = self.selection_buffer(&name, &selection, nc_type.extsize())?;
+        self.update_buffer_selection(name.clone(), &selection, &mut buffer)?;
+        let values = match data::decode_f64(&nc_type, &buffer) {
+            Some(values) => values,
+            None => {
+                let msg = format!("{} of type {:?} has no numeric values", name, nc_type);
+                return Err(HSE::TypeMismatch(msg));
+            }
+        };
+        let wrap = LONGITUDE_CANDIDATES.contains(&name.as_str());
+        Ok(Axis::new(name, values, wrap))
+    }
+
+    /// Selections of a variable by coordinate values
+    ///
+    /// Dimensions without a coordinate in `coords` are selected completely.
+    /// There is one selection per combination of pieces (see
+    /// [`Axis::range`]), usually a single one.
+    pub fn coord_selections(
+        &mut self,
+        variable: &str,
+        coords: &[(&str, Coord)],
+    ) -> Result<Vec<Selection>, HSE<String>> {
+        let pieces = self.coord_pieces(variable, coords)?;
+        Ok(combinations(&pieces)
+            .into_iter()
+            .map(|(start, count)| Selection::new(start, count))
+            .collect())
+    }
+
+    /// Read a variable selected by coordinate values
+    ///
+    /// Values are in the order of the variable's dimensions, with a longitude
+    /// range across the end of the axis joined up from `low` to `high`.
+    pub fn read_coords<T: NetCDFData>(
+        &mut self,
+        variable: &str,
+        coords: &[(&str, Coord)],
+    ) -> Result<Vec<T>, HSE<String>> {
+        let pieces = self.coord_pieces(variable, coords)?;
+        let mut parts = Vec::new();
+        for (start, count) in combinations(&pieces) {
+            let selection = Selection::new(start, count);
+            parts.push(self.read_selection::<T>(variable.to_string(), &selection)?);
+        }
+        Ok(join(&pieces, &parts))
+    }
+
+    /// Pieces `(start, count)` per dimension of a variable
+    fn coord_pieces(
+        &mut self,
+        variable: &str,
+        coords: &[(&str, Coord)],
+    ) -> Result<Vec<Vec<(usize, usize)>>, HSE<String>> {
+        let shape = self.header.variable_shape(variable)?;
+        let mut pieces: Vec<Vec<(usize, usize)>> =
+            shape.iter().map(|(_, length)| vec![(0, *length)]).collect();
+        for (dim, coord) in coords {
+            let aliases = aliases(dim);
+            let d = match shape
+                .iter()
+                .position(|(n, _)| aliases.contains(&n.as_str()))
+            {
+                Some(d) => d,
+                None => {
+                    let msg = format!("{} of {}", dim, variable);
+                    return Err(HSE::CouldNotFindDimension(msg));
+                }
+            };
+            pieces[d] = self.axis(&shape[d].0)?.select(*coord)?;
+        }
+        Ok(pieces)
+    }
+}
+
+/// `(start, count)` of every combination of pieces, the last dimension fastest
+fn combinations(pieces: &[Vec<(usize, usize)>]) -> Vec<(Vec<usize>, Vec<usize>)> {
+    pieces
+        .iter()
+        .fold(vec![(Vec::new(), Vec::new())], |acc, dim| {
+            acc.iter()
+                .flat_map(|(start, count)| {
+                    dim.iter().map(move |(s, c)| {
+                        let (mut start, mut count) = (start.clone(), count.clone());
+                        start.push(*s);
+                        count.push(*c);
+                        (start, count)
+                    })
+                })
+                .collect()
+        })
+}
+
+/// Join the values read for every combination of pieces into one array
+fn join<T: Copy>(pieces: &[Vec<(usize, usize)>], parts: &[Vec<T>]) -> Vec<T> {
+    if parts.len() == 1 {
+        return parts[0].clone();
+    }
+    // piece and index within it of every index along every dimension
+    let locate: Vec<Vec<(usize, usize)>> = pieces
+        .iter()
+        .map(|dim| {
+            dim.iter()
+                .enumerate()
+                .flat_map(|(p, (_, count))| (0..*count).map(move |k| (p, k)))
+                .collect()
+        })
+        .collect();
+    let total: usize = locate.iter().map(|l| l.len()).product();
+    let mut values = Vec::with_capacity(total);
+    let mut index = vec![0; pieces.len()];
+    for _ in 0..total {
+        let (mut part, mut offset) = (0, 0);
+        for (d, i) in index.iter().enumerate() {
+            let (p, k) = locate[d][*i];
+            part = part * pieces[d].len() + p;
+            offset = offset * pieces[d][p].1 + k;
+        }
+        values.push(parts[part][offset]);
+        // next index, the last dimension fastest
+        for d in (0..index.len()).rev() {
+            index[d] += 1;
+            if index[d] < locate[d].len() {
+                break;
+            }
+            index[d] = 0;
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::components::{NetCDFType, NetCDFVersion};
+
+    #[test]
+    fn test_axis() {
+        let lat = Axis::new(
+            "lat".to_string(),
+            vec![60.0, 30.0, 0.0, -30.0, -60.0],
+            false,
+        );
+        assert_eq!(lat.nearest(40.0), Some(1));
+        assert_eq!(lat.nearest(-100.0), Some(4));
+        assert_eq!(lat.nearest(f64::NAN), None);
+        assert_eq!(lat.range(-30.0, 30.0), vec![(1, 3)]);
+        assert_eq!(lat.range(70.0, 80.0), vec![]);
+        assert_eq!(lat.range(30.0, -30.0), vec![(1, 3)]);
+
+        let lon: Vec<f64> = (0..8).map(|k| k as f64 * 45.0).collect();
+        let lon = Axis::new("lon".to_string(), lon, true);
+        assert_eq!(lon.nearest(-50.0), Some(7));
+        assert_eq!(lon.nearest(350.0), Some(0));
+        assert_eq!(lon.nearest(-170.0), Some(4));
+        assert_eq!(lon.range(-90.0, 45.0), vec![(6, 2), (0, 2)]);
+        assert_eq!(lon.range(90.0, 180.0), vec![(2, 3)]);
+        assert_eq!(lon.range(-180.0, 179.0), vec![(4, 4), (0, 4)]);
+        assert_eq!(lon.range(-180.0, 180.0), vec![(4, 4), (0, 4)]);
+        // high to low crosses the seam
+        assert_eq!(lon.range(350.0, 10.0), vec![(0, 1)]);
+        assert_eq!(lon.range(300.0, 50.0), vec![(7, 1), (0, 2)]);
+        assert_eq!(lon.range(100.0, -100.0), vec![(3, 3)]);
+
+        let lon = Axis::new("lon".to_string(), vec![-135.0, -45.0, 45.0, 135.0], true);
+        assert_eq!(lon.range(300.0, 360.0), vec![(1, 1)]);
+        assert_eq!(lon.range(90.0, 270.0), vec![(3, 1), (0, 1)]);
+        let e = lon.select(Coord::Range(0.0, 10.0)).unwrap_err();
+        assert!(matches!(e, HSE::InvalidSelection(_)));
+    }
+
+    #[test]
+    fn test_join() {
+        // 2x4 array, the columns 3, 0 and 1 out of two parts
+        let pieces = vec![vec![(0, 2)], vec![(3, 1), (0, 2)]];
+        let parts = vec![vec![3, 7], vec![0, 1, 4, 5]];
+        assert_eq!(join(&pieces, &parts), vec![3, 0, 1, 7, 4, 5]);
+        let starts: Vec<Vec<usize>> = combinations(&pieces).into_iter().map(|c| c.0).collect();
+        assert_eq!(starts, vec![vec![0, 3], vec![0, 0]]);
+    }
+
+    #[test]
+    fn test_read_coords() {
+        let filename = "assets/sresa1b_ncar_ccsm3-example.nc".to_string();
+        let mut netcdf = NetCDF::new(filename).unwrap();
+        let lat = netcdf.axis("latitude").unwrap();
+        let lon = netcdf.axis("lon").unwrap();
+        assert_eq!((lat.values.len(), lat.wrap), (128, false));
+        assert_eq!((lon.values.len(), lon.wrap), (256, true));
+        let plev = netcdf.axis("plev").unwrap();
+        assert_eq!(plev.values.len(), 17);
+
+        // a point
+        let (y, x) = (lat.nearest(52.5).unwrap(), lon.nearest(13.4).unwrap());
+        let coords = [("lat", Coord::Nearest(52.5)), ("lon", Coord::Nearest(13.4))];
+        let tas: Vec<f32> = netcdf.read_coords("tas", &coords).unwrap();
+        let expected: Vec<f32> = netcdf
+            .read_slab("tas".to_string(), &[0, y, x], &[1, 1, 1])
+            .unwrap();
+        assert_eq!(tas, expected);
+
+        // a box across the prime meridian, in -180..180
+        let coords = [
+            ("lat", Coord::Range(40.0, 50.0)),
+            ("lon", Coord::Range(-10.0, 10.0)),
+        ];
+        let selections = netcdf.coord_selections("tas", &coords).unwrap();
+        assert_eq!(selections.len(), 2);
+        let tas: Vec<f32> = netcdf.read_coords("tas", &coords).unwrap();
+        let ys = lat.range(40.0, 50.0);
+        let xs = lon.range(-10.0, 10.0);
+        let (ny, nx) = (ys[0].1, xs[0].1 + xs[1].1);
+        assert_eq!(tas.len(), ny * nx);
+        let full: Vec<f32> = netcdf
+            .read_slab("tas".to_string(), &[0, 0, 0], &[1, 128, 256])
+            .unwrap();
+        let columns: Vec<usize> = xs.iter().flat_map(|(s, c)| *s..s + c).collect();
+        for (k, value) in tas.iter().enumerate() {
+            let (y, x) = (ys[0].0 + k / nx, columns[k % nx]);
+            assert!(lon.distance(lon.values[x], 0.0) <= 10.0);
+            assert_eq!(*value, full[y * 256 + x]);
+        }
+
+        // the same box with the longitudes across the seam
+        let coords = [
+            ("lat", Coord::Range(50.0, 40.0)),
+            ("lon", Coord::Range(350.0, 10.0)),
+        ];
+        let across: Vec<f32> = netcdf.read_coords("tas", &coords).unwrap();
+        assert_eq!(across, tas);
+
+        let e = netcdf
+            .read_coords::<f32>("tas", &[("plev", Coord::Nearest(1000.0))])
+            .unwrap_err();
+        assert!(matches!(e, HSE::CouldNotFindDimension(_)));
+        let e = netcdf
+            .read_coords::<f32>("tas", &[("lat", Coord::Range(95.0, 99.0))])
+            .unwrap_err();
+        assert!(matches!(e, HSE::InvalidSelection(_)));
+
+        // the records of a streaming file are not counted
+        let mut builder = crate::builder::NetCDFBuilder::new(NetCDFVersion::Classic);
+        builder.add_unlimited_dimension("time").unwrap();
+        builder.add_dimension("lat", 3).unwrap();
+        builder
+            .add_variable("lat", &["lat"], NetCDFType::NC_FLOAT)
+            .unwrap()
+            .add_variable("time", &["time"], NetCDFType::NC_DOUBLE)
+            .unwrap()
+            .add_variable("v", &["time", "lat"], NetCDFType::NC_FLOAT)
+            .unwrap();
+        let mut bytes = builder.header().unwrap().to_bytes().unwrap();
+        bytes.resize(bytes.len() + 64, 0);
+        bytes[4..8].copy_from_slice(&crate::constants_and_types::STREAMING.to_be_bytes());
+        let mut netcdf = NetCDF::new_from_file(std::io::Cursor::new(bytes)).unwrap();
+        let e = netcdf.axis("time").unwrap_err();
+        assert!(matches!(e, HSE::UnknownNumberOfRecords(_)));
+        let e = netcdf
+            .read_coords::<f32>("v", &[("lat", Coord::Nearest(0.0))])
+            .unwrap_err();
+        assert!(matches!(e, HSE::UnknownNumberOfRecords(_)));
+        assert_eq!(netcdf.axis("lat").unwrap().values, vec![0.0; 3]);
+    }
+}
@@ -0,0 +1,218 @@
+//! Decoding of variable data
+//!
+//! NetCDF stores all values in big endian byte order. Every Rust type that
+//! can be read from a variable implements [`NetCDFData`], which ties the
+//! type to its external netCDF type and converts raw bytes to native values.
+//! The `border` feature switches bulk decoding to the `byteorder` crate.
+use crate::error::HeadScratcherError as HSE;
+use crate::parser::components::{NetCDFType, NetCDFTypeInstance};
+
+/// Native Rust representation of an external netCDF type
+pub trait NetCDFData: Sized + Copy {
+    /// External type of the values
+    const NC_TYPE: NetCDFType;
+
+    /// Decode a big endian buffer into native values
+    fn decode(buffer: &[u8]) -> Vec<Self>;
+
+    /// Encode native values into a big endian buffer
+    fn encode(values: &[Self]) -> Vec<u8>;
+
+    /// Widen a value to `f64`
+    fn to_f64(self) -> f64;
+
+    /// Convert an `f64` to the native type (`as` semantics)
+    fn from_f64(value: f64) -> Self;
+
+    /// View a big endian buffer as native values without copying
+    ///
+    /// `None` unless the buffer is aligned for `Self` and big endian bytes are
+    /// native values, i.e. for single bytes or on big endian hosts.
+    fn view(_buffer: &[u8]) -> Option<&[Self]> {
+        None
+    }
+}
+
+/// Portable decoding through `from_be_bytes`, vectorized by the compiler
+macro_rules! decode_chunks {
+    ($t:ty, $buffer:expr) => {{
+        const SIZE: usize = std::mem::size_of::<$t>();
+        $buffer
+            .chunks_exact(SIZE)
+            .map(|c| {
+                let mut b = [0u8; SIZE];
+                b.copy_from_slice(c);
+                <$t>::from_be_bytes(b)
+            })
+            .collect()
+    }};
+}
+
+macro_rules! impl_netcdf_data {
+    ($t:ty, $nc:ident) => {
+        impl_netcdf_data!(@impl $t, $nc, |buffer: &[u8]| decode_chunks!($t, buffer));
+    };
+    ($t:ty, $nc:ident, $read_into:ident) => {
+        #[cfg(feature = "border")]
+        impl_netcdf_data!(@impl $t, $nc, |buffer: &[u8]| {
+            use byteorder::ByteOrder;
+            let size = std::mem::size_of::<$t>();
+            let mut values = vec![<$t>::default(); buffer.len() / size];
+            byteorder::BigEndian::$read_into(&buffer[..values.len() * size], &mut values);
+            values
+        });
+        #[cfg(not(feature = "border"))]
+        impl_netcdf_data!($t, $nc);
+    };
+    (@impl $t:ty, $nc:ident, $decode:expr) => {
+        impl NetCDFData for $t {
+            const NC_TYPE: NetCDFType = NetCDFType::$nc;
+
+            fn decode(buffer: &[u8]) -> Vec<Self> {
+                ($decode)(buffer)
+            }
+
+            fn encode(values: &[Self]) -> Vec<u8> {
+                values.iter().flat_map(|v| v.to_be_bytes()).collect()
+            }
+
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+
+            fn from_f64(value: f64) -> Self {
+                value as $t
+            }
+
+            fn view(buffer: &[u8]) -> Option<&[Self]> {
+                if cfg!(target_endian = "little") && std::mem::size_of::<$t>() > 1 {
+                    return None;
+                }
+                // every bit pattern is a valid number
+                let (head, values, tail) = unsafe { buffer.align_to::<$t>() };
+                match (head.is_empty(), tail.is_empty()) {
+                    (true, true) => Some(values),
+                    (_, _) => None,
+                }
+            }
+        }
+    };
+}
+
+impl_netcdf_data!(i8, NC_BYTE);
+impl_netcdf_data!(i16, NC_SHORT, read_i16_into);
+impl_netcdf_data!(i32, NC_INT, read_i32_into);
+impl_netcdf_data!(f32, NC_FLOAT, read_f32_into);
+impl_netcdf_data!(f64, NC_DOUBLE, read_f64_into);
+impl_netcdf_data!(u8, NC_UBYTE);
+impl_netcdf_data!(u16, NC_USHORT, read_u16_into);
+impl_netcdf_data!(u32, NC_UINT, read_u32_into);
+impl_netcdf_data!(i64, NC_INT64, read_i64_into);
+impl_netcdf_data!(u64, NC_UINT64, read_u64_into);
+
+/// Error for buffers that do not hold a whole number of values
+pub(crate) fn check_length(nc_type: &NetCDFType, buffer: &[u8]) -> Result<(), HSE<String>> {
+    match buffer.len() % nc_type.extsize() {
+        0 => Ok(()),
+        _ => Err(HSE::InvalidBufferLength(buffer.len(), nc_type.extsize())),
+    }
+}
+
+/// Decode a big endian buffer of any external type into a native vector
+pub fn decode_values(
+    nc_type: &NetCDFType,
+    buffer: &[u8],
+) -> Result<NetCDFTypeInstance, HSE<String>> {
+    check_length(nc_type, buffer)?;
+    Ok(match nc_type {
+        NetCDFType::NC_BYTE => NetCDFTypeInstance::BYTES(i8::decode(buffer)),
+        NetCDFType::NC_CHAR => match std::str::from_utf8(buffer) {
+            Ok(s) => NetCDFTypeInstance::STRING(s.to_string()),
+            Err(_) => return Err(HSE::UTF8error),
+        },
+        NetCDFType::NC_SHORT => NetCDFTypeInstance::SHORTS(i16::decode(buffer)),
+        NetCDFType::NC_INT => NetCDFTypeInstance::INTS(i32::decode(buffer)),
+        NetCDFType::NC_FLOAT => NetCDFTypeInstance::FLOATS(f32::decode(buffer)),
+        NetCDFType::NC_DOUBLE => NetCDFTypeInstance::DOUBLES(f64::decode(buffer)),
+        NetCDFType::NC_UBYTE => NetCDFTypeInstance::UBYTES(buffer.to_vec()),
+        NetCDFType::NC_USHORT => NetCDFTypeInstance::USHORTS(u16::decode(buffer)),
+        NetCDFType::NC_UINT => NetCDFTypeInstance::UINTS(u32::decode(buffer)),
+        NetCDFType::NC_INT64 => NetCDFTypeInstance::INT64S(i64::decode(buffer)),
+        NetCDFType::NC_UINT64 => NetCDFTypeInstance::UINT64S(u64::decode(buffer)),
+    })
+}
+
+/// Decode a big endian buffer of any numeric external type into `f64` values
+pub fn decode_f64(nc_type: &NetCDFType, buffer: &[u8]) -> Option<Vec<f64>> {
+    fn widen<T: NetCDFData>(buffer: &[u8]) -> Vec<f64> {
+        T::decode(buffer).into_iter().map(T::to_f64).collect()
+    }
+    match nc_type {
+        NetCDFType::NC_BYTE => Some(widen::<i8>(buffer)),
+        NetCDFType::NC_CHAR => None,
+        NetCDFType::NC_SHORT => Some(widen::<i16>(buffer)),
+        NetCDFType::NC_INT => Some(widen::<i32>(buffer)),
+        NetCDFType::NC_FLOAT => Some(widen::<f32>(buffer)),
+        NetCDFType::NC_DOUBLE => Some(widen::<f64>(buffer)),
+        NetCDFType::NC_UBYTE => Some(widen::<u8>(buffer)),
+        NetCDFType::NC_USHORT => Some(widen::<u16>(buffer)),
+        NetCDFType::NC_UINT => Some(widen::<u32>(buffer)),
+        NetCDFType::NC_INT64 => Some(widen::<i64>(buffer)),
+        NetCDFType::NC_UINT64 => Some(widen::<u64>(buffer)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(f32::decode(&[67, 87, 228, 188]), vec![215.8935]);
+        assert_eq!(i16::decode(&[0xFF, 0xFE, 0, 1]), vec![-2, 1]);
+        assert_eq!(i8::decode(&[0xFF]), vec![-1]);
+        assert_eq!(u16::decode(&[234, 96]), vec![60000]);
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(f32::encode(&[215.8935]), vec![67, 87, 228, 188]);
+        assert_eq!(i16::encode(&[-2, 1]), vec![0xFF, 0xFE, 0, 1]);
+        let values = vec![1.5f64, -2.25, 1e20];
+        assert_eq!(f64::decode(&f64::encode(&values)), values);
+    }
+
+    #[test]
+    fn test_view() {
+        assert_eq!(i8::view(&[0xFF, 1]), Some(&[-1i8, 1][..]));
+        assert_eq!(u8::view(&[200]), Some(&[200u8][..]));
+        let buffer = f32::encode(&[215.8935, 1.0]);
+        match f32::view(&buffer) {
+            Some(values) => assert_eq!(values, [215.8935, 1.0]),
+            None => assert!(
+                cfg!(target_endian = "little") || !(buffer.as_ptr() as usize).is_multiple_of(4)
+            ),
+        }
+    }
+
+    #[test]
+    fn test_decode_values() {
+        let shorts = decode_values(&NetCDFType::NC_SHORT, &[0xFF, 0xFE, 0, 1]).unwrap();
+        assert_eq!(shorts, NetCDFTypeInstance::SHORTS(vec![-2, 1]));
+        let chars = decode_values(&NetCDFType::NC_CHAR, b"ab").unwrap();
+        assert_eq!(chars, NetCDFTypeInstance::STRING("ab".to_string()));
+        let uint64 = decode_values(&NetCDFType::NC_UINT64, &[0xFF; 8]).unwrap();
+        assert_eq!(uint64, NetCDFTypeInstance::UINT64S(vec![u64::MAX]));
+        let e = decode_values(&NetCDFType::NC_INT, &[0, 0, 0, 1, 2]).unwrap_err();
+        assert_eq!(e, HSE::InvalidBufferLength(5, 4));
+    }
+
+    #[test]
+    fn test_decode_f64() {
+        let shorts = decode_f64(&NetCDFType::NC_SHORT, &[0xFF, 0xFE, 0, 1]);
+        assert_eq!(shorts, Some(vec![-2.0, 1.0]));
+        let ubytes = decode_f64(&NetCDFType::NC_UBYTE, &[200, 1]);
+        assert_eq!(ubytes, Some(vec![200.0, 1.0]));
+        assert_eq!(decode_f64(&NetCDFType::NC_CHAR, b"ab"), None);
+    }
+}
@@ -35,6 +35,24 @@ pub enum HeadScratcherError<I> {
     VariableNotFound(String),
     /// Search for Dimensions unsuccessful
     CouldNotFindDimension(String),
+    /// Requested data type does not match the variable type
+    TypeMismatch(String),
+    /// Start, count or stride do not fit the variable shape
+    InvalidSelection(String),
+    /// Dimension, attribute or variable cannot be defined
+    InvalidDefinition(String),
+    /// Size or offset does not fit the format version (NC_EVARSIZE)
+    ExceedsFormat(String),
+    /// Buffer length (first) is not a multiple of the type size (second)
+    InvalidBufferLength(usize, usize),
+    /// netCDF-4 (HDF5) file given to the netCDF-3 parser or writer
+    NetCDF4NotSupported,
+    /// HDF5 feature not covered by the netCDF-4 reader
+    UnsupportedHDF5(String),
+    /// No group at the given path
+    GroupNotFound(String),
+    /// Number of records of a streaming file is not in its header
+    UnknownNumberOfRecords(String),
 }
 
 impl<I> nom::error::ParseError<I> for HeadScratcherError<I> {
@@ -64,11 +82,20 @@ impl<I> HeadScratcherError<I> {
             HeadScratcherError::UTF8error => Some(HeadScratcherError::UTF8error),
             HeadScratcherError::UnknownNetCDFType(tpe) => Some(HeadScratcherError::UnknownNetCDFType(*tpe)),
             HeadScratcherError::NomError(_, _) => None,
-            HeadScratcherError::IOError(err) => Some(HeadScratcherError::IOError(err.clone())),
+            HeadScratcherError::IOError(err) => Some(HeadScratcherError::IOError(*err)),
             HeadScratcherError::NoVariablesInFile => Some(HeadScratcherError::NoVariablesInFile),
             HeadScratcherError::NoDimensionsInFile => Some(HeadScratcherError::NoDimensionsInFile),
             HeadScratcherError::VariableNotFound(var) => Some(HeadScratcherError::VariableNotFound(var.clone())),
             HeadScratcherError::CouldNotFindDimension(dim) => Some(HeadScratcherError::CouldNotFindDimension(dim.clone())),
+            HeadScratcherError::TypeMismatch(msg) => Some(HeadScratcherError::TypeMismatch(msg.clone())),
+            HeadScratcherError::InvalidSelection(msg) => Some(HeadScratcherError::InvalidSelection(msg.clone())),
+            HeadScratcherError::InvalidDefinition(msg) => Some(HeadScratcherError::InvalidDefinition(msg.clone())),
+            HeadScratcherError::ExceedsFormat(msg) => Some(HeadScratcherError::ExceedsFormat(msg.clone())),
+            HeadScratcherError::InvalidBufferLength(len, size) => Some(HeadScratcherError::InvalidBufferLength(*len, *size)),
+            HeadScratcherError::NetCDF4NotSupported => Some(HeadScratcherError::NetCDF4NotSupported),
+            HeadScratcherError::UnsupportedHDF5(msg) => Some(HeadScratcherError::UnsupportedHDF5(msg.clone())),
+            HeadScratcherError::GroupNotFound(path) => Some(HeadScratcherError::GroupNotFound(path.clone())),
+            HeadScratcherError::UnknownNumberOfRecords(dim) => Some(HeadScratcherError::UnknownNumberOfRecords(dim.clone())),
         }
     }
 }
@@ -0,0 +1,224 @@
+//! Group tree of a netCDF file
+//!
+//! # Groups
+//! netCDF-4 files nest groups, each with its own dimensions, attributes and
+//! variables. Classic files are a single root group. Members are looked up by
+//! path, e.g. `/forecast/model1/temp`; paths without a leading `/` are relative
+//! to the group they are looked up in.
+//!
+//! Dimension ids are unique within a file, and variables may use the
+//! dimensions of any ancestor of their group.
+use crate::error::HeadScratcherError as HSE;
+use crate::hdf5;
+use crate::parser::components::{
+    DimensionHM, NetCDFAttribute, NetCDFDimension, NetCDFVariable, NetCDFVersion, NumberOfRecords,
+};
+use crate::parser::{self, NetCDFHeader};
+use std::io::{Read, Seek};
+
+/// Group of a netCDF file with its subgroups
+#[derive(Debug, PartialEq, Clone)]
+pub struct NetCDFGroup {
+    /// Name of the group, empty for the root group
+    pub name: String,
+    /// Dimensions, attributes and variables declared in this group
+    pub header: NetCDFHeader,
+    pub groups: Vec<NetCDFGroup>,
+}
+
+impl NetCDFGroup {
+    pub fn new(name: String, header: NetCDFHeader, groups: Vec<NetCDFGroup>) -> Self {
+        NetCDFGroup {
+            name,
+            header,
+            groups,
+        }
+    }
+
+    /// Root group holding everything of a classic file
+    pub fn from_header(header: NetCDFHeader) -> Self {
+        NetCDFGroup::new(String::new(), header, Vec::new())
+    }
+
+    /// Read the group tree of a classic or netCDF-4 file
+    pub fn from_file<F: Read + Seek>(file: &mut F) -> Result<Self, HSE<String>> {
+        match parser::file_format(file)? {
+            NetCDFVersion::NetCDF4 => hdf5::tree(file),
+            _ => Ok(NetCDFGroup::from_header(NetCDFHeader::from_file(file)?)),
+        }
+    }
+
+    /// Paths of this group and all its descendants, starting with `/`
+    pub fn paths(&self) -> Vec<String> {
+        fn walk(group: &NetCDFGroup, path: &str, paths: &mut Vec<String>) {
+            for g in group.groups.iter() {
+                let path = format!("{}/{}", path, g.name);
+                paths.push(path.clone());
+                walk(g, &path, paths);
+            }
+        }
+        let mut paths = vec!["/".to_string()];
+        walk(self, "", &mut paths);
+        paths
+    }
+
+    /// Groups along a path of group names, starting with this group
+    fn lineage(&self, path: &str) -> Result<Vec<&NetCDFGroup>, HSE<String>> {
+        let mut lineage = vec![self];
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            let group = lineage.last().unwrap();
+            match group.groups.iter().find(|g| g.name == name) {
+                Some(g) => lineage.push(g),
+                None => return Err(HSE::GroupNotFound(path.to_string())),
+            }
+        }
+        Ok(lineage)
+    }
+
+    /// Group by path, `/` for this group
+    pub fn group(&self, path: &str) -> Result<&NetCDFGroup, HSE<String>> {
+        Ok(self.lineage(path)?.pop().unwrap())
+    }
+
+    /// Variable by path
+    pub fn variable(&self, path: &str) -> Result<&NetCDFVariable, HSE<String>> {
+        let (group, name) = split(path);
+        let group = self.group(group)?;
+        match group.header.vars.as_ref().and_then(|v| v.get(name)) {
+            Some(v) => Ok(v),
+            None => Err(HSE::VariableNotFound(path.to_string())),
+        }
+    }
+
+    /// Group attribute by path
+    pub fn attribute(&self, path: &str) -> Option<&NetCDFAttribute> {
+        let (group, name) = split(path);
+        let group = self.group(group).ok()?;
+        group.header.attrs.as_ref().and_then(|a| a.get(name))
+    }
+
+    /// Dimension by path, declared in the group or one of its ancestors
+    pub fn dimension(&self, path: &str) -> Result<&NetCDFDimension, HSE<String>> {
+        let (group, name) = split(path);
+        let lineage = self.lineage(group)?;
+        lineage
+            .iter()
+            .rev()
+            .filter_map(|g| g.header.dims.as_ref())
+            .find_map(|dims| dims.values().find(|d| d.name == name))
+            .ok_or_else(|| HSE::CouldNotFindDimension(path.to_string()))
+    }
+
+    /// Header of a group holding the dimensions of its ancestors as well
+    ///
+    /// Dimension and variable lookups of the header then work for every
+    /// variable of the group. The number of records is the one of the nearest
+    /// group declaring an unlimited dimension.
+    pub fn header(&self, path: &str) -> Result<NetCDFHeader, HSE<String>> {
+        let lineage = self.lineage(path)?;
+        let mut header = lineage.last().unwrap().header.clone();
+        let mut dims = DimensionHM::new();
+        for g in lineage.iter() {
+            if let Some(d) = &g.header.dims {
+                dims.extend(d.iter().map(|(id, dim)| (*id, dim.clone())));
+            }
+        }
+        let unlimited = |g: &&&NetCDFGroup| {
+            let dims = g.header.dims.as_ref();
+            dims.map(|d| d.values().any(|d| d.length == 0))
+                .unwrap_or(false)
+        };
+        header.nor = match lineage.iter().rev().find(unlimited) {
+            Some(g) => g.header.nor.clone(),
+            None => NumberOfRecords::NonNegative(0),
+        };
+        header.dims = if dims.is_empty() { None } else { Some(dims) };
+        header.seeks = parser::calculate_seeks(&header.vars, &header.dims);
+        Ok(header)
+    }
+
+    /// Dimension names and lengths of a variable by path
+    pub fn variable_shape(&self, path: &str) -> Result<Vec<(String, usize)>, HSE<String>> {
+        let (group, name) = split(path);
+        self.header(group)?.variable_shape(name)
+    }
+}
+
+/// Split a path into the group path and the member name
+fn split(path: &str) -> (&str, &str) {
+    match path.rfind('/') {
+        Some(i) => (&path[..i], &path[i + 1..]),
+        None => ("", path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn test_classic_root() {
+        let mut file = File::open("assets/sresa1b_ncar_ccsm3-example.nc").unwrap();
+        let root = NetCDFGroup::from_file(&mut file).unwrap();
+        let mut file = File::open("assets/sresa1b_ncar_ccsm3-example.nc").unwrap();
+        let header = NetCDFHeader::from_file(&mut file).unwrap();
+
+        assert_eq!(root.paths(), vec!["/"]);
+        assert_eq!(root.group("/").unwrap().header, header);
+        assert_eq!(root.header("/").unwrap(), header);
+        assert_eq!(
+            root.variable("/tas").unwrap(),
+            root.variable("tas").unwrap()
+        );
+        assert_eq!(root.dimension("/lat").unwrap().length, 128);
+        assert_eq!(
+            root.variable_shape("/tas").unwrap(),
+            header.variable_shape("tas").unwrap()
+        );
+        let title = root.attribute("/title").and_then(|a| a.as_string());
+        assert!(title
+            .unwrap()
+            .starts_with("model output prepared for IPCC AR4"));
+    }
+
+    #[test]
+    fn test_nested_groups() {
+        let mut file = File::open("assets/test_hgroups.nc").unwrap();
+        let root = NetCDFGroup::from_file(&mut file).unwrap();
+        let flight = "/mozaic_flight_2012030403540535_ascent";
+
+        let paths = root.paths();
+        assert_eq!(paths.len(), 8);
+        assert_eq!(root.group(flight).unwrap().name, &flight[1..]);
+        assert_eq!(root.group(&flight[1..]).unwrap().name, &flight[1..]);
+
+        // dimensions of the root group are visible in the flights
+        let o3 = format!("{}/O3", flight);
+        assert_eq!(
+            root.variable_shape(&o3).unwrap(),
+            vec![("recNum".to_string(), 74)]
+        );
+        assert_eq!(
+            root.dimension(&format!("{}/recNum", flight))
+                .unwrap()
+                .length,
+            74
+        );
+        assert_eq!(root.variable(&o3).unwrap().vsize(), 74 * 8);
+        let header = root.header(flight).unwrap();
+        assert_eq!(header.dimension_length("recNum").unwrap(), 74);
+        let altitude = root.variable(&format!("{}/altitude", flight)).unwrap();
+        assert!(altitude.attribute("unit").is_some());
+        let attr = format!("{}/flight", flight);
+        let value = root.attribute(&attr).and_then(|a| a.as_string());
+        assert_eq!(value, Some("2012030403540535".to_string()));
+
+        assert_eq!(
+            root.group("/nothing/here"),
+            Err(HSE::GroupNotFound("/nothing/here".to_string()))
+        );
+        assert!(root.variable("/O3").is_err());
+        assert!(root.attribute(&format!("{}/nothing", flight)).is_none());
+    }
+}
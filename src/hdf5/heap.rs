@@ -0,0 +1,285 @@
+//! HDF5 heaps and B-trees holding group members and attributes
+use super::{read_at, Cursor, UNDEFINED};
+use crate::error::HeadScratcherError as HSE;
+use std::io::{Read, Seek};
+
+/// Bytes needed to store values up to `max`
+fn bytes_for(max: u64) -> usize {
+    let bits = 64 - max.leading_zeros() as usize;
+    bits.div_ceil(8)
+}
+
+/// Fractal heap, storage of dense links and attributes
+#[derive(Debug)]
+pub(crate) struct FractalHeap {
+    /// Bytes of the heap offset in heap ids
+    offset_size: usize,
+    /// Bytes of the object length in heap ids
+    length_size: usize,
+    filtered: bool,
+    table_width: u64,
+    start_block: u64,
+    max_direct_rows: u64,
+    root: u64,
+    root_rows: u64,
+}
+
+impl FractalHeap {
+    /// Read the fractal heap header at `address`
+    pub fn open<F: Read + Seek>(file: &mut F, address: u64) -> Result<Self, HSE<String>> {
+        let buf = read_at(file, address, 144)?;
+        let mut c = Cursor::new(&buf);
+        c.signature(b"FRHP")?;
+        c.skip(3)?; // version, heap id length
+        let filtered = c.u16()? != 0;
+        c.skip(1)?; // flags
+        let max_managed = c.u32()? as u64;
+        c.skip(12 * 8)?; // huge objects, free space and statistics
+        let table_width = c.u16()? as u64;
+        let start_block = c.u64()?;
+        let max_direct = c.u64()?;
+        let max_heap_bits = c.u16()? as usize;
+        c.skip(2)?; // starting rows of the root indirect block
+        let root = c.u64()?;
+        let root_rows = c.u16()? as u64;
+        if table_width == 0 || start_block == 0 || max_direct < start_block {
+            return Err(HSE::InvalidFile);
+        }
+        let log2 = |v: u64| 63 - v.leading_zeros() as u64;
+        Ok(FractalHeap {
+            offset_size: max_heap_bits.div_ceil(8),
+            length_size: bytes_for(max_direct.min(max_managed)),
+            filtered,
+            table_width,
+            start_block,
+            max_direct_rows: log2(max_direct) - log2(start_block) + 2,
+            root,
+            root_rows,
+        })
+    }
+
+    /// Object stored under a heap id
+    pub fn get<F: Read + Seek>(&self, file: &mut F, id: &[u8]) -> Result<Vec<u8>, HSE<String>> {
+        let mut c = Cursor::new(id);
+        let flags = c.u8()?;
+        match (flags >> 4) & 0x03 {
+            // managed object
+            0 => {
+                let offset = c.uint(self.offset_size)?;
+                let length = c.uint(self.length_size)? as usize;
+                let address = self.locate(file, offset)?;
+                read_at(file, address, length)
+            }
+            // tiny object, stored in the id itself
+            2 => Ok(c.bytes((flags & 0x0F) as usize + 1)?.to_vec()),
+            _ => Err(HSE::UnsupportedHDF5("Huge fractal heap object".to_string())),
+        }
+    }
+
+    /// File address of a heap offset
+    fn locate<F: Read + Seek>(&self, file: &mut F, offset: u64) -> Result<u64, HSE<String>> {
+        if self.filtered {
+            return Err(HSE::UnsupportedHDF5("Filtered fractal heap".to_string()));
+        }
+        // the root is a single direct block
+        if self.root_rows == 0 {
+            return self.root.checked_add(offset).ok_or(HSE::InvalidFile);
+        }
+        // walk the doubling table: rows 0 and 1 hold blocks of the starting
+        // size, every further row doubles the block size
+        let (mut row, mut row_start, mut size) = (0, 0u64, self.start_block);
+        loop {
+            if row >= self.max_direct_rows.min(self.root_rows) {
+                return Err(HSE::UnsupportedHDF5(
+                    "Nested fractal heap blocks".to_string(),
+                ));
+            }
+            let end = size
+                .checked_mul(self.table_width)
+                .and_then(|width| row_start.checked_add(width))
+                .ok_or(HSE::InvalidFile)?;
+            if offset < end {
+                break;
+            }
+            row_start = end;
+            if row > 0 {
+                size *= 2;
+            }
+            row += 1;
+        }
+        let column = (offset - row_start) / size;
+        let entry = row * self.table_width + column;
+        // signature, version, heap header address and block offset
+        let pos = self.root + 5 + 8 + self.offset_size as u64 + 8 * entry;
+        let block = Cursor::new(&read_at(file, pos, 8)?).u64()?;
+        if block == UNDEFINED {
+            return Err(HSE::InvalidFile);
+        }
+        block
+            .checked_add(offset - row_start - column * size)
+            .ok_or(HSE::InvalidFile)
+    }
+}
+
+/// All records of a version 2 B-tree
+pub(crate) fn btree_v2_records<F: Read + Seek>(
+    file: &mut F,
+    address: u64,
+) -> Result<Vec<Vec<u8>>, HSE<String>> {
+    let buf = read_at(file, address, 38)?;
+    let mut c = Cursor::new(&buf);
+    c.signature(b"BTHD")?;
+    c.skip(2)?; // version, type
+    let node_size = c.u32()? as usize;
+    let record_size = c.u16()? as usize;
+    let depth = c.u16()?;
+    c.skip(2)?; // split and merge percentages
+    let root = c.u64()?;
+    let count = c.u16()? as usize;
+    if depth > 1 {
+        return Err(HSE::UnsupportedHDF5("Deep version 2 B-tree".to_string()));
+    }
+    let mut records = Vec::new();
+    if root == UNDEFINED {
+        return Ok(records);
+    }
+    // children of the root are leaves, their record count is stored in as
+    // few bytes as the maximum number of leaf records needs
+    let leaf_records = match (node_size.checked_sub(10), record_size) {
+        (Some(space), size) if size > 0 => space / size,
+        _ => return Err(HSE::InvalidFile),
+    };
+    let mut nodes = vec![(root, count, depth)];
+    while let Some((address, count, depth)) = nodes.pop() {
+        let children = if depth > 0 { count + 1 } else { 0 };
+        let len = 6 + count * record_size + children * (8 + bytes_for(leaf_records as u64));
+        let buf = read_at(file, address, len)?;
+        let mut c = Cursor::new(&buf);
+        c.signature(if depth > 0 { b"BTIN" } else { b"BTLF" })?;
+        c.skip(2)?;
+        for _ in 0..count {
+            records.push(c.bytes(record_size)?.to_vec());
+        }
+        for _ in 0..children {
+            let child = c.u64()?;
+            let count = c.uint(bytes_for(leaf_records as u64))? as usize;
+            nodes.push((child, count, depth - 1));
+        }
+    }
+    Ok(records)
+}
+
+/// Object `index` of the global heap collection at `address`
+pub(crate) fn global_heap_object<F: Read + Seek>(
+    file: &mut F,
+    address: u64,
+    index: u32,
+) -> Result<Vec<u8>, HSE<String>> {
+    let buf = read_at(file, address, 16)?;
+    let mut c = Cursor::new(&buf);
+    c.signature(b"GCOL")?;
+    c.skip(4)?; // version, reserved
+    let size = c.u64()? as usize;
+    let buf = read_at(file, address, size)?;
+    let mut c = Cursor::new(&buf);
+    c.skip(16)?;
+    while c.remaining() >= 16 {
+        let id = c.u16()?;
+        c.skip(6)?; // reference count, reserved
+        let len = c.u64()? as usize;
+        // object 0 is the free space at the end of the collection
+        if id == 0 {
+            break;
+        }
+        let padded = len.checked_next_multiple_of(8).ok_or(HSE::InvalidFile)?;
+        let data = c.bytes(padded)?;
+        if id as u32 == index {
+            return Ok(data[..len].to_vec());
+        }
+    }
+    Err(HSE::InvalidFile)
+}
+
+/// Members `(name, object header address)` of an old style group
+pub(crate) fn symbol_table<F: Read + Seek>(
+    file: &mut F,
+    btree: u64,
+    heap: u64,
+) -> Result<Vec<(String, u64)>, HSE<String>> {
+    let buf = read_at(file, heap, 32)?;
+    let mut c = Cursor::new(&buf);
+    c.signature(b"HEAP")?;
+    c.skip(4)?; // version, reserved
+    let heap_size = c.u64()? as usize;
+    c.skip(8)?; // free list offset
+    let heap_data = read_at(file, c.u64()?, heap_size)?;
+
+    let mut entries = Vec::new();
+    for node in btree_v1_children(file, btree, 0)? {
+        let buf = read_at(file, node, 8)?;
+        let mut c = Cursor::new(&buf);
+        c.signature(b"SNOD")?;
+        c.skip(2)?; // version, reserved
+        let count = c.u16()? as usize;
+        let buf = read_at(file, node + 8, count * 40)?;
+        let mut c = Cursor::new(&buf);
+        for _ in 0..count {
+            let name_offset = c.u64()? as usize;
+            let address = c.u64()?;
+            c.skip(24)?; // cache type, reserved, scratch pad
+            let name = heap_data
+                .get(name_offset..)
+                .and_then(|n| n.split(|b| *b == 0).next())
+                .ok_or(HSE::InvalidFile)?;
+            let name = String::from_utf8(name.to_vec()).map_err(|_| HSE::UTF8error)?;
+            entries.push((name, address));
+        }
+    }
+    Ok(entries)
+}
+
+/// Leaf children of a version 1 B-tree of group nodes, whose keys are 8 bytes
+pub(crate) fn btree_v1_children<F: Read + Seek>(
+    file: &mut F,
+    address: u64,
+    node_type: u8,
+) -> Result<Vec<u64>, HSE<String>> {
+    Ok(btree_v1_entries(file, address, node_type, 8)?
+        .into_iter()
+        .map(|(_, child)| child)
+        .collect())
+}
+
+/// Leaf entries `(key, child)` of a version 1 B-tree, keys of `key_size` bytes
+pub(crate) fn btree_v1_entries<F: Read + Seek>(
+    file: &mut F,
+    address: u64,
+    node_type: u8,
+    key_size: usize,
+) -> Result<Vec<(Vec<u8>, u64)>, HSE<String>> {
+    let mut entries = Vec::new();
+    let mut nodes = vec![address];
+    while let Some(address) = nodes.pop() {
+        let buf = read_at(file, address, 24)?;
+        let mut c = Cursor::new(&buf);
+        c.signature(b"TREE")?;
+        if c.u8()? != node_type {
+            return Err(HSE::InvalidFile);
+        }
+        let level = c.u8()?;
+        let count = c.u16()? as usize;
+        let buf = read_at(file, address + 24, count * (key_size + 8) + key_size)?;
+        let mut c = Cursor::new(&buf);
+        let mut children = Vec::new();
+        for _ in 0..count {
+            let key = c.bytes(key_size)?.to_vec();
+            children.push((key, c.u64()?));
+        }
+        match level {
+            0 => entries.extend(children),
+            // keep the left to right order on the stack
+            _ => nodes.extend(children.into_iter().rev().map(|(_, child)| child)),
+        }
+    }
+    Ok(entries)
+}
@@ -0,0 +1,724 @@
+//! Minimal netCDF-4 reader
+//!
+//! # HDF5
+//! netCDF-4 files are HDF5 files. This module reads just enough of HDF5 to
+//! recover the netCDF view of a file: superblocks of version 0 to 3, object
+//! headers of version 1 and 2, compact and dense (fractal heap) storage of
+//! links and attributes, and old style symbol table groups. Only 8 byte
+//! addresses and lengths are supported, as written by the netCDF library.
+//!
+//! Dimensions are the datasets marked as dimension scales. Variables find
+//! their dimensions through `_Netcdf4Coordinates` or `DIMENSION_LIST`.
+//!
+//! Variable data is read from contiguous, compact and chunked storage, see
+//! `Storage`. Values are handed out big endian, like netCDF-3 data.
+use crate::constants_and_types as csts;
+use crate::data;
+use crate::error::HeadScratcherError as HSE;
+use crate::group::NetCDFGroup;
+use crate::parser::components::{
+    AttributeHM, DimensionHM, NetCDFAttribute, NetCDFDimension, NetCDFType, NetCDFTypeInstance,
+    NetCDFVariable, NetCDFVersion, NumberOfRecords, VariableHM,
+};
+use crate::parser::NetCDFHeader;
+use objects::{Attribute, Dataspace, Datatype, Filter, Layout, Message};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::io::{Read, Seek, SeekFrom};
+
+mod heap;
+mod objects;
+mod storage;
+
+pub(crate) use storage::Storage;
+
+/// Undefined address
+pub(crate) const UNDEFINED: u64 = u64::MAX;
+
+/// Attributes netCDF-4 uses for its own bookkeeping
+const HIDDEN_ATTRIBUTES: &[&str] = &[
+    "CLASS",
+    "NAME",
+    "REFERENCE_LIST",
+    "DIMENSION_LIST",
+    "_Netcdf4Dimid",
+    "_Netcdf4Coordinates",
+    "_nc3_strict",
+    "_NCProperties",
+];
+
+/// Name of dimension scales that are no netCDF variable
+const DIMENSION_ONLY: &str = "This is a netCDF dimension but not a netCDF variable";
+
+/// Read up to `len` bytes at `address`, less at the end of the file
+///
+/// Addresses beyond the end of the file are invalid.
+pub(crate) fn read_at<F: Read + Seek>(
+    file: &mut F,
+    address: u64,
+    len: usize,
+) -> Result<Vec<u8>, HSE<String>> {
+    // lengths come from the file, never allocate more than it holds
+    let end = file.seek(SeekFrom::End(0))?;
+    let available = end.checked_sub(address).ok_or(HSE::InvalidFile)?;
+    let len = len.min(usize::try_from(available).unwrap_or(usize::MAX));
+    file.seek(SeekFrom::Start(address))?;
+    let mut buf = Vec::with_capacity(len);
+    file.take(len as u64).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Little endian reader over a block of HDF5 metadata
+pub(crate) struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Cursor { data, pos: 0 }
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], HSE<String>> {
+        let end = self.pos.checked_add(n).ok_or(HSE::InvalidFile)?;
+        match self.data.get(self.pos..end) {
+            Some(b) => {
+                self.pos += n;
+                Ok(b)
+            }
+            None => Err(HSE::InvalidFile),
+        }
+    }
+
+    pub fn skip(&mut self, n: usize) -> Result<(), HSE<String>> {
+        self.bytes(n).map(|_| ())
+    }
+
+    /// Unsigned integer of `n` bytes
+    pub fn uint(&mut self, n: usize) -> Result<u64, HSE<String>> {
+        let b = self.bytes(n)?;
+        Ok(b.iter().rev().fold(0, |acc, x| (acc << 8) | *x as u64))
+    }
+
+    pub fn u8(&mut self) -> Result<u8, HSE<String>> {
+        self.uint(1).map(|v| v as u8)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, HSE<String>> {
+        self.uint(2).map(|v| v as u16)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, HSE<String>> {
+        self.uint(4).map(|v| v as u32)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, HSE<String>> {
+        self.uint(8)
+    }
+
+    pub fn signature(&mut self, signature: &[u8]) -> Result<(), HSE<String>> {
+        match self.bytes(signature.len())? == signature {
+            true => Ok(()),
+            false => Err(HSE::InvalidFile),
+        }
+    }
+}
+
+/// HDF5 dataset
+#[derive(Debug, Clone)]
+pub(crate) struct Dataset {
+    pub name: String,
+    pub address: u64,
+    pub datatype: Datatype,
+    pub dataspace: Dataspace,
+    pub layout: Layout,
+    pub filters: Vec<Filter>,
+    pub attributes: Vec<Attribute>,
+}
+
+impl Dataset {
+    fn attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes.iter().find(|a| a.name == name)
+    }
+
+    fn is_dimension(&self) -> bool {
+        self.attribute("CLASS")
+            .map(|a| a.data.starts_with(b"DIMENSION_SCALE"))
+            .unwrap_or(false)
+    }
+}
+
+/// Dimension scale visible to a group
+#[derive(Debug, Clone)]
+pub(crate) struct Scale {
+    address: u64,
+    /// Dimension id given by the netCDF library, `_Netcdf4Dimid`
+    dimid: Option<i64>,
+    /// Dimension id in the headers
+    id: usize,
+}
+
+/// HDF5 group with its members
+#[derive(Debug, Clone)]
+pub(crate) struct Group {
+    pub name: String,
+    pub attributes: Vec<Attribute>,
+    pub datasets: Vec<Dataset>,
+    pub groups: Vec<Group>,
+}
+
+/// Object header address of the root group
+fn superblock<F: Read + Seek>(file: &mut F) -> Result<u64, HSE<String>> {
+    let buf = read_at(file, 0, 96)?;
+    let mut c = Cursor::new(&buf);
+    if c.bytes(8)? != csts::HDF5_SIGNATURE {
+        return Err(HSE::InvalidFile);
+    }
+    let version = c.u8()?;
+    let sizes = match version {
+        0 | 1 => {
+            c.skip(4)?; // versions of free space, symbol table, shared headers
+            let sizes = (c.u8()?, c.u8()?);
+            c.skip(1 + 4 + 4)?; // reserved, B-tree K values, flags
+            if version == 1 {
+                c.skip(4)?; // indexed storage K
+            }
+            sizes
+        }
+        2 | 3 => {
+            let sizes = (c.u8()?, c.u8()?);
+            c.skip(1)?; // flags
+            sizes
+        }
+        _ => {
+            let msg = format!("Superblock version {}", version);
+            return Err(HSE::UnsupportedHDF5(msg));
+        }
+    };
+    if sizes != (8, 8) {
+        let msg = format!("Offset and length sizes {:?}", sizes);
+        return Err(HSE::UnsupportedHDF5(msg));
+    }
+    match version {
+        0 | 1 => {
+            c.skip(4 * 8)?; // base, free space, end of file and driver addresses
+            c.skip(8)?; // root symbol table entry: link name offset
+            c.u64()
+        }
+        _ => {
+            c.skip(3 * 8)?; // base, extension and end of file addresses
+            c.u64()
+        }
+    }
+}
+
+/// Read the group at `address` with all its members
+fn group<F: Read + Seek>(
+    file: &mut F,
+    name: &str,
+    address: u64,
+    visited: &mut HashSet<u64>,
+) -> Result<Group, HSE<String>> {
+    visited.insert(address);
+    let messages = objects::object_header(file, address)?;
+    let mut group = Group {
+        name: name.to_string(),
+        attributes: attributes(file, &messages)?,
+        datasets: Vec::new(),
+        groups: Vec::new(),
+    };
+    for (name, address) in links(file, &messages)? {
+        // hard links may point back up the tree
+        if visited.contains(&address) {
+            continue;
+        }
+        let messages = objects::object_header(file, address)?;
+        let (mut datatype, mut dataspace, mut layout) = (None, None, None);
+        let mut filters = Vec::new();
+        for m in messages.iter() {
+            match m {
+                Message::Datatype(t) => datatype = Some(t.clone()),
+                Message::Dataspace(s) => dataspace = Some(s.clone()),
+                Message::Layout(l) => layout = Some(l.clone()),
+                Message::Filters(f) => filters = f.clone(),
+                _ => (),
+            }
+        }
+        match (datatype, dataspace, layout) {
+            (Some(datatype), Some(dataspace), Some(layout)) => {
+                visited.insert(address);
+                group.datasets.push(Dataset {
+                    name,
+                    address,
+                    datatype,
+                    dataspace,
+                    layout,
+                    filters,
+                    attributes: attributes(file, &messages)?,
+                })
+            }
+            _ => group
+                .groups
+                .push(self::group(file, &name, address, visited)?),
+        }
+    }
+    Ok(group)
+}
+
+/// Members `(name, address)` of a group in creation order
+fn links<F: Read + Seek>(
+    file: &mut F,
+    messages: &[Message],
+) -> Result<Vec<(String, u64)>, HSE<String>> {
+    let mut links = Vec::new();
+    for m in messages.iter() {
+        match m {
+            Message::Link(l) => links.push(l.clone()),
+            Message::LinkInfo { heap, names } if *heap != UNDEFINED => {
+                let heap = heap::FractalHeap::open(file, *heap)?;
+                for record in heap::btree_v2_records(file, *names)? {
+                    // name hash in front of the heap id
+                    let id = record.get(4..).ok_or(HSE::InvalidFile)?;
+                    let data = heap.get(file, id)?;
+                    links.push(objects::link(&mut Cursor::new(&data))?);
+                }
+            }
+            Message::SymbolTable { btree, heap } => {
+                return heap::symbol_table(file, *btree, *heap);
+            }
+            _ => (),
+        }
+    }
+    links.sort_by(|a, b| (a.order, &a.name).cmp(&(b.order, &b.name)));
+    Ok(links
+        .into_iter()
+        .filter_map(|l| l.address.map(|a| (l.name, a)))
+        .collect())
+}
+
+/// Compact and dense attributes of an object in creation order
+fn attributes<F: Read + Seek>(
+    file: &mut F,
+    messages: &[Message],
+) -> Result<Vec<Attribute>, HSE<String>> {
+    let mut attrs = Vec::new();
+    for m in messages.iter() {
+        match m {
+            Message::Attribute(a) => attrs.push(a.clone()),
+            Message::AttributeInfo { heap, names } if *heap != UNDEFINED => {
+                let heap = heap::FractalHeap::open(file, *heap)?;
+                let mut dense = Vec::new();
+                for record in heap::btree_v2_records(file, *names)? {
+                    // heap id, flags, creation order and name hash
+                    let (id, order) = match (record.get(..8), record.get(9..13)) {
+                        (Some(id), Some(order)) => (id, Cursor::new(order).u32()?),
+                        _ => return Err(HSE::InvalidFile),
+                    };
+                    let data = heap.get(file, id)?;
+                    dense.push((order, objects::attribute(&mut Cursor::new(&data))?));
+                }
+                dense.sort_by_key(|(order, _)| *order);
+                attrs.extend(dense.into_iter().map(|(_, a)| a));
+            }
+            _ => (),
+        }
+    }
+    Ok(attrs)
+}
+
+/// Read the group tree of a netCDF-4 file
+pub(crate) fn read<F: Read + Seek>(file: &mut F) -> Result<Group, HSE<String>> {
+    let root = superblock(file)?;
+    group(file, "", root, &mut HashSet::new())
+}
+
+/// Parse the root group of a netCDF-4 file into a header
+pub fn header<F: Read + Seek>(file: &mut F) -> Result<NetCDFHeader, HSE<String>> {
+    Ok(open(file)?.0)
+}
+
+/// Header of the root group and the storage of its variables
+pub(crate) fn open<F: Read + Seek>(
+    file: &mut F,
+) -> Result<(NetCDFHeader, HashMap<String, Storage>), HSE<String>> {
+    let root = read(file)?;
+    let (header, _) = convert(file, &root, &[], &mut 0)?;
+    let vars = header.vars.as_ref();
+    let storage = root
+        .datasets
+        .iter()
+        .filter(|d| vars.map(|v| v.contains_key(&d.name)).unwrap_or(false))
+        .map(|d| (d.name.clone(), Storage::new(d)))
+        .collect();
+    Ok((header, storage))
+}
+
+/// Parse the group tree of a netCDF-4 file
+pub fn tree<F: Read + Seek>(file: &mut F) -> Result<NetCDFGroup, HSE<String>> {
+    fn walk<F: Read + Seek>(
+        file: &mut F,
+        group: &Group,
+        scales: &mut Vec<Scale>,
+        next_id: &mut usize,
+    ) -> Result<NetCDFGroup, HSE<String>> {
+        let (header, own) = convert(file, group, scales, next_id)?;
+        // dimensions of a group are visible to all its descendants
+        scales.extend(own);
+        let mut groups = Vec::new();
+        for g in group.groups.iter() {
+            let len = scales.len();
+            groups.push(walk(file, g, scales, next_id)?);
+            scales.truncate(len);
+        }
+        Ok(NetCDFGroup::new(group.name.clone(), header, groups))
+    }
+    let root = read(file)?;
+    walk(file, &root, &mut Vec::new(), &mut 0)
+}
+
+/// netCDF view of a group and the dimension scales it declares
+///
+/// `scales` are the dimension scales of the ancestors of the group. Dimension
+/// ids are unique within the file, `next_id` is the first free one.
+pub(crate) fn convert<F: Read + Seek>(
+    file: &mut F,
+    group: &Group,
+    ancestors: &[Scale],
+    next_id: &mut usize,
+) -> Result<(NetCDFHeader, Vec<Scale>), HSE<String>> {
+    let mut dims = DimensionHM::new();
+    let mut scales = ancestors.to_vec();
+    let mut records = None;
+    let mut ordered: Vec<(Option<i64>, &Dataset)> = group
+        .datasets
+        .iter()
+        .filter(|d| d.is_dimension())
+        .map(|d| (integer(d.attribute("_Netcdf4Dimid")), d))
+        .collect();
+    ordered.sort_by_key(|(id, _)| id.unwrap_or(i64::MAX));
+    for (dimid, d) in ordered {
+        let length = d.dataspace.dims.first().copied().unwrap_or(0) as usize;
+        let unlimited = match &d.dataspace.max {
+            Some(max) => max.first() == Some(&UNDEFINED),
+            None => false,
+        };
+        let id = *next_id;
+        *next_id += 1;
+        if unlimited {
+            records.get_or_insert(length);
+        }
+        let length = if unlimited { 0 } else { length };
+        dims.insert(id, NetCDFDimension::new(d.name.clone(), length));
+        scales.push(Scale {
+            address: d.address,
+            dimid,
+            id,
+        });
+    }
+
+    let mut vars = VariableHM::new();
+    for d in group.datasets.iter() {
+        let is_variable = d
+            .attribute("NAME")
+            .map(|a| !a.data.starts_with(DIMENSION_ONLY.as_bytes()))
+            .unwrap_or(true);
+        let nc_type = match nc_type(&d.datatype) {
+            Some(t) if is_variable => t,
+            _ => continue,
+        };
+        let ids = variable_dims(file, d, &scales, &mut dims, next_id)?;
+        let attrs: AttributeHM = d
+            .attributes
+            .iter()
+            .filter_map(|a| attribute(file, a).transpose())
+            .map(|a| a.map(|a| (a.name(), a)))
+            .collect::<Result<AttributeHM, _>>()?;
+        let attrs = if attrs.is_empty() { None } else { Some(attrs) };
+        let (begin, vsize) = match d.layout {
+            Layout::Contiguous { address, size } => (address, size as usize),
+            _ => (0, d.dataspace.bytes(d.datatype.size())?),
+        };
+        let var = NetCDFVariable::new(d.name.clone(), ids, attrs, nc_type, vsize, begin);
+        vars.insert(d.name.clone(), var);
+    }
+
+    let attrs: AttributeHM = group
+        .attributes
+        .iter()
+        .filter_map(|a| attribute(file, a).transpose())
+        .map(|a| a.map(|a| (a.name(), a)))
+        .collect::<Result<AttributeHM, _>>()?;
+    let nor = NumberOfRecords::NonNegative(records.unwrap_or(0) as u64);
+    let attrs = if attrs.is_empty() { None } else { Some(attrs) };
+    let dims = if dims.is_empty() { None } else { Some(dims) };
+    let vars = if vars.is_empty() { None } else { Some(vars) };
+    // the seek table needs the dimensions of the ancestors too
+    let seeks = match ancestors.is_empty() {
+        true => crate::parser::calculate_seeks(&vars, &dims),
+        false => None,
+    };
+    let header = NetCDFHeader::new(NetCDFVersion::NetCDF4, nor, attrs, dims, vars, seeks, 0);
+    Ok((header, scales.split_off(ancestors.len())))
+}
+
+/// Dimension ids of a dataset
+///
+/// Scales are searched from the last one, so dimensions of a group hide
+/// those of its ancestors. Datasets without dimension information get
+/// anonymous `phony_dim_N` dimensions, shared by size like the netCDF library
+/// does.
+fn variable_dims<F: Read + Seek>(
+    file: &mut F,
+    d: &Dataset,
+    scales: &[Scale],
+    dims: &mut DimensionHM,
+    next_id: &mut usize,
+) -> Result<Vec<u32>, HSE<String>> {
+    let rank = d.dataspace.dims.len();
+    if let Some(a) = d.attribute("_Netcdf4Coordinates") {
+        let ids: Vec<u32> = NetCDFAttribute::from_value(a.name.clone(), values(a)?)
+            .as_i64_vec()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|dimid| scales.iter().rev().find(|s| s.dimid == Some(dimid)))
+            .map(|s| s.id as u32)
+            .collect();
+        if ids.len() == rank {
+            return Ok(ids);
+        }
+    }
+    if let Some(a) = d.attribute("DIMENSION_LIST") {
+        let mut ids = Vec::new();
+        for element in a.data.chunks_exact(16) {
+            let mut c = Cursor::new(element);
+            c.skip(4)?; // number of references
+            let (collection, index) = (c.u64()?, c.u32()?);
+            let refs = heap::global_heap_object(file, collection, index)?;
+            let address = Cursor::new(&refs).u64()?;
+            match scales.iter().rev().find(|s| s.address == address) {
+                Some(s) => ids.push(s.id as u32),
+                None => break,
+            }
+        }
+        if ids.len() == rank {
+            return Ok(ids);
+        }
+    }
+    if let Some(s) = scales.iter().rev().find(|s| s.address == d.address) {
+        if rank == 1 {
+            return Ok(vec![s.id as u32]);
+        }
+    }
+    let mut ids = Vec::new();
+    for length in d.dataspace.dims.iter().map(|l| *l as usize) {
+        let phony = dims
+            .iter()
+            .find(|(_, dim)| dim.name.starts_with("phony_dim_") && dim.length == length)
+            .map(|(k, _)| *k);
+        let id = match phony {
+            Some(id) => id,
+            None => {
+                let id = *next_id;
+                *next_id += 1;
+                let count = dims
+                    .values()
+                    .filter(|d| d.name.starts_with("phony_dim_"))
+                    .count();
+                let name = format!("phony_dim_{}", count);
+                dims.insert(id, NetCDFDimension::new(name, length));
+                id
+            }
+        };
+        ids.push(id as u32);
+    }
+    Ok(ids)
+}
+
+/// netCDF type of a HDF5 datatype, `None` for types without netCDF-3 equivalent
+fn nc_type(datatype: &Datatype) -> Option<NetCDFType> {
+    let t = match datatype {
+        Datatype::Integer { size, signed, .. } => match (size, signed) {
+            (1, true) => NetCDFType::NC_BYTE,
+            (1, false) => NetCDFType::NC_UBYTE,
+            (2, true) => NetCDFType::NC_SHORT,
+            (2, false) => NetCDFType::NC_USHORT,
+            (4, true) => NetCDFType::NC_INT,
+            (4, false) => NetCDFType::NC_UINT,
+            (8, true) => NetCDFType::NC_INT64,
+            (8, false) => NetCDFType::NC_UINT64,
+            _ => return None,
+        },
+        Datatype::Float { size: 4, .. } => NetCDFType::NC_FLOAT,
+        Datatype::Float { size: 8, .. } => NetCDFType::NC_DOUBLE,
+        Datatype::String { size: 1 } => NetCDFType::NC_CHAR,
+        _ => return None,
+    };
+    Some(t)
+}
+
+/// Whether values are stored little endian
+pub(crate) fn little_endian(datatype: &Datatype) -> bool {
+    match datatype {
+        Datatype::Integer { big_endian, .. } => !big_endian,
+        Datatype::Float { big_endian, .. } => !big_endian,
+        _ => false,
+    }
+}
+
+/// Reverse the bytes of every element of size `size` in place
+pub(crate) fn swap_bytes(buffer: &mut [u8], size: usize) {
+    for element in buffer.chunks_exact_mut(size) {
+        element.reverse();
+    }
+}
+
+/// Decoded values of an attribute
+fn values(a: &Attribute) -> Result<NetCDFTypeInstance, HSE<String>> {
+    match &a.datatype {
+        // netCDF-4 char attributes, kept byte for byte like classic ones
+        Datatype::String { .. } => {
+            let s = String::from_utf8(a.data.clone()).map_err(|_| HSE::UTF8error)?;
+            Ok(NetCDFTypeInstance::STRING(s))
+        }
+        t => match nc_type(t) {
+            Some(nc_type) => {
+                let mut buffer = a.data.clone();
+                if little_endian(t) {
+                    swap_bytes(&mut buffer, t.size());
+                }
+                data::decode_values(&nc_type, &buffer)
+            }
+            None => Err(HSE::UnsupportedHDF5(format!("Type of {}", a.name))),
+        },
+    }
+}
+
+/// netCDF attribute of a HDF5 attribute, `None` for hidden and unsupported ones
+fn attribute<F: Read + Seek>(
+    file: &mut F,
+    a: &Attribute,
+) -> Result<Option<NetCDFAttribute>, HSE<String>> {
+    if HIDDEN_ATTRIBUTES.contains(&a.name.as_str()) {
+        return Ok(None);
+    }
+    let value = match &a.datatype {
+        // netCDF-4 strings, joined by newlines
+        Datatype::VarString => {
+            let mut strings = Vec::new();
+            for element in a.data.chunks_exact(16) {
+                let mut c = Cursor::new(element);
+                let len = c.u32()? as usize;
+                let (collection, index) = (c.u64()?, c.u32()?);
+                let s = match len {
+                    0 => Vec::new(),
+                    _ => heap::global_heap_object(file, collection, index)?,
+                };
+                strings.push(String::from_utf8(s).map_err(|_| HSE::UTF8error)?);
+            }
+            NetCDFTypeInstance::STRING(strings.join("\n"))
+        }
+        Datatype::String { .. } | Datatype::Integer { .. } | Datatype::Float { .. } => values(a)?,
+        _ => return Ok(None),
+    };
+    Ok(Some(NetCDFAttribute::from_value(a.name.clone(), value)))
+}
+
+/// First value of an integer attribute
+fn integer(a: Option<&Attribute>) -> Option<i64> {
+    a.and_then(|a| values(a).ok())
+        .and_then(|v| NetCDFAttribute::from_value(String::new(), v).as_i64_vec())
+        .and_then(|v| v.first().copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn test_tree() {
+        let mut file = File::open("assets/test_hgroups.nc").unwrap();
+        let root = tree(&mut file).unwrap();
+        let paths = root.paths();
+        assert_eq!(paths.len(), 8);
+        assert_eq!(paths[0], "/");
+        assert!(paths.contains(&"/mozaic_flight_2012030403540535_ascent".to_string()));
+
+        let g = &root.groups[0].header;
+        let flight = g.attrs.as_ref().unwrap()["flight"].as_string();
+        assert_eq!(flight, Some("2012030403540535".to_string()));
+        let vars = g.vars.as_ref().unwrap();
+        assert_eq!(vars["O3"].nc_type, NetCDFType::NC_DOUBLE);
+        assert_eq!(vars["O3"].vsize(), 74 * 8);
+        assert!(vars["lat"].dims.is_empty());
+        // the flights share the dimension of the root group
+        let id = root.header.dimension_id("recNum").unwrap() as u32;
+        assert_eq!(vars["O3"].dims, vec![id]);
+        assert!(g.dims.is_none());
+    }
+
+    #[test]
+    fn test_malformed() {
+        let mut data = Vec::new();
+        File::open("assets/test_hgroups.nc")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        // damaged files fail with an error instead of a panic
+        for pos in (8..data.len()).step_by(127) {
+            for value in [0x00, 0xFF] {
+                let mut damaged = data.clone();
+                damaged[pos] = value;
+                let _ = tree(&mut std::io::Cursor::new(damaged));
+            }
+        }
+        for len in (0..data.len()).step_by(997) {
+            let _ = tree(&mut std::io::Cursor::new(&data[..len]));
+        }
+        let e = tree(&mut std::io::Cursor::new(&data[..512])).unwrap_err();
+        assert_eq!(e, HSE::InvalidFile);
+    }
+
+    #[test]
+    fn test_header() {
+        let mut file = File::open("assets/sresa1b_ncar_ccsm3-example.4.nc").unwrap();
+        let h = header(&mut file).unwrap();
+        let mut file = File::open("assets/sresa1b_ncar_ccsm3-example.nc").unwrap();
+        let c = NetCDFHeader::from_file(&mut file).unwrap();
+
+        assert_eq!(h.version, NetCDFVersion::NetCDF4);
+        assert_eq!(h.nor, c.nor);
+        assert!(h.is_unlimited("time").unwrap());
+        let (hv, cv) = (h.vars.as_ref().unwrap(), c.vars.as_ref().unwrap());
+        assert_eq!(hv.keys().collect::<Vec<_>>(), cv.keys().collect::<Vec<_>>());
+        // nccopy drops the trailing NULs the classic file stores
+        let text = |a: Option<&NetCDFAttribute>| {
+            let s = a.and_then(|a| a.as_string());
+            s.map(|s| s.trim_end_matches('\0').to_string())
+        };
+        for (name, var) in cv.iter() {
+            assert_eq!(hv[name].nc_type, var.nc_type);
+            assert_eq!(h.variable_shape(name), c.variable_shape(name));
+            assert_eq!(
+                text(hv[name].attribute("units")),
+                text(var.attribute("units"))
+            );
+        }
+        let (ha, ca) = (h.attrs.as_ref().unwrap(), c.attrs.as_ref().unwrap());
+        assert_eq!(ha.keys().collect::<Vec<_>>(), ca.keys().collect::<Vec<_>>());
+        assert_eq!(ha["realization"], ca["realization"]);
+        assert_eq!(text(ha.get("title")), text(ca.get("title")));
+        assert_eq!(
+            hv["pr"].attribute("missing_value"),
+            cv["pr"].attribute("missing_value")
+        );
+    }
+}
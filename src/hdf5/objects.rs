@@ -0,0 +1,489 @@
+//! HDF5 object headers and the header messages used by netCDF-4
+use super::{read_at, Cursor};
+use crate::error::HeadScratcherError as HSE;
+use std::convert::TryFrom;
+use std::io::{Read, Seek};
+
+const NIL: u8 = 0x00;
+const DATASPACE: u8 = 0x01;
+const LINK_INFO: u8 = 0x02;
+const DATATYPE: u8 = 0x03;
+const LINK: u8 = 0x06;
+const LAYOUT: u8 = 0x08;
+const FILTER_PIPELINE: u8 = 0x0B;
+const ATTRIBUTE: u8 = 0x0C;
+const CONTINUATION: u8 = 0x10;
+const SYMBOL_TABLE: u8 = 0x11;
+const ATTRIBUTE_INFO: u8 = 0x15;
+
+/// Size and maximum size of a dataset or attribute
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Dataspace {
+    pub dims: Vec<u64>,
+    pub max: Option<Vec<u64>>,
+}
+
+impl Dataspace {
+    /// Size in bytes of all elements of `size` bytes
+    pub fn bytes(&self, size: usize) -> Result<usize, HSE<String>> {
+        self.dims
+            .iter()
+            .try_fold(size, |acc, l| {
+                usize::try_from(*l).ok().and_then(|l| acc.checked_mul(l))
+            })
+            .ok_or(HSE::InvalidFile)
+    }
+}
+
+/// Element type of a dataset or attribute
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Datatype {
+    Integer {
+        size: usize,
+        signed: bool,
+        big_endian: bool,
+    },
+    Float {
+        size: usize,
+        big_endian: bool,
+    },
+    /// Fixed length string
+    String {
+        size: usize,
+    },
+    /// Variable length string, stored in the global heap
+    VarString,
+    /// Variable length sequence, stored in the global heap
+    Sequence(Box<Datatype>),
+    /// Object reference
+    Reference,
+    Other {
+        class: u8,
+        size: usize,
+    },
+}
+
+impl Datatype {
+    /// Size of one element in the file
+    pub fn size(&self) -> usize {
+        match self {
+            Datatype::Integer { size, .. } => *size,
+            Datatype::Float { size, .. } => *size,
+            Datatype::String { size } => *size,
+            // length, global heap collection and object index
+            Datatype::VarString | Datatype::Sequence(_) => 16,
+            Datatype::Reference => 8,
+            Datatype::Other { size, .. } => *size,
+        }
+    }
+}
+
+/// Storage of the raw data of a dataset
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Layout {
+    Compact(Vec<u8>),
+    Contiguous {
+        address: u64,
+        size: u64,
+    },
+    /// Chunk dimensions (without the element size) and chunk index
+    Chunked {
+        chunk: Vec<u64>,
+        index: ChunkIndex,
+    },
+}
+
+/// Index of the chunks of a dataset
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum ChunkIndex {
+    /// Version 1 B-tree, the only index of layout message version 3
+    BTree(u64),
+    /// Layout message version 4 index type and address
+    Other(u8, u64),
+}
+
+/// Filter applied to every chunk of a dataset
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Filter {
+    pub id: u16,
+    /// Client data, e.g. the element size of the shuffle filter
+    pub params: Vec<u32>,
+}
+
+/// HDF5 attribute, data still in file byte order
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Attribute {
+    pub name: String,
+    pub datatype: Datatype,
+    pub dataspace: Dataspace,
+    pub data: Vec<u8>,
+}
+
+/// Link from a group to an object
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Link {
+    pub name: String,
+    /// Object header address of hard links
+    pub address: Option<u64>,
+    pub order: Option<u64>,
+}
+
+/// Header messages netCDF-4 relies on
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Message {
+    Dataspace(Dataspace),
+    Datatype(Datatype),
+    Layout(Layout),
+    Filters(Vec<Filter>),
+    Attribute(Attribute),
+    Link(Link),
+    /// Fractal heap and name index of densely stored links
+    LinkInfo {
+        heap: u64,
+        names: u64,
+    },
+    /// Fractal heap and name index of densely stored attributes
+    AttributeInfo {
+        heap: u64,
+        names: u64,
+    },
+    /// Version 1 B-tree and local heap of an old style group
+    SymbolTable {
+        btree: u64,
+        heap: u64,
+    },
+}
+
+/// Read all messages of an object header, following continuation blocks
+pub(crate) fn object_header<F: Read + Seek>(
+    file: &mut F,
+    address: u64,
+) -> Result<Vec<Message>, HSE<String>> {
+    let prefix = read_at(file, address, 32)?;
+    let mut messages = Vec::new();
+    let mut blocks = Vec::new();
+    let v2 = prefix.starts_with(b"OHDR");
+    let flags = if v2 {
+        let mut c = Cursor::new(&prefix);
+        c.skip(5)?;
+        let flags = c.u8()?;
+        if flags & 0x20 != 0 {
+            c.skip(16)?; // access, modification, change and birth times
+        }
+        if flags & 0x10 != 0 {
+            c.skip(4)?; // attribute storage phase change values
+        }
+        let size = c.uint(1 << (flags & 0x03))?;
+        blocks.push((address + c.pos() as u64, size));
+        flags
+    } else {
+        let mut c = Cursor::new(&prefix);
+        if c.u8()? != 1 {
+            return Err(HSE::UnsupportedHDF5("Object header version".to_string()));
+        }
+        c.skip(7)?;
+        let size = c.u32()? as u64;
+        blocks.push((address + 16, size));
+        0
+    };
+    let mut k = 0;
+    while let Some(&(start, size)) = blocks.get(k) {
+        k += 1;
+        let block = read_at(file, start, size as usize)?;
+        let mut c = Cursor::new(&block);
+        // gaps smaller than a message prefix can trail a v2 block
+        let prefix = match (v2, flags & 0x04) {
+            (true, 0) => 4,
+            (true, _) => 6,
+            (false, _) => 8,
+        };
+        while c.remaining() >= prefix {
+            let (kind, len, mflags) = if v2 {
+                let kind = c.u8()?;
+                let len = c.u16()? as usize;
+                let mflags = c.u8()?;
+                if flags & 0x04 != 0 {
+                    c.skip(2)?; // creation order
+                }
+                (kind, len, mflags)
+            } else {
+                let kind = c.u16()?;
+                let len = c.u16()? as usize;
+                let mflags = c.u8()?;
+                c.skip(3)?;
+                (kind as u8, len, mflags)
+            };
+            let data = c.bytes(len)?;
+            if kind == CONTINUATION {
+                let mut m = Cursor::new(data);
+                let (addr, length) = (m.u64()?, m.u64()?);
+                let block = if v2 {
+                    // signature in front, checksum at the end
+                    match (addr.checked_add(4), length.checked_sub(8)) {
+                        (Some(addr), Some(length)) => (addr, length),
+                        _ => return Err(HSE::InvalidFile),
+                    }
+                } else {
+                    (addr, length)
+                };
+                // a continuation back to a read block would never end
+                if blocks.contains(&block) {
+                    return Err(HSE::InvalidFile);
+                }
+                blocks.push(block);
+            } else if kind != NIL && mflags & 0x02 == 0 {
+                if let Some(message) = message(kind, data)? {
+                    messages.push(message);
+                }
+            }
+        }
+    }
+    Ok(messages)
+}
+
+/// Decode a single message, `None` for message types netCDF-4 does not need
+fn message(kind: u8, data: &[u8]) -> Result<Option<Message>, HSE<String>> {
+    let mut c = Cursor::new(data);
+    let message = match kind {
+        DATASPACE => Message::Dataspace(dataspace(&mut c)?),
+        DATATYPE => Message::Datatype(datatype(&mut c)?),
+        LAYOUT => Message::Layout(layout(&mut c)?),
+        FILTER_PIPELINE => Message::Filters(filters(&mut c)?),
+        ATTRIBUTE => Message::Attribute(attribute(&mut c)?),
+        LINK => Message::Link(link(&mut c)?),
+        LINK_INFO => {
+            c.skip(1)?;
+            if c.u8()? & 0x01 != 0 {
+                c.skip(8)?; // maximum creation index
+            }
+            Message::LinkInfo {
+                heap: c.u64()?,
+                names: c.u64()?,
+            }
+        }
+        ATTRIBUTE_INFO => {
+            c.skip(1)?;
+            if c.u8()? & 0x01 != 0 {
+                c.skip(2)?; // maximum creation index
+            }
+            Message::AttributeInfo {
+                heap: c.u64()?,
+                names: c.u64()?,
+            }
+        }
+        SYMBOL_TABLE => Message::SymbolTable {
+            btree: c.u64()?,
+            heap: c.u64()?,
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(message))
+}
+
+/// Decode a dataspace message
+pub(crate) fn dataspace(c: &mut Cursor) -> Result<Dataspace, HSE<String>> {
+    let version = c.u8()?;
+    let rank = c.u8()? as usize;
+    let flags = c.u8()?;
+    match version {
+        1 => c.skip(5)?,
+        _ => {
+            // null dataspaces hold no elements
+            if c.u8()? == 2 {
+                return Ok(Dataspace {
+                    dims: vec![0],
+                    max: None,
+                });
+            }
+        }
+    }
+    let dims = (0..rank)
+        .map(|_| c.u64())
+        .collect::<Result<Vec<u64>, _>>()?;
+    let max = match flags & 0x01 {
+        0 => None,
+        _ => Some(
+            (0..rank)
+                .map(|_| c.u64())
+                .collect::<Result<Vec<u64>, _>>()?,
+        ),
+    };
+    Ok(Dataspace { dims, max })
+}
+
+/// Decode a datatype message
+pub(crate) fn datatype(c: &mut Cursor) -> Result<Datatype, HSE<String>> {
+    let class = c.u8()? & 0x0F;
+    let bits = c.u8()?;
+    c.skip(2)?;
+    let size = c.u32()? as usize;
+    let datatype = match class {
+        0 => {
+            c.skip(4)?; // bit offset and precision
+            Datatype::Integer {
+                size,
+                signed: bits & 0x08 != 0,
+                big_endian: bits & 0x01 != 0,
+            }
+        }
+        1 => {
+            c.skip(12)?; // bit layout of sign, exponent and mantissa
+            Datatype::Float {
+                size,
+                big_endian: bits & 0x01 != 0,
+            }
+        }
+        3 => Datatype::String { size },
+        7 => Datatype::Reference,
+        9 => match bits & 0x0F {
+            1 => Datatype::VarString,
+            _ => Datatype::Sequence(Box::new(datatype(c)?)),
+        },
+        _ => Datatype::Other { class, size },
+    };
+    Ok(datatype)
+}
+
+/// Decode a data layout message (versions 3 and 4)
+fn layout(c: &mut Cursor) -> Result<Layout, HSE<String>> {
+    let version = c.u8()?;
+    if !(3..=4).contains(&version) {
+        let msg = format!("Data layout message version {}", version);
+        return Err(HSE::UnsupportedHDF5(msg));
+    }
+    let layout = match (c.u8()?, version) {
+        (0, _) => {
+            let size = c.u16()? as usize;
+            Layout::Compact(c.bytes(size)?.to_vec())
+        }
+        (1, _) => Layout::Contiguous {
+            address: c.u64()?,
+            size: c.u64()?,
+        },
+        (2, 3) => {
+            let rank = c.u8()? as usize;
+            let index = ChunkIndex::BTree(c.u64()?);
+            let mut chunk = (0..rank)
+                .map(|_| c.u32().map(|d| d as u64))
+                .collect::<Result<Vec<u64>, _>>()?;
+            chunk.pop(); // element size
+            Layout::Chunked { chunk, index }
+        }
+        (2, _) => {
+            let flags = c.u8()?;
+            let rank = c.u8()? as usize;
+            let width = c.u8()? as usize;
+            let mut chunk = (0..rank)
+                .map(|_| c.uint(width))
+                .collect::<Result<Vec<u64>, _>>()?;
+            chunk.pop();
+            let kind = c.u8()?;
+            // index specific parameters in front of the address
+            let skip = match kind {
+                // filtered single chunks store their size and filter mask
+                1 if flags & 0x02 != 0 => 12,
+                3 => 1,
+                4 => 5,
+                5 => 6,
+                _ => 0,
+            };
+            c.skip(skip)?;
+            Layout::Chunked {
+                chunk,
+                index: ChunkIndex::Other(kind, c.u64()?),
+            }
+        }
+        (class, _) => {
+            let msg = format!("Data layout class {}", class);
+            return Err(HSE::UnsupportedHDF5(msg));
+        }
+    };
+    Ok(layout)
+}
+
+/// Decode a filter pipeline message (versions 1 and 2)
+fn filters(c: &mut Cursor) -> Result<Vec<Filter>, HSE<String>> {
+    let version = c.u8()?;
+    let count = c.u8()?;
+    if version == 1 {
+        c.skip(6)?;
+    }
+    let mut filters = Vec::new();
+    for _ in 0..count {
+        let id = c.u16()?;
+        // version 2 leaves out the name length of predefined filters
+        let name_size = match version == 1 || id >= 256 {
+            true => c.u16()? as usize,
+            false => 0,
+        };
+        c.skip(2)?; // flags
+        let values = c.u16()? as usize;
+        match version {
+            1 => c.skip(name_size.div_ceil(8) * 8)?,
+            _ => c.skip(name_size)?,
+        }
+        let params = (0..values)
+            .map(|_| c.u32())
+            .collect::<Result<Vec<u32>, _>>()?;
+        if version == 1 && values % 2 == 1 {
+            c.skip(4)?;
+        }
+        filters.push(Filter { id, params });
+    }
+    Ok(filters)
+}
+
+/// Decode an attribute message
+pub(crate) fn attribute(c: &mut Cursor) -> Result<Attribute, HSE<String>> {
+    let version = c.u8()?;
+    c.skip(1)?;
+    let name_size = c.u16()? as usize;
+    let type_size = c.u16()? as usize;
+    let space_size = c.u16()? as usize;
+    if version == 3 {
+        c.skip(1)?; // name encoding
+    }
+    // version 1 pads every part to 8 bytes
+    let padded = |n: usize| if version == 1 { n.div_ceil(8) * 8 } else { n };
+    let name = c.bytes(padded(name_size))?;
+    let name = name[..name_size].split(|b| *b == 0).next().unwrap_or(&[]);
+    let name = String::from_utf8(name.to_vec()).map_err(|_| HSE::UTF8error)?;
+    let datatype = datatype(&mut Cursor::new(c.bytes(padded(type_size))?))?;
+    let dataspace = dataspace(&mut Cursor::new(c.bytes(padded(space_size))?))?;
+    let len = dataspace.bytes(datatype.size())?;
+    let data = c.bytes(len)?.to_vec();
+    Ok(Attribute {
+        name,
+        datatype,
+        dataspace,
+        data,
+    })
+}
+
+/// Decode a link message
+pub(crate) fn link(c: &mut Cursor) -> Result<Link, HSE<String>> {
+    c.skip(1)?;
+    let flags = c.u8()?;
+    let kind = match flags & 0x08 {
+        0 => 0,
+        _ => c.u8()?,
+    };
+    let order = match flags & 0x04 {
+        0 => None,
+        _ => Some(c.u64()?),
+    };
+    if flags & 0x10 != 0 {
+        c.skip(1)?; // character set
+    }
+    let len = c.uint(1 << (flags & 0x03))? as usize;
+    let name = String::from_utf8(c.bytes(len)?.to_vec()).map_err(|_| HSE::UTF8error)?;
+    let address = match kind {
+        0 => Some(c.u64()?),
+        _ => None,
+    };
+    Ok(Link {
+        name,
+        address,
+        order,
+    })
+}
@@ -0,0 +1,527 @@
+//! Raw values of netCDF-4 variables
+//!
+//! Values are stored contiguously, compact within the object header, or in
+//! chunks indexed by a version 1 B-tree. Chunks pass the filter pipeline on
+//! their way to the file; deflate, shuffle and fletcher32 can be undone.
+use super::objects::{ChunkIndex, Filter, Layout};
+use super::{heap, little_endian, read_at, swap_bytes, Cursor, Dataset, UNDEFINED};
+use crate::error::HeadScratcherError as HSE;
+use crate::selection::Selection;
+use flate2::read::ZlibDecoder;
+use std::io::{Read, Seek};
+
+const DEFLATE: u16 = 1;
+const SHUFFLE: u16 = 2;
+const FLETCHER32: u16 = 3;
+
+/// Storage of the values of a variable
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Storage {
+    layout: Layout,
+    filters: Vec<Filter>,
+    /// Current lengths of the dimensions
+    pub shape: Vec<usize>,
+    /// Size of one element in bytes
+    pub size: usize,
+    little_endian: bool,
+}
+
+/// Chunk of a chunked dataset
+struct Chunk {
+    /// Index of the first element per dimension
+    origin: Vec<usize>,
+    address: u64,
+    size: usize,
+    /// Filters skipped for this chunk, one bit per filter
+    mask: u32,
+}
+
+impl Storage {
+    pub fn new(d: &Dataset) -> Self {
+        Storage {
+            layout: d.layout.clone(),
+            filters: d.filters.clone(),
+            shape: d.dataspace.dims.iter().map(|l| *l as usize).collect(),
+            size: d.datatype.size(),
+            little_endian: little_endian(&d.datatype),
+        }
+    }
+
+    /// Read a selection into `buffer` as big endian values in selection order
+    ///
+    /// Elements of chunks never written hold `fill`, the big endian fill value.
+    pub fn read_selection<F: Read + Seek>(
+        &self,
+        file: &mut F,
+        selection: &Selection,
+        fill: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), HSE<String>> {
+        self.validate(selection)?;
+        self.check_buffer(selection.len()?, buffer)?;
+        if selection.is_empty() {
+            return Ok(());
+        }
+        let bounds: Vec<(usize, usize)> = (0..self.shape.len())
+            .map(|d| {
+                let (start, count) = (selection.start[d], selection.count[d]);
+                (start, start + (count - 1) * selection.stride[d] + 1)
+            })
+            .collect();
+        // position of an element in selection order, if it is selected
+        let target = |index: &[usize]| {
+            let mut position = 0;
+            for (d, i) in index.iter().enumerate() {
+                let offset = i - selection.start[d];
+                if !offset.is_multiple_of(selection.stride[d]) {
+                    return None;
+                }
+                position = position * selection.count[d] + offset / selection.stride[d];
+            }
+            Some(position)
+        };
+        self.gather(file, &bounds, target, fill, buffer)
+    }
+
+    /// Check a selection against the shape of the dataset
+    pub fn validate(&self, selection: &Selection) -> Result<(), HSE<String>> {
+        selection.validate(&self.shape)
+    }
+
+    /// Read consecutive elements, in file order, from `start` into `buffer`
+    pub fn read_range<F: Read + Seek>(
+        &self,
+        file: &mut F,
+        start: &[usize],
+        fill: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), HSE<String>> {
+        if !buffer.len().is_multiple_of(self.size) {
+            return Err(HSE::InvalidBufferLength(buffer.len(), self.size));
+        }
+        let n = buffer.len() / self.size;
+        let origin = vec![0; self.shape.len()];
+        if start.len() != self.shape.len() || start.iter().zip(&self.shape).any(|(s, l)| s >= l) {
+            let msg = format!("Start {:?} outside of shape {:?}", start, self.shape);
+            return Err(HSE::InvalidSelection(msg));
+        }
+        let first = flat(start, &origin, &self.shape);
+        if first + n > self.shape.iter().product() {
+            let msg = format!(
+                "{} elements from {:?} exceed shape {:?}",
+                n, start, self.shape
+            );
+            return Err(HSE::InvalidSelection(msg));
+        }
+        if n == 0 {
+            return Ok(());
+        }
+        // rows of the leading dimension touched by the range
+        let row: usize = self.shape.iter().skip(1).product();
+        let bounds: Vec<(usize, usize)> = self
+            .shape
+            .iter()
+            .enumerate()
+            .map(|(d, l)| match d {
+                0 => (first / row, (first + n - 1) / row + 1),
+                _ => (0, *l),
+            })
+            .collect();
+        let target = |index: &[usize]| {
+            let i = flat(index, &origin, &self.shape);
+            (first..first + n).contains(&i).then(|| i - first)
+        };
+        self.gather(file, &bounds, target, fill, buffer)
+    }
+
+    fn check_buffer(&self, len: usize, buffer: &[u8]) -> Result<(), HSE<String>> {
+        if buffer.len() != len * self.size {
+            let msg = format!(
+                "Buffer holds {} bytes, selection {}",
+                buffer.len(),
+                len * self.size
+            );
+            return Err(HSE::InvalidSelection(msg));
+        }
+        Ok(())
+    }
+
+    /// Copy the elements within `bounds` to their `target` position in `buffer`
+    fn gather<F, T>(
+        &self,
+        file: &mut F,
+        bounds: &[(usize, usize)],
+        target: T,
+        fill: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), HSE<String>>
+    where
+        F: Read + Seek,
+        T: Fn(&[usize]) -> Option<usize>,
+    {
+        let (size, rank) = (self.size, self.shape.len());
+        // fill in file byte order, everything is swapped at the end
+        let mut fill = fill.to_vec();
+        if self.little_endian {
+            swap_bytes(&mut fill, size);
+        }
+        for element in buffer.chunks_exact_mut(size) {
+            element.copy_from_slice(&fill);
+        }
+        match &self.layout {
+            Layout::Compact(data) => {
+                let origin = vec![0; rank];
+                self.copy(data, &origin, &self.shape, bounds, &target, buffer)?;
+            }
+            Layout::Contiguous { address, .. } if *address == UNDEFINED => (),
+            Layout::Contiguous { address, .. } => {
+                // read row by row along the last dimension
+                let (lo, hi) = bounds.last().copied().unwrap_or((0, 1));
+                let mut rows = bounds.to_vec();
+                let mut dims = vec![1; rank];
+                if rank > 0 {
+                    rows[rank - 1] = (lo, lo + 1);
+                    dims[rank - 1] = hi - lo;
+                }
+                let zeros = vec![0; rank];
+                for_each(&rows, |index| {
+                    let offset = flat(index, &zeros, &self.shape) * size;
+                    let data = read_at(file, address + offset as u64, (hi - lo) * size)?;
+                    self.copy(&data, index, &dims, bounds, &target, buffer)
+                })?;
+            }
+            Layout::Chunked {
+                chunk,
+                index: ChunkIndex::BTree(address),
+            } => {
+                let dims: Vec<usize> = chunk.iter().map(|l| *l as usize).collect();
+                let len = dims
+                    .iter()
+                    .try_fold(size, |n, l| n.checked_mul(*l))
+                    .ok_or(HSE::InvalidFile)?;
+                for c in self.chunks(file, *address)? {
+                    let overlaps = c
+                        .origin
+                        .iter()
+                        .zip(&dims)
+                        .zip(bounds)
+                        .all(|((o, l), (lo, hi))| o < hi && o + l > *lo);
+                    if overlaps {
+                        let data = read_at(file, c.address, c.size)?;
+                        let data = self.decode(data, c.mask, len)?;
+                        self.copy(&data, &c.origin, &dims, bounds, &target, buffer)?;
+                    }
+                }
+            }
+            Layout::Chunked {
+                index: ChunkIndex::Other(kind, _),
+                ..
+            } => {
+                let msg = format!("Chunk index type {}", kind);
+                return Err(HSE::UnsupportedHDF5(msg));
+            }
+        }
+        if self.little_endian {
+            swap_bytes(buffer, size);
+        }
+        Ok(())
+    }
+
+    /// Copy the elements of a block of `dims` at `origin` lying within `bounds`
+    fn copy<T: Fn(&[usize]) -> Option<usize>>(
+        &self,
+        data: &[u8],
+        origin: &[usize],
+        dims: &[usize],
+        bounds: &[(usize, usize)],
+        target: &T,
+        buffer: &mut [u8],
+    ) -> Result<(), HSE<String>> {
+        let size = self.size;
+        let block: Vec<(usize, usize)> = origin
+            .iter()
+            .zip(dims)
+            .zip(bounds)
+            .map(|((o, l), (lo, hi))| ((*o).max(*lo), (o + l).min(*hi)))
+            .collect();
+        for_each(&block, |index| {
+            if let Some(position) = target(index) {
+                let from = flat(index, origin, dims) * size;
+                let element = data.get(from..from + size).ok_or(HSE::InvalidFile)?;
+                buffer[position * size..(position + 1) * size].copy_from_slice(element);
+            }
+            Ok(())
+        })
+    }
+
+    /// All chunks of the B-tree at `address`
+    fn chunks<F: Read + Seek>(
+        &self,
+        file: &mut F,
+        address: u64,
+    ) -> Result<Vec<Chunk>, HSE<String>> {
+        if address == UNDEFINED {
+            return Ok(Vec::new());
+        }
+        let rank = self.shape.len();
+        // chunk size, filter mask and offsets, the last one of the element size
+        let key_size = 8 + 8 * (rank + 1);
+        heap::btree_v1_entries(file, address, 1, key_size)?
+            .into_iter()
+            .map(|(key, address)| {
+                let mut c = Cursor::new(&key);
+                let size = c.u32()? as usize;
+                let mask = c.u32()?;
+                let origin = (0..rank)
+                    .map(|_| c.u64().map(|o| o as usize))
+                    .collect::<Result<Vec<usize>, _>>()?;
+                Ok(Chunk {
+                    origin,
+                    address,
+                    size,
+                    mask,
+                })
+            })
+            .collect()
+    }
+
+    /// Undo the filters of a chunk of `len` bytes, except the ones set in `mask`
+    fn decode(&self, mut data: Vec<u8>, mask: u32, len: usize) -> Result<Vec<u8>, HSE<String>> {
+        for (i, filter) in self.filters.iter().enumerate().rev() {
+            if mask & (1 << i) != 0 {
+                continue;
+            }
+            data = match filter.id {
+                DEFLATE => {
+                    // never inflate beyond the chunk, one more byte tells it is too long
+                    let mut out = Vec::new();
+                    ZlibDecoder::new(&data[..])
+                        .take(len as u64 + 1)
+                        .read_to_end(&mut out)?;
+                    if out.len() != len {
+                        return Err(HSE::InvalidFile);
+                    }
+                    out
+                }
+                SHUFFLE => {
+                    let size = filter.params.first().map(|s| *s as usize);
+                    unshuffle(&data, size.unwrap_or(self.size))
+                }
+                FLETCHER32 => {
+                    data.truncate(data.len().saturating_sub(4)); // checksum
+                    data
+                }
+                id => return Err(HSE::UnsupportedHDF5(format!("Filter {}", id))),
+            };
+        }
+        Ok(data)
+    }
+}
+
+/// Undo the shuffle filter, which stores byte `b` of all elements together
+fn unshuffle(data: &[u8], size: usize) -> Vec<u8> {
+    let count = data.len() / size.max(1);
+    let mut out = data.to_vec();
+    if size <= 1 || count == 0 {
+        return out;
+    }
+    for (b, bytes) in data.chunks_exact(count).take(size).enumerate() {
+        for (e, byte) in bytes.iter().enumerate() {
+            out[e * size + b] = *byte;
+        }
+    }
+    out
+}
+
+/// Row-major position of `index` in a block of `dims` starting at `origin`
+fn flat(index: &[usize], origin: &[usize], dims: &[usize]) -> usize {
+    index
+        .iter()
+        .zip(origin)
+        .zip(dims)
+        .fold(0, |acc, ((i, o), l)| acc * l + (i - o))
+}
+
+/// Call `f` with every index of the box `bounds`, in row-major order
+fn for_each<G>(bounds: &[(usize, usize)], mut f: G) -> Result<(), HSE<String>>
+where
+    G: FnMut(&[usize]) -> Result<(), HSE<String>>,
+{
+    if bounds.iter().any(|(lo, hi)| lo >= hi) {
+        return Ok(());
+    }
+    let mut index: Vec<usize> = bounds.iter().map(|(lo, _)| *lo).collect();
+    loop {
+        f(&index)?;
+        let mut d = index.len();
+        loop {
+            if d == 0 {
+                return Ok(());
+            }
+            d -= 1;
+            index[d] += 1;
+            if index[d] < bounds[d].1 {
+                break;
+            }
+            index[d] = bounds[d].0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
+
+    const SHAPE: [usize; 2] = [5, 6];
+    const CHUNK: [usize; 2] = [2, 4];
+
+    fn shuffle(data: &[u8], size: usize) -> Vec<u8> {
+        let count = data.len() / size;
+        (0..size)
+            .flat_map(|b| (0..count).map(move |e| data[e * size + b]))
+            .collect()
+    }
+
+    /// In memory file holding a version 1 B-tree of shuffled and deflated
+    /// float chunks with values `10 * row + column`
+    ///
+    /// The chunk at (4, 4) is never written, the one at (2, 4) skips deflate.
+    fn chunked_file() -> std::io::Cursor<Vec<u8>> {
+        let origins = [(0, 0), (0, 4), (2, 0), (2, 4), (4, 0)];
+        let mut chunks = Vec::new();
+        for (r0, c0) in origins.iter() {
+            let mut data = Vec::new();
+            for r in *r0..r0 + CHUNK[0] {
+                for c in *c0..c0 + CHUNK[1] {
+                    let value = if r < SHAPE[0] && c < SHAPE[1] {
+                        10 * r + c
+                    } else {
+                        0
+                    };
+                    data.extend_from_slice(&(value as f32).to_le_bytes());
+                }
+            }
+            let data = shuffle(&data, 4);
+            let (data, mask) = match (r0, c0) {
+                (2, 4) => (data, 0b10u32),
+                _ => {
+                    let mut e = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                    e.write_all(&data).unwrap();
+                    (e.finish().unwrap(), 0)
+                }
+            };
+            chunks.push((r0, c0, mask, data));
+        }
+
+        let key_size = 8 + 8 * 3;
+        let mut address = 24 + chunks.len() * (key_size + 8) + key_size;
+        let mut file = b"TREE\x01\x00".to_vec();
+        file.extend_from_slice(&(chunks.len() as u16).to_le_bytes());
+        file.extend_from_slice(&[0xFF; 16]); // siblings
+        for (r0, c0, mask, data) in chunks.iter() {
+            file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            file.extend_from_slice(&mask.to_le_bytes());
+            for offset in [**r0 as u64, **c0 as u64, 0] {
+                file.extend_from_slice(&offset.to_le_bytes());
+            }
+            file.extend_from_slice(&(address as u64).to_le_bytes());
+            address += data.len();
+        }
+        file.extend_from_slice(&[0; 8 + 8 * 3]); // final key
+        for (_, _, _, data) in chunks.iter() {
+            file.extend_from_slice(data);
+        }
+        std::io::Cursor::new(file)
+    }
+
+    fn chunked_storage() -> Storage {
+        Storage {
+            layout: Layout::Chunked {
+                chunk: CHUNK.iter().map(|l| *l as u64).collect(),
+                index: ChunkIndex::BTree(0),
+            },
+            filters: vec![
+                Filter {
+                    id: SHUFFLE,
+                    params: vec![4],
+                },
+                Filter {
+                    id: DEFLATE,
+                    params: vec![6],
+                },
+            ],
+            shape: SHAPE.to_vec(),
+            size: 4,
+            little_endian: true,
+        }
+    }
+
+    fn floats(buffer: &[u8]) -> Vec<f32> {
+        buffer
+            .chunks_exact(4)
+            .map(|b| f32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn test_chunked() {
+        let (mut file, storage) = (chunked_file(), chunked_storage());
+        let fill = (-1f32).to_be_bytes();
+        let expected = |r: usize, c: usize| match (r, c) {
+            (4, 4..) => -1.,
+            _ => (10 * r + c) as f32,
+        };
+
+        let selection = Selection::all(&SHAPE);
+        let mut buffer = vec![0; 30 * 4];
+        storage
+            .read_selection(&mut file, &selection, &fill, &mut buffer)
+            .unwrap();
+        let all: Vec<f32> = (0..30).map(|i| expected(i / 6, i % 6)).collect();
+        assert_eq!(floats(&buffer), all);
+
+        let selection = Selection::new(vec![1, 1], vec![2, 3]).with_stride(vec![3, 2]);
+        let mut buffer = vec![0; 6 * 4];
+        storage
+            .read_selection(&mut file, &selection, &fill, &mut buffer)
+            .unwrap();
+        assert_eq!(floats(&buffer), vec![11., 13., 15., 41., 43., -1.]);
+
+        let mut buffer = vec![0; 10 * 4];
+        storage
+            .read_range(&mut file, &[1, 3], &fill, &mut buffer)
+            .unwrap();
+        assert_eq!(floats(&buffer), all[9..19].to_vec());
+
+        let selection = Selection::new(vec![4, 0], vec![2, 1]);
+        let e = storage.read_selection(&mut file, &selection, &fill, &mut buffer);
+        assert!(matches!(e, Err(HSE::InvalidSelection(_))));
+        let mut buffer = vec![0; 13 * 4];
+        let e = storage.read_range(&mut file, &[3, 0], &fill, &mut buffer);
+        assert!(matches!(e, Err(HSE::InvalidSelection(_))));
+    }
+
+    #[test]
+    fn test_inflate_bound() {
+        let mut file = chunked_file();
+        let mut storage = chunked_storage();
+        // deflated chunks hold more than a chunk of this shape
+        storage.layout = Layout::Chunked {
+            chunk: vec![1, 4],
+            index: ChunkIndex::BTree(0),
+        };
+        let selection = Selection::new(vec![0, 0], vec![1, 1]);
+        let mut buffer = vec![0; 4];
+        let e = storage.read_selection(&mut file, &selection, &[0; 4], &mut buffer);
+        assert_eq!(e, Err(HSE::InvalidFile));
+    }
+
+    #[test]
+    fn test_unshuffle() {
+        let data: Vec<u8> = (0..12).collect();
+        assert_eq!(unshuffle(&shuffle(&data, 4), 4), data);
+        assert_eq!(unshuffle(&data, 1), data);
+    }
+}
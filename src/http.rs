@@ -0,0 +1,235 @@
+//! Files on HTTP servers
+//!
+//! [`HttpFile`] reads a remote file with `Range:` requests, see [`Remote`] for
+//! how reads are turned into requests. Available with the `http` feature.
+use crate::error::HeadScratcherError as HSE;
+use crate::source::{RangeRequest, Remote};
+use crate::NetCDF;
+use std::io::{self, Read};
+
+/// File on an HTTP server that answers range requests
+#[derive(Debug)]
+pub struct Http {
+    agent: ureq::Agent,
+    url: String,
+}
+
+impl Http {
+    pub fn new(url: &str) -> Self {
+        Http {
+            agent: ureq::Agent::new(),
+            url: url.to_string(),
+        }
+    }
+}
+
+impl RangeRequest for Http {
+    fn size(&mut self) -> io::Result<u64> {
+        size(&self.agent, &self.url, &[])
+    }
+
+    fn request(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        get_range(&self.agent, &self.url, offset, len, &[])
+    }
+}
+
+/// Remote file read with HTTP range requests
+pub type HttpFile = Remote<Http>;
+
+impl Remote<Http> {
+    /// Open the file at `url`, asking the server for its size
+    pub fn open(url: &str) -> Result<Self, HSE<String>> {
+        Ok(Remote::new(Http::new(url))?)
+    }
+}
+
+impl NetCDF<HttpFile> {
+    /// Open a remote file, fetching its header with range requests
+    pub fn from_url(url: &str) -> Result<Self, HSE<String>> {
+        NetCDF::new_from_file(HttpFile::open(url)?)
+    }
+}
+
+/// Size of the file at `url`, taken from the range of its first byte
+pub(crate) fn size(
+    agent: &ureq::Agent,
+    url: &str,
+    headers: &[(String, String)],
+) -> io::Result<u64> {
+    let response = get(agent, url, "bytes=0-0", headers)?;
+    // Content-Range: bytes 0-0/<size>
+    let size = response
+        .header("Content-Range")
+        .and_then(|r| r.rsplit_once('/'))
+        .map(|(_, size)| size.parse());
+    match size {
+        Some(Ok(size)) => Ok(size),
+        _ => Err(io::Error::other(format!("No size of {} in response", url))),
+    }
+}
+
+/// `len` bytes from `offset` on of the file at `url`
+pub(crate) fn get_range(
+    agent: &ureq::Agent,
+    url: &str,
+    offset: u64,
+    len: usize,
+    headers: &[(String, String)],
+) -> io::Result<Vec<u8>> {
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    let range = format!("bytes={}-{}", offset, offset + len as u64 - 1);
+    let mut bytes = Vec::with_capacity(len);
+    get(agent, url, &range, headers)?
+        .into_reader()
+        .take(len as u64)
+        .read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Send a range request, failing unless the server answers with the range
+fn get(
+    agent: &ureq::Agent,
+    url: &str,
+    range: &str,
+    headers: &[(String, String)],
+) -> io::Result<ureq::Response> {
+    let mut request = agent.get(url).set("Range", range);
+    for (name, value) in headers {
+        request = request.set(name, value);
+    }
+    let response = match request.call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(404, _)) => return Err(io::ErrorKind::NotFound.into()),
+        Err(e) => return Err(io::Error::other(e)),
+    };
+    match response.status() {
+        206 => Ok(response),
+        _ => Err(io::Error::other(format!("{} ignored range {}", url, range))),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::selection::Selection;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Serve the files below `root` with range requests on a local port
+    ///
+    /// Returns the URL of the server and a counter of the requests.
+    pub(crate) fn serve(root: &str) -> (String, Arc<AtomicUsize>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let root = root.to_string();
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let bytes = match std::fs::read(format!("{}{}", root, request.url())) {
+                    Ok(bytes) => bytes,
+                    Err(_) => {
+                        let response = tiny_http::Response::empty(404);
+                        request.respond(response).unwrap();
+                        continue;
+                    }
+                };
+                let range = request
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv("Range"))
+                    .map(|h| h.value.as_str().to_string());
+                let response = match range {
+                    Some(range) => {
+                        let range = range.trim_start_matches("bytes=");
+                        let (start, end) = range.split_once('-').unwrap();
+                        let (start, end): (usize, usize) =
+                            (start.parse().unwrap(), end.parse().unwrap());
+                        let total = format!("bytes {}-{}/{}", start, end, bytes.len());
+                        let header = tiny_http::Header::from_bytes("Content-Range", total);
+                        tiny_http::Response::from_data(bytes[start..=end].to_vec())
+                            .with_status_code(206)
+                            .with_header(header.unwrap())
+                    }
+                    None => tiny_http::Response::from_data(bytes),
+                };
+                request.respond(response).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    #[test]
+    fn test_http_read() {
+        let (url, requests) = serve("assets");
+        let filename = "assets/sresa1b_ncar_ccsm3-example.nc".to_string();
+        let mut local = NetCDF::new(filename.clone()).unwrap();
+        let mut remote =
+            NetCDF::from_url(&format!("{}/sresa1b_ncar_ccsm3-example.nc", url)).unwrap();
+        assert_eq!(remote.header(), local.header());
+        // size and a single block for the header
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(remote.file.requests(), 2);
+
+        // every 4th longitude of three rows, one request per value or per row
+        let selection = Selection::new(vec![0, 10, 3], vec![1, 3, 20]).with_stride(vec![1, 1, 4]);
+        let expected: Vec<f32> = local.read_selection("tas".to_string(), &selection).unwrap();
+        remote.file = remote.file.with_gap(0);
+        let tas: Vec<f32> = remote
+            .read_selection("tas".to_string(), &selection)
+            .unwrap();
+        assert_eq!(tas, expected);
+        assert_eq!(remote.file.requests(), 2 + 60);
+        remote.file = remote.file.with_gap(64);
+        let tas: Vec<f32> = remote
+            .read_selection("tas".to_string(), &selection)
+            .unwrap();
+        assert_eq!(tas, expected);
+        assert_eq!(remote.file.requests(), 2 + 60 + 3);
+        assert_eq!(requests.load(Ordering::SeqCst), remote.file.requests());
+
+        // prefetched selections are read from memory
+        remote.prefetch("tas".to_string(), &selection).unwrap();
+        assert_eq!(remote.file.requests(), 2 + 60 + 3 + 3);
+        let tas: Vec<f32> = remote
+            .read_selection("tas".to_string(), &selection)
+            .unwrap();
+        assert_eq!(tas, expected);
+        assert_eq!(remote.file.requests(), 2 + 60 + 3 + 3);
+        assert_eq!(
+            remote.file.len(),
+            std::fs::metadata(&filename).unwrap().len()
+        );
+
+        // large reads are fetched directly
+        let ua: Vec<f32> = remote
+            .read_slab("ua".to_string(), &[0, 3, 0, 0], &[1, 1, 128, 256])
+            .unwrap();
+        let expected: Vec<f32> = local
+            .read_slab("ua".to_string(), &[0, 3, 0, 0], &[1, 1, 128, 256])
+            .unwrap();
+        assert_eq!(ua, expected);
+        assert_eq!(remote.file.requests(), 2 + 60 + 3 + 3 + 1);
+    }
+
+    #[test]
+    fn test_http_netcdf4() {
+        let (url, _) = serve("assets");
+        let classic = "assets/sresa1b_ncar_ccsm3-example.nc".to_string();
+        let mut classic = NetCDF::new(classic).unwrap();
+        let mut remote =
+            NetCDF::from_url(&format!("{}/sresa1b_ncar_ccsm3-example.4.nc", url)).unwrap();
+        let selection = Selection::new(vec![0, 2, 5, 7], vec![1, 3, 4, 5]);
+        let expected: Vec<f32> = classic
+            .read_selection("ua".to_string(), &selection)
+            .unwrap();
+        remote.prefetch("ua".to_string(), &selection).unwrap();
+        let ua: Vec<f32> = remote.read_selection("ua".to_string(), &selection).unwrap();
+        assert_eq!(ua, expected);
+        let e = NetCDF::from_url(&format!("{}/missing.nc", url));
+        assert!(matches!(e, Err(HSE::IOError(std::io::ErrorKind::NotFound))));
+    }
+}
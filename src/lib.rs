@@ -1,26 +1,67 @@
 //! Netcdf Head Scratcher - Library for stream parsing netcdf files
+use cf::{Mask, Packing};
+use data::NetCDFData;
 use error::HeadScratcherError;
+use parser::components::{NetCDFVariable, NetCDFVersion, NumberOfRecords};
 use parser::NetCDFHeader;
+use selection::Selection;
+use source::{ByteRangeSource, Reader};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom, Write};
 use utils::calc_seek;
 
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod builder;
+pub mod cache;
+pub mod cf;
 #[rustfmt::skip]
 pub mod constants_and_types;
+pub mod coords;
+pub mod data;
 pub mod error;
+pub mod group;
+pub mod hdf5;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub mod object;
 pub mod parser;
+pub mod planner;
+pub mod selection;
+#[cfg(any(unix, windows))]
+pub mod shared;
+pub mod source;
 mod utils;
+pub mod writer;
 
+/// netCDF file, classic or netCDF-4
+///
+/// netCDF-4 files expose the variables of their root group; see
+/// [`group::NetCDFGroup`] for the others. The file is read from any
+/// [`ByteRangeSource`], local or remote.
 #[derive(Debug, PartialEq)]
-pub struct NetCDF<F: Seek + Read> {
+pub struct NetCDF<F: ByteRangeSource> {
     file: F,
     header: NetCDFHeader,
+    /// Storage of netCDF-4 variables, empty for classic files
+    storage: HashMap<String, hdf5::Storage>,
 }
 
-impl<F: Seek + Read> NetCDF<F> {
+impl<F: ByteRangeSource> NetCDF<F> {
     pub fn new_from_file(mut file: F) -> Result<Self, HeadScratcherError<String>> {
-        let h = NetCDFHeader::from_file(&mut file)?;
-        Ok(NetCDF { file, header: h })
+        let mut reader = Reader::new(&mut file)?;
+        let (header, storage) = match parser::file_format(&mut reader)? {
+            NetCDFVersion::NetCDF4 => hdf5::open(&mut reader)?,
+            _ => (NetCDFHeader::from_file(&mut reader)?, HashMap::new()),
+        };
+        Ok(NetCDF {
+            file,
+            header,
+            storage,
+        })
     }
 
     pub fn update_buffer(
@@ -29,28 +70,276 @@ impl<F: Seek + Read> NetCDF<F> {
         start: &[usize],
         buffer: &mut [u8],
     ) -> Result<(), HeadScratcherError<String>> {
-        let seek_pos = match (&self.header.vars, &self.header.seeks) {
-            (Some(v), Some(s)) => calc_seek(v, s, variable.clone(), start),
-            (_, _) => return Err(HeadScratcherError::NoVariablesInFile),
+        read_range(
+            &mut self.file,
+            &self.header,
+            &self.storage,
+            variable,
+            start,
+            buffer,
+        )
+    }
+
+    /// Read the slab `start..start + count` of a variable as native values
+    ///
+    /// The requested type `T` has to match the external type of the variable.
+    pub fn read_slab<T: NetCDFData>(
+        &mut self,
+        variable: String,
+        start: &[usize],
+        count: &[usize],
+    ) -> Result<Vec<T>, HeadScratcherError<String>> {
+        let selection = Selection::new(start.to_vec(), count.to_vec());
+        self.read_selection(variable, &selection)
+    }
+
+    /// Read a (strided) selection of a variable as native values
+    pub fn read_selection<T: NetCDFData>(
+        &mut self,
+        variable: String,
+        selection: &Selection,
+    ) -> Result<Vec<T>, HeadScratcherError<String>> {
+        self.check_type::<T>(&variable)?;
+        let mut buffer = self.selection_buffer(&variable, selection, T::NC_TYPE.extsize())?;
+        self.update_buffer_selection(variable, selection, &mut buffer)?;
+        Ok(T::decode(&buffer))
+    }
+
+    /// Read the slab `start..start + count` of a packed variable, unpacked
+    ///
+    /// See [`NetCDF::read_unpacked_selection`].
+    pub fn read_unpacked<T: NetCDFData>(
+        &mut self,
+        variable: String,
+        start: &[usize],
+        count: &[usize],
+    ) -> Result<Vec<T>, HeadScratcherError<String>> {
+        let selection = Selection::new(start.to_vec(), count.to_vec());
+        self.read_unpacked_selection(variable, &selection)
+    }
+
+    /// Read a selection of a variable, unpacked with its `scale_factor` and
+    /// `add_offset` attributes
+    ///
+    /// `T` has to be the unpacked type, i.e. the type of the packing attributes.
+    /// Packed values that are missing (see [`NetCDF::read_masked_selection`])
+    /// are not unpacked but returned as NaN.
+    /// Variables without packing attributes are read like [`NetCDF::read_selection`].
+    pub fn read_unpacked_selection<T: NetCDFData>(
+        &mut self,
+        variable: String,
+        selection: &Selection,
+    ) -> Result<Vec<T>, HeadScratcherError<String>> {
+        let var = self.variable(&variable)?;
+        let nc_type = var.nc_type;
+        let mask = Mask::from_variable(var);
+        let packing = match Packing::from_variable(var)? {
+            Some(p) => p,
+            None => return self.read_selection(variable, selection),
+        };
+        if packing.unpacked_type != T::NC_TYPE {
+            let msg = format!(
+                "{} unpacks to {:?}, not {:?}",
+                variable,
+                packing.unpacked_type,
+                T::NC_TYPE
+            );
+            return Err(HeadScratcherError::TypeMismatch(msg));
+        }
+        let mut buffer = self.selection_buffer(&variable, selection, nc_type.extsize())?;
+        self.update_buffer_selection(variable.clone(), selection, &mut buffer)?;
+        match data::decode_f64(&nc_type, &buffer) {
+            Some(packed) => Ok(packing.unpack_masked(&packed, &mask)),
+            None => {
+                let msg = format!("{} of type {:?} cannot be unpacked", variable, nc_type);
+                Err(HeadScratcherError::TypeMismatch(msg))
+            }
+        }
+    }
+
+    /// Read the slab `start..start + count` of a variable, missing values as `None`
+    ///
+    /// See [`NetCDF::read_masked_selection`].
+    pub fn read_masked<T: NetCDFData>(
+        &mut self,
+        variable: String,
+        start: &[usize],
+        count: &[usize],
+    ) -> Result<Vec<Option<T>>, HeadScratcherError<String>> {
+        let selection = Selection::new(start.to_vec(), count.to_vec());
+        self.read_masked_selection(variable, &selection)
+    }
+
+    /// Read a selection of a variable, missing values as `None`
+    ///
+    /// Values equal to `_FillValue` (or the default fill value of the type) or
+    /// `missing_value`, and values outside the valid range are missing.
+    pub fn read_masked_selection<T: NetCDFData>(
+        &mut self,
+        variable: String,
+        selection: &Selection,
+    ) -> Result<Vec<Option<T>>, HeadScratcherError<String>> {
+        let mask = Mask::from_variable(self.variable(&variable)?);
+        let values = self.read_selection(variable, selection)?;
+        Ok(mask.apply(values))
+    }
+
+    /// Values of a variable at fixed indices over all time steps
+    ///
+    /// `fixed` holds the indices of all other dimensions, in order. Without
+    /// `time_dim` the time dimension is the first one named in
+    /// [`constants_and_types::TIME_CANDIDATES`].
+    pub fn time_series<T: NetCDFData>(
+        &mut self,
+        variable: String,
+        fixed: &[usize],
+        time_dim: Option<&str>,
+    ) -> Result<Vec<T>, HeadScratcherError<String>> {
+        let selection = self.time_selection(&variable, fixed, time_dim)?;
+        self.read_selection(variable, &selection)
+    }
+
+    /// Values of a variable over all time steps, missing values as `None`
+    ///
+    /// See [`NetCDF::time_series`] and [`NetCDF::read_masked_selection`].
+    pub fn time_series_masked<T: NetCDFData>(
+        &mut self,
+        variable: String,
+        fixed: &[usize],
+        time_dim: Option<&str>,
+    ) -> Result<Vec<Option<T>>, HeadScratcherError<String>> {
+        let selection = self.time_selection(&variable, fixed, time_dim)?;
+        self.read_masked_selection(variable, &selection)
+    }
+
+    /// Selection of all time steps at fixed indices of the other dimensions
+    fn time_selection(
+        &self,
+        variable: &str,
+        fixed: &[usize],
+        time_dim: Option<&str>,
+    ) -> Result<Selection, HeadScratcherError<String>> {
+        let shape = self.header.variable_shape(variable)?;
+        let time = match time_dim {
+            Some(name) => shape.iter().position(|(n, _)| n == name),
+            None => shape
+                .iter()
+                .position(|(n, _)| constants_and_types::TIME_CANDIDATES.contains(&n.as_str())),
         };
-        let pos = match seek_pos {
-            Some(k) => k,
-            None => return Err(HeadScratcherError::VariableNotFound(variable)),
+        let time = match time {
+            Some(t) => t,
+            None => {
+                let msg = match time_dim {
+                    Some(name) => format!("{} of {}", name, variable),
+                    None => format!(
+                        "Candidate space: {:?}",
+                        constants_and_types::TIME_CANDIDATES
+                    ),
+                };
+                return Err(HeadScratcherError::CouldNotFindDimension(msg));
+            }
         };
-        self.file.seek(SeekFrom::Start(pos))?;
-        let ok = self.file.read_exact(buffer)?;
-        Ok(ok)
+        if fixed.len() + 1 != shape.len() {
+            let msg = format!(
+                "{} fixed indices for {} with {} dimensions",
+                fixed.len(),
+                variable,
+                shape.len()
+            );
+            return Err(HeadScratcherError::InvalidSelection(msg));
+        }
+        let length = shape[time].1;
+        let mut start = fixed.to_vec();
+        start.insert(time, 0);
+        let mut count = vec![1; shape.len()];
+        count[time] = length;
+        Ok(Selection::new(start, count))
+    }
+
+    /// Fill `buffer` with the raw bytes of a selection, in selection order
+    pub fn update_buffer_selection(
+        &mut self,
+        variable: String,
+        selection: &Selection,
+        buffer: &mut [u8],
+    ) -> Result<(), HeadScratcherError<String>> {
+        read_runs(
+            &mut self.file,
+            &self.header,
+            &self.storage,
+            variable,
+            selection,
+            buffer,
+        )
+    }
+
+    /// Read the raw bytes of a batch of slabs with merged reads
+    ///
+    /// Byte ranges at most `gap` bytes apart are read together, see
+    /// [`planner::ReadPlan`]. Slabs of netCDF-4 variables are read one by one.
+    pub fn read_batch(
+        &mut self,
+        requests: &[planner::SlabRequest],
+        gap: u64,
+    ) -> Result<Vec<Vec<u8>>, HeadScratcherError<String>> {
+        if self.storage.is_empty() {
+            let plan = planner::ReadPlan::new(&self.header, requests, gap)?;
+            return plan.execute(&mut self.file);
+        }
+        requests
+            .iter()
+            .map(|(variable, start, count)| {
+                let selection = Selection::new(start.clone(), count.clone());
+                let size = self.variable(variable)?.nc_type.extsize();
+                let mut buffer = self.selection_buffer(variable, &selection, size)?;
+                self.update_buffer_selection(variable.clone(), &selection, &mut buffer)?;
+                Ok(buffer)
+            })
+            .collect()
+    }
+
+    /// Zeroed buffer for a selection of values of `size` bytes, once the
+    /// selection is checked against the variable
+    fn selection_buffer(
+        &self,
+        variable: &str,
+        selection: &Selection,
+        size: usize,
+    ) -> Result<Vec<u8>, HeadScratcherError<String>> {
+        selection_buffer(&self.header, &self.storage, variable, selection, size)
+    }
+
+    /// Check that values of type `T` can be read from or written to a variable
+    fn check_type<T: NetCDFData>(&self, variable: &str) -> Result<(), HeadScratcherError<String>> {
+        check_type::<T>(&self.header, variable)
+    }
+
+    /// Check that the file format can be written to
+    fn check_writable(&self) -> Result<(), HeadScratcherError<String>> {
+        match self.header.version {
+            NetCDFVersion::NetCDF4 => Err(HeadScratcherError::NetCDF4NotSupported),
+            _ => Ok(()),
+        }
+    }
+
+    fn variable(&self, variable: &str) -> Result<&NetCDFVariable, HeadScratcherError<String>> {
+        self.header.variable(variable)
     }
 
     pub fn header(&self) -> &NetCDFHeader {
         &self.header
     }
 
+    /// Source the file is read from
+    pub fn file(&self) -> &F {
+        &self.file
+    }
+
     pub fn mapsize(&self) -> Result<usize, HeadScratcherError<String>> {
         match &self.header.dims {
             Some(dims) => {
                 let ncells = crate::utils::get_coordinate_dim_id(
-                    &dims,
+                    &self.header,
                     crate::constants_and_types::NCELLS_CANDIDATES,
                 );
 
@@ -59,69 +348,268 @@ impl<F: Seek + Read> NetCDF<F> {
                 }
 
                 let lon = crate::utils::get_coordinate_dim_id(
-                    &dims,
+                    &self.header,
                     crate::constants_and_types::LONGITUDE_CANDIDATES,
                 );
                 let lat = crate::utils::get_coordinate_dim_id(
-                    &dims,
+                    &self.header,
                     crate::constants_and_types::LATITUDE_CANDIDATES,
                 );
                 match (lon, lat) {
                     (Ok(lon_id), Ok(lat_id)) => {
-                        return Ok(
-                            dims.get(&lon_id).unwrap().length * dims.get(&lat_id).unwrap().length
-                        )
+                        Ok(dims.get(&lon_id).unwrap().length * dims.get(&lat_id).unwrap().length)
                     }
-                    (Ok(_), Err(_)) => {
-                        return Err(HeadScratcherError::CouldNotFindDimension(
-                            "Latitude".to_string(),
-                        ))
-                    }
-                    (Err(_), Ok(_)) => {
-                        return Err(HeadScratcherError::CouldNotFindDimension(
-                            "Longitude".to_string(),
-                        ))
-                    }
-                    (Err(_), Err(_)) => {
-                        return Err(HeadScratcherError::CouldNotFindDimension(
-                            "Longitude and Latitude".to_string(),
-                        ))
-                    }
-                };
+                    (Ok(_), Err(_)) => Err(HeadScratcherError::CouldNotFindDimension(
+                        "Latitude".to_string(),
+                    )),
+                    (Err(_), Ok(_)) => Err(HeadScratcherError::CouldNotFindDimension(
+                        "Longitude".to_string(),
+                    )),
+                    (Err(_), Err(_)) => Err(HeadScratcherError::CouldNotFindDimension(
+                        "Longitude and Latitude".to_string(),
+                    )),
+                }
             }
             _ => Err(HeadScratcherError::NoDimensionsInFile),
         }
     }
 }
 
+impl<F: Seek + Read + Write> NetCDF<F> {
+    /// Write native values into the slab `start..start + count` of a variable
+    pub fn write_slab<T: NetCDFData>(
+        &mut self,
+        variable: String,
+        start: &[usize],
+        count: &[usize],
+        data: &[T],
+    ) -> Result<(), HeadScratcherError<String>> {
+        let selection = Selection::new(start.to_vec(), count.to_vec());
+        self.write_selection(variable, &selection, data)
+    }
+
+    /// Write native values into a (strided) selection of a variable
+    pub fn write_selection<T: NetCDFData>(
+        &mut self,
+        variable: String,
+        selection: &Selection,
+        data: &[T],
+    ) -> Result<(), HeadScratcherError<String>> {
+        self.check_writable()?;
+        self.check_type::<T>(&variable)?;
+        let len = selection.len()?;
+        if data.len() != len {
+            let msg = format!("{} values for {} selected", data.len(), len);
+            return Err(HeadScratcherError::InvalidSelection(msg));
+        }
+        let buffer = T::encode(data);
+        let runs = self.header.slab_runs(variable, selection)?;
+        let mut pos = 0;
+        for (offset, len) in runs {
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.write_all(&buffer[pos..pos + len])?;
+            pos += len;
+        }
+        Ok(())
+    }
+
+    /// Append `count` records to all record variables, filled with fill values
+    ///
+    /// Returns the index of the first new record. Files with a streaming record
+    /// count keep it until [`NetCDF::finish`] writes the actual count.
+    pub fn append_records(&mut self, count: usize) -> Result<usize, HeadScratcherError<String>> {
+        self.check_writable()?;
+        let first = self.number_of_records()?;
+        let recsize = self.header.recsize;
+        let records: Vec<(u64, Vec<u8>)> = match &self.header.vars {
+            Some(vars) => vars
+                .values()
+                .filter(|v| v.is_record())
+                .map(|v| (v.begin, builder::fill_bytes(v, v.vsize().min(recsize))))
+                .collect(),
+            None => return Err(HeadScratcherError::NoVariablesInFile),
+        };
+        if records.is_empty() {
+            let msg = "No record variables to append to".to_string();
+            return Err(HeadScratcherError::InvalidDefinition(msg));
+        }
+        for record in first..first + count {
+            for (begin, fill) in records.iter() {
+                let pos = begin + (record * recsize) as u64;
+                self.file.seek(SeekFrom::Start(pos))?;
+                self.file.write_all(fill)?;
+            }
+        }
+        if let NumberOfRecords::NonNegative(_) = self.header.nor {
+            self.write_number_of_records(first + count)?;
+        }
+        Ok(first)
+    }
+
+    /// Finish writing: replace a streaming record count with the actual count
+    pub fn finish(&mut self) -> Result<(), HeadScratcherError<String>> {
+        if self.header.nor == NumberOfRecords::Streaming {
+            let numrecs = self.number_of_records()?;
+            self.write_number_of_records(numrecs)?;
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+
+    /// Number of records, counted from the file size for streaming files
+    fn number_of_records(&mut self) -> Result<usize, HeadScratcherError<String>> {
+        match self.header.nor {
+            NumberOfRecords::NonNegative(n) => Ok(n as usize),
+            NumberOfRecords::Streaming => {
+                let begin = match &self.header.vars {
+                    Some(vars) => vars
+                        .values()
+                        .filter(|v| v.is_record())
+                        .map(|v| v.begin)
+                        .min(),
+                    None => None,
+                };
+                let end = self.file.seek(SeekFrom::End(0))?;
+                match (begin, self.header.recsize) {
+                    (Some(begin), recsize) if recsize > 0 => {
+                        Ok((end.saturating_sub(begin) / recsize as u64) as usize)
+                    }
+                    (_, _) => Ok(0),
+                }
+            }
+        }
+    }
+
+    /// Update `numrecs` in memory and in the file
+    fn write_number_of_records(
+        &mut self,
+        numrecs: usize,
+    ) -> Result<(), HeadScratcherError<String>> {
+        self.header.nor = NumberOfRecords::NonNegative(numrecs as u64);
+        let mut o = Vec::new();
+        writer::number_of_records(&mut o, &self.header.nor, self.header.version)?;
+        // numrecs directly follows the magic bytes
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&o)?;
+        Ok(())
+    }
+}
+
 impl NetCDF<File> {
     pub fn new(filename: String) -> Result<Self, HeadScratcherError<String>> {
-        let mut fs = File::open(&filename)?;
-        let h = NetCDFHeader::from_file(&mut fs)?;
-        Ok(NetCDF {
-            file: fs,
-            header: h,
-        })
+        NetCDF::new_from_file(File::open(&filename)?)
     }
 }
 
-#[cfg(features = "border")]
-use byteorder::ReadBytesExt;
+/// Fill `buffer` with the raw bytes of a variable from `start` on
+fn read_range<F: ByteRangeSource>(
+    file: &mut F,
+    header: &NetCDFHeader,
+    storage: &HashMap<String, hdf5::Storage>,
+    variable: String,
+    start: &[usize],
+    buffer: &mut [u8],
+) -> Result<(), HeadScratcherError<String>> {
+    if let Some(storage) = storage.get(&variable) {
+        let fill = builder::fill_bytes(header.variable(&variable)?, storage.size);
+        return storage.read_range(&mut Reader::new(file)?, start, &fill, buffer);
+    }
+    let runs = range_runs(header, variable, start, buffer.len())?;
+    file.read_ranges(&runs, buffer)?;
+    Ok(())
+}
 
-#[cfg(features = "border")]
-pub fn vec_to_data(buffer: &[u8]) -> Vec<f32> {
-    let mut result = vec![0f32; buffer.len() / 4];
-    std::io::Cursor::new(buffer)
-        .read_f32_into::<byteorder::BigEndian>(&mut result)
-        .unwrap();
-    result
+/// Fill `buffer` with the raw bytes of a selection, in selection order
+fn read_runs<F: ByteRangeSource>(
+    file: &mut F,
+    header: &NetCDFHeader,
+    storage: &HashMap<String, hdf5::Storage>,
+    variable: String,
+    selection: &Selection,
+    buffer: &mut [u8],
+) -> Result<(), HeadScratcherError<String>> {
+    if let Some(storage) = storage.get(&variable) {
+        let fill = builder::fill_bytes(header.variable(&variable)?, storage.size);
+        return storage.read_selection(&mut Reader::new(file)?, selection, &fill, buffer);
+    }
+    let runs = selection_runs(header, variable, selection, buffer.len())?;
+    file.read_ranges(&runs, buffer)?;
+    Ok(())
 }
 
-#[cfg(not(features = "border"))]
-pub fn vec_to_data(buffer: &[u8]) -> Vec<f32> {
-    let (_, result) =
-        nom::multi::count(crate::parser::components::float, buffer.len() / 4)(buffer).unwrap();
-    result
+/// Byte run `(offset, length)` of `len` raw bytes of a classic variable from
+/// `start` on
+pub(crate) fn range_runs(
+    header: &NetCDFHeader,
+    variable: String,
+    start: &[usize],
+    len: usize,
+) -> Result<Vec<(u64, usize)>, HeadScratcherError<String>> {
+    let seek_pos = match (&header.vars, &header.seeks) {
+        (Some(v), Some(s)) => calc_seek(v, s, header.recsize, variable.clone(), start),
+        (_, _) => return Err(HeadScratcherError::NoVariablesInFile),
+    };
+    match seek_pos {
+        Some(pos) => Ok(vec![(pos, len)]),
+        None => Err(HeadScratcherError::VariableNotFound(variable)),
+    }
+}
+
+/// Byte runs `(offset, length)` of a selection of a classic variable, checked
+/// against a buffer of `len` bytes
+pub(crate) fn selection_runs(
+    header: &NetCDFHeader,
+    variable: String,
+    selection: &Selection,
+    len: usize,
+) -> Result<Vec<(u64, usize)>, HeadScratcherError<String>> {
+    let runs = header.slab_runs(variable, selection)?;
+    let size: usize = runs.iter().map(|(_, len)| len).sum();
+    if len != size {
+        let msg = format!("Buffer holds {} bytes, selection {}", len, size);
+        return Err(HeadScratcherError::InvalidSelection(msg));
+    }
+    Ok(runs)
+}
+
+/// Zeroed buffer for a selection of values of `size` bytes
+///
+/// The selection is checked against the variable first, so that its size
+/// neither overflows nor exceeds the variable.
+pub(crate) fn selection_buffer(
+    header: &NetCDFHeader,
+    storage: &HashMap<String, hdf5::Storage>,
+    variable: &str,
+    selection: &Selection,
+    size: usize,
+) -> Result<Vec<u8>, HeadScratcherError<String>> {
+    match storage.get(variable) {
+        Some(storage) => storage.validate(selection)?,
+        None => header.check_selection(variable, selection)?,
+    }
+    Ok(vec![0u8; selection.bytes(size)?])
+}
+
+/// Check that values of type `T` can be read from or written to a variable
+fn check_type<T: NetCDFData>(
+    header: &NetCDFHeader,
+    variable: &str,
+) -> Result<(), HeadScratcherError<String>> {
+    let nc_type = header.variable(variable)?.nc_type;
+    if nc_type != T::NC_TYPE {
+        let msg = format!("{} is {:?}, not {:?}", variable, nc_type, T::NC_TYPE);
+        return Err(HeadScratcherError::TypeMismatch(msg));
+    }
+    Ok(())
+}
+
+/// Convert a big endian buffer into native values of type `T`
+///
+/// With the `border` feature the conversion runs through byteorder's bulk
+/// routines.
+pub fn vec_to_data<T: NetCDFData>(buffer: &[u8]) -> Result<Vec<T>, HeadScratcherError<String>> {
+    data::check_length(&T::NC_TYPE, buffer)?;
+    Ok(T::decode(buffer))
 }
 
 #[cfg(test)]
@@ -134,11 +622,444 @@ mod tests {
         let mut netcdf = NetCDF::new(filename).unwrap();
         let mut buffer = vec![0u8; 4];
         netcdf
-            .update_buffer("tas".to_string(), &vec![0, 0, 0], &mut buffer)
+            .update_buffer("tas".to_string(), &[0, 0, 0], &mut buffer)
             .unwrap();
-        assert_eq!(vec_to_data(&buffer), vec![215.8935]);
+        assert_eq!(vec_to_data::<f32>(&buffer).unwrap(), vec![215.8935]);
         assert_eq!(buffer, vec![67, 87, 228, 188]);
         let map = netcdf.mapsize().unwrap();
         assert_eq!(map, 256 * 128)
     }
+
+    #[test]
+    fn test_vec_to_data() {
+        let buffer = [0xFF, 0xFE, 0, 1];
+        assert_eq!(vec_to_data::<i16>(&buffer).unwrap(), vec![-2, 1]);
+        assert_eq!(vec_to_data::<i32>(&buffer).unwrap(), vec![-131071]);
+        let e = vec_to_data::<f64>(&buffer).unwrap_err();
+        assert_eq!(e, HeadScratcherError::InvalidBufferLength(4, 8));
+    }
+
+    #[test]
+    fn test_read_slab() {
+        let filename = "assets/sresa1b_ncar_ccsm3-example.nc".to_string();
+        let mut netcdf = NetCDF::new(filename).unwrap();
+        let tas: Vec<f32> = netcdf
+            .read_slab("tas".to_string(), &[0, 0, 0], &[1, 1, 1])
+            .unwrap();
+        assert_eq!(tas, vec![215.8935]);
+
+        // 3x4 box out of a 128x256 field
+        let tas: Vec<f32> = netcdf
+            .read_slab("tas".to_string(), &[0, 10, 20], &[1, 3, 4])
+            .unwrap();
+        let mut buffer = vec![0u8; 4];
+        for (k, value) in tas.iter().enumerate() {
+            let coord = [0, 10 + k / 4, 20 + k % 4];
+            netcdf
+                .update_buffer("tas".to_string(), &coord, &mut buffer)
+                .unwrap();
+            assert_eq!(vec_to_data::<f32>(&buffer).unwrap(), vec![*value]);
+        }
+
+        // 2-D slice out of a 4-D variable
+        let ua: Vec<f32> = netcdf
+            .read_slab("ua".to_string(), &[0, 3, 0, 0], &[1, 1, 128, 256])
+            .unwrap();
+        assert_eq!(ua.len(), 128 * 256);
+
+        let e = netcdf
+            .read_slab::<f32>("tas".to_string(), &[0, 127, 250], &[1, 2, 1])
+            .unwrap_err();
+        assert!(matches!(e, HeadScratcherError::InvalidSelection(_)));
+        let e = netcdf
+            .read_slab::<f64>("tas".to_string(), &[0, 0, 0], &[1, 1, 1])
+            .unwrap_err();
+        assert!(matches!(e, HeadScratcherError::TypeMismatch(_)));
+        let e = netcdf
+            .read_slab::<f32>("tas".to_string(), &[0, 0], &[1, 1])
+            .unwrap_err();
+        assert!(matches!(e, HeadScratcherError::InvalidSelection(_)));
+        // checked before the buffer is allocated
+        let e = netcdf
+            .read_slab::<f32>("tas".to_string(), &[0, 0, 0], &[1, 1, usize::MAX / 2])
+            .unwrap_err();
+        assert!(matches!(e, HeadScratcherError::InvalidSelection(_)));
+        let e = netcdf
+            .read_unpacked::<f32>("tas".to_string(), &[0, 0, 0], &[1, usize::MAX, 2])
+            .unwrap_err();
+        assert!(matches!(e, HeadScratcherError::InvalidSelection(_)));
+        let requests = [("tas".to_string(), vec![0, 0, 0], vec![2, 2, usize::MAX])];
+        let e = netcdf.read_batch(&requests, 0).unwrap_err();
+        assert!(matches!(e, HeadScratcherError::InvalidSelection(_)));
+    }
+
+    #[test]
+    fn test_read_selection() {
+        let filename = "assets/sresa1b_ncar_ccsm3-example.nc".to_string();
+        let mut netcdf = NetCDF::new(filename).unwrap();
+        let full: Vec<f32> = netcdf
+            .read_slab("tas".to_string(), &[0, 0, 0], &[1, 128, 256])
+            .unwrap();
+        // every 4th longitude of a regional box
+        let selection = Selection::new(vec![0, 10, 3], vec![1, 5, 20]).with_stride(vec![1, 1, 4]);
+        let tas: Vec<f32> = netcdf
+            .read_selection("tas".to_string(), &selection)
+            .unwrap();
+        assert_eq!(tas.len(), 100);
+        for (k, value) in tas.iter().enumerate() {
+            let (lat, lon) = (10 + k / 20, 3 + 4 * (k % 20));
+            assert_eq!(*value, full[lat * 256 + lon]);
+        }
+        let mut buffer = vec![0u8; 3];
+        let e = netcdf
+            .update_buffer_selection("tas".to_string(), &selection, &mut buffer)
+            .unwrap_err();
+        assert!(matches!(e, HeadScratcherError::InvalidSelection(_)));
+    }
+
+    #[test]
+    fn test_read_cdf5() {
+        let filename = "assets/cdf5.nc".to_string();
+        let mut netcdf = NetCDF::new(filename).unwrap();
+        let mut buffer = vec![0u8; 8];
+        netcdf
+            .update_buffer("big".to_string(), &[1], &mut buffer)
+            .unwrap();
+        assert_eq!(buffer, vec![0xFF; 8]);
+        let mut buffer = vec![0u8; 1];
+        netcdf
+            .update_buffer("b".to_string(), &[2], &mut buffer)
+            .unwrap();
+        assert_eq!(buffer, vec![202]);
+    }
+
+    #[test]
+    fn test_read_netcdf4() {
+        let classic = "assets/sresa1b_ncar_ccsm3-example.nc".to_string();
+        let mut classic = NetCDF::new(classic).unwrap();
+        let filename = "assets/sresa1b_ncar_ccsm3-example.4.nc".to_string();
+        let mut netcdf = NetCDF::new(filename).unwrap();
+        assert_eq!(netcdf.header().version, NetCDFVersion::NetCDF4);
+
+        // contiguous (lat, area, ...) and chunked (tas, ua, ...) variables
+        let names: Vec<String> = classic
+            .header()
+            .vars
+            .as_ref()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        for name in names {
+            let shape = classic.header().variable_shape(&name).unwrap();
+            let shape: Vec<usize> = shape.into_iter().map(|(_, l)| l).collect();
+            let selection = Selection::all(&shape);
+            let size = classic.variable(&name).unwrap().nc_type.extsize();
+            let mut expected = vec![0u8; selection.len().unwrap() * size];
+            let mut buffer = vec![0u8; selection.len().unwrap() * size];
+            classic
+                .update_buffer_selection(name.clone(), &selection, &mut expected)
+                .unwrap();
+            netcdf
+                .update_buffer_selection(name.clone(), &selection, &mut buffer)
+                .unwrap();
+            assert_eq!(buffer, expected, "{}", name);
+        }
+
+        let selection =
+            Selection::new(vec![0, 2, 5, 7], vec![1, 3, 4, 5]).with_stride(vec![1, 5, 3, 2]);
+        let expected: Vec<f32> = classic
+            .read_selection("ua".to_string(), &selection)
+            .unwrap();
+        let values: Vec<f32> = netcdf.read_selection("ua".to_string(), &selection).unwrap();
+        assert_eq!(values, expected);
+        let e = netcdf
+            .read_slab::<f32>("ua".to_string(), &[0, 0, 0, 0], &[1, 1, 1, usize::MAX / 2])
+            .unwrap_err();
+        assert!(matches!(e, HeadScratcherError::InvalidSelection(_)));
+
+        let mut expected = vec![0u8; 4 * 300];
+        let mut buffer = vec![0u8; 4 * 300];
+        classic
+            .update_buffer("ua".to_string(), &[0, 3, 127, 100], &mut expected)
+            .unwrap();
+        netcdf
+            .update_buffer("ua".to_string(), &[0, 3, 127, 100], &mut buffer)
+            .unwrap();
+        assert_eq!(buffer, expected);
+
+        let values = netcdf
+            .read_masked::<f32>("pr".to_string(), &[0, 0, 0], &[1, 2, 2])
+            .unwrap();
+        assert!(values.iter().all(|v| v.is_some()));
+        let e = netcdf.write_slab("lat".to_string(), &[0], &[1], &[0f32]);
+        assert_eq!(e, Err(HeadScratcherError::NetCDF4NotSupported));
+    }
+
+    #[test]
+    fn test_read_records() {
+        let filename = "assets/cdf5.nc".to_string();
+        let mut netcdf = NetCDF::new(filename).unwrap();
+        let mut buffer = vec![0u8; 6];
+        netcdf
+            .update_buffer("v".to_string(), &[0, 0], &mut buffer)
+            .unwrap();
+        assert_eq!(buffer, vec![234, 96, 0, 1, 0, 2]);
+        netcdf
+            .update_buffer("v".to_string(), &[1, 0], &mut buffer)
+            .unwrap();
+        assert_eq!(buffer, vec![0, 3, 0, 4, 0, 5]);
+        let v: Vec<u16> = netcdf.read_slab("v".to_string(), &[0, 1], &[2, 2]).unwrap();
+        assert_eq!(v, vec![1, 2, 4, 5]);
+        let selection = Selection::new(vec![0, 0], vec![2, 2]).with_stride(vec![1, 2]);
+        let v: Vec<u16> = netcdf.read_selection("v".to_string(), &selection).unwrap();
+        assert_eq!(v, vec![60000, 2, 3, 5]);
+        let big: Vec<i64> = netcdf.read_slab("big".to_string(), &[0], &[3]).unwrap();
+        assert_eq!(big, vec![1 << 40, -1, 3]);
+        let t: Vec<i64> = netcdf.read_slab("t".to_string(), &[0], &[2]).unwrap();
+        assert_eq!(t, vec![10, 20]);
+    }
+
+    fn record_file(name: &str) -> (std::path::PathBuf, NetCDF<File>) {
+        let path =
+            std::env::temp_dir().join(format!("headscratcher-{}-{}.nc", name, std::process::id()));
+        let mut builder = builder::NetCDFBuilder::new(parser::components::NetCDFVersion::Classic);
+        builder.add_unlimited_dimension("time").unwrap();
+        builder.add_dimension("x", 3).unwrap();
+        builder
+            .add_variable("t", &["time"], parser::components::NetCDFType::NC_DOUBLE)
+            .unwrap()
+            .add_variable(
+                "v",
+                &["time", "x"],
+                parser::components::NetCDFType::NC_SHORT,
+            )
+            .unwrap();
+        let netcdf = builder.create(&path).unwrap();
+        (path, netcdf)
+    }
+
+    fn open_rw(path: &std::path::Path) -> NetCDF<File> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        NetCDF::new_from_file(file).unwrap()
+    }
+
+    #[test]
+    fn test_append_records() {
+        let (path, mut netcdf) = record_file("append");
+        assert_eq!(netcdf.append_records(2).unwrap(), 0);
+        netcdf
+            .write_slab("t".to_string(), &[0], &[2], &[0.5, 1.5])
+            .unwrap();
+        netcdf
+            .write_slab("v".to_string(), &[1, 0], &[1, 3], &[4i16, 5, 6])
+            .unwrap();
+        drop(netcdf);
+
+        let mut netcdf = open_rw(&path);
+        assert_eq!(netcdf.header().nor, NumberOfRecords::NonNegative(2));
+        assert_eq!(netcdf.append_records(1).unwrap(), 2);
+        netcdf
+            .write_slab("t".to_string(), &[2], &[1], &[2.5])
+            .unwrap();
+        drop(netcdf);
+
+        let mut netcdf = NetCDF::new(path.to_str().unwrap().to_string()).unwrap();
+        assert_eq!(netcdf.header().nor, NumberOfRecords::NonNegative(3));
+        let t: Vec<f64> = netcdf.read_slab("t".to_string(), &[0], &[3]).unwrap();
+        assert_eq!(t, vec![0.5, 1.5, 2.5]);
+        let v: Vec<i16> = netcdf.read_slab("v".to_string(), &[0, 0], &[3, 3]).unwrap();
+        assert_eq!(
+            v,
+            vec![-32767, -32767, -32767, 4, 5, 6, -32767, -32767, -32767]
+        );
+        let size = std::fs::metadata(&path).unwrap().len();
+        let begin = netcdf.header().vars.as_ref().unwrap()["t"].begin;
+        assert_eq!(size, begin + 3 * netcdf.header().recsize as u64);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_time_series() {
+        let (path, mut netcdf) = record_file("series");
+        netcdf.append_records(4).unwrap();
+        netcdf
+            .write_slab("t".to_string(), &[0], &[4], &[0.5, 1.5, 2.5, 3.5])
+            .unwrap();
+        netcdf
+            .write_slab(
+                "v".to_string(),
+                &[0, 0],
+                &[3, 3],
+                &[1i16, 2, 3, 4, 5, 6, 7, 8, 9],
+            )
+            .unwrap();
+
+        let v: Vec<i16> = netcdf.time_series("v".to_string(), &[1], None).unwrap();
+        assert_eq!(v, vec![2, 5, 8, -32767]);
+        let v = netcdf
+            .time_series_masked::<i16>("v".to_string(), &[2], Some("time"))
+            .unwrap();
+        assert_eq!(v, vec![Some(3), Some(6), Some(9), None]);
+        let t: Vec<f64> = netcdf.time_series("t".to_string(), &[], None).unwrap();
+        assert_eq!(t, vec![0.5, 1.5, 2.5, 3.5]);
+        // any dimension can be walked
+        let v: Vec<i16> = netcdf
+            .time_series("v".to_string(), &[1], Some("x"))
+            .unwrap();
+        assert_eq!(v, vec![4, 5, 6]);
+
+        let e = netcdf
+            .time_series::<i16>("v".to_string(), &[1, 1], None)
+            .unwrap_err();
+        assert!(matches!(e, HeadScratcherError::InvalidSelection(_)));
+        let e = netcdf
+            .time_series::<i16>("v".to_string(), &[1], Some("lat"))
+            .unwrap_err();
+        assert!(matches!(e, HeadScratcherError::CouldNotFindDimension(_)));
+        let e = netcdf
+            .time_series::<i16>("v".to_string(), &[3], None)
+            .unwrap_err();
+        assert!(matches!(e, HeadScratcherError::InvalidSelection(_)));
+        std::fs::remove_file(path).unwrap();
+
+        // a point of a netCDF-4 file
+        let filename = "assets/sresa1b_ncar_ccsm3-example.4.nc".to_string();
+        let mut netcdf = NetCDF::new(filename).unwrap();
+        let tas: Vec<f32> = netcdf
+            .time_series("tas".to_string(), &[0, 0], None)
+            .unwrap();
+        assert_eq!(tas, vec![215.8935]);
+    }
+
+    #[test]
+    fn test_read_unpacked() {
+        use parser::components::{NetCDFAttribute, NetCDFType, NetCDFTypeInstance};
+        let path =
+            std::env::temp_dir().join(format!("headscratcher-packed-{}.nc", std::process::id()));
+        let mut builder = builder::NetCDFBuilder::new(parser::components::NetCDFVersion::Classic);
+        builder.add_dimension("x", 4).unwrap();
+        builder
+            .add_variable("t2m", &["x"], NetCDFType::NC_SHORT)
+            .unwrap()
+            .add_variable("sst", &["x"], NetCDFType::NC_BYTE)
+            .unwrap()
+            .add_variable("raw", &["x"], NetCDFType::NC_FLOAT)
+            .unwrap();
+        let attr = |n: &str, v| NetCDFAttribute::from_value(n.to_string(), v);
+        builder
+            .add_variable_attribute(
+                "t2m",
+                attr("scale_factor", NetCDFTypeInstance::FLOATS(vec![0.01])),
+            )
+            .unwrap()
+            .add_variable_attribute(
+                "t2m",
+                attr("add_offset", NetCDFTypeInstance::FLOATS(vec![273.15])),
+            )
+            .unwrap()
+            .add_variable_attribute(
+                "t2m",
+                attr("_FillValue", NetCDFTypeInstance::SHORTS(vec![-32767])),
+            )
+            .unwrap()
+            .add_variable_attribute(
+                "sst",
+                attr("scale_factor", NetCDFTypeInstance::DOUBLES(vec![0.5])),
+            )
+            .unwrap();
+        let mut netcdf = builder.create(&path).unwrap();
+        netcdf
+            .write_slab("t2m".to_string(), &[0], &[4], &[-100i16, 0, 100, 1000])
+            .unwrap();
+        netcdf
+            .write_slab("sst".to_string(), &[0], &[4], &[-2i8, 0, 2, 41])
+            .unwrap();
+        netcdf
+            .write_slab("raw".to_string(), &[0], &[4], &[1.0f32, 2.0, 3.0, 4.0])
+            .unwrap();
+
+        let t2m: Vec<f32> = netcdf.read_unpacked("t2m".to_string(), &[0], &[4]).unwrap();
+        let expected = [272.15f32, 273.15, 274.15, 283.15];
+        for (value, expected) in t2m.iter().zip(expected.iter()) {
+            assert!((value - expected).abs() < 1e-4);
+        }
+        // fill values are masked before unpacking
+        netcdf
+            .write_slab("t2m".to_string(), &[1], &[1], &[-32767i16])
+            .unwrap();
+        let t2m: Vec<f32> = netcdf.read_unpacked("t2m".to_string(), &[0], &[2]).unwrap();
+        assert!((t2m[0] - 272.15).abs() < 1e-4);
+        assert!(t2m[1].is_nan());
+        let sst: Vec<f64> = netcdf.read_unpacked("sst".to_string(), &[1], &[3]).unwrap();
+        assert_eq!(sst, vec![0.0, 1.0, 20.5]);
+        let raw: Vec<f32> = netcdf.read_unpacked("raw".to_string(), &[2], &[2]).unwrap();
+        assert_eq!(raw, vec![3.0, 4.0]);
+        let e = netcdf
+            .read_unpacked::<f64>("t2m".to_string(), &[0], &[1])
+            .unwrap_err();
+        assert!(matches!(e, HeadScratcherError::TypeMismatch(_)));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_read_masked() {
+        let (path, mut netcdf) = record_file("masked");
+        netcdf.append_records(2).unwrap();
+        netcdf
+            .write_slab("v".to_string(), &[1, 0], &[1, 2], &[4i16, 5])
+            .unwrap();
+        let v: Vec<Option<i16>> = netcdf
+            .read_masked("v".to_string(), &[0, 0], &[2, 3])
+            .unwrap();
+        assert_eq!(v, vec![None, None, None, Some(4), Some(5), None]);
+        netcdf
+            .write_slab("t".to_string(), &[1], &[1], &[0.5])
+            .unwrap();
+        let t: Vec<Option<f64>> = netcdf.read_masked("t".to_string(), &[0], &[2]).unwrap();
+        assert_eq!(t, vec![None, Some(0.5)]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_append_streaming() {
+        let (path, mut netcdf) = record_file("streaming");
+        netcdf.append_records(1).unwrap();
+        drop(netcdf);
+        let mut file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(4)).unwrap();
+        file.write_all(&constants_and_types::STREAMING.to_be_bytes())
+            .unwrap();
+        drop(file);
+
+        let mut netcdf = open_rw(&path);
+        assert_eq!(netcdf.header().nor, NumberOfRecords::Streaming);
+        let e = netcdf.header().dimension_length("time").unwrap_err();
+        assert_eq!(
+            e,
+            HeadScratcherError::UnknownNumberOfRecords("time".to_string())
+        );
+        let e = netcdf.header().variable_shape("v").unwrap_err();
+        assert!(matches!(e, HeadScratcherError::UnknownNumberOfRecords(_)));
+        let e = netcdf
+            .time_series::<i16>("v".to_string(), &[1], None)
+            .unwrap_err();
+        assert!(matches!(e, HeadScratcherError::UnknownNumberOfRecords(_)));
+        assert_eq!(netcdf.header().dimension_length("x").unwrap(), 3);
+        let v: Vec<i16> = netcdf.read_slab("v".to_string(), &[0, 0], &[1, 3]).unwrap();
+        assert_eq!(v.len(), 3);
+        assert_eq!(netcdf.append_records(2).unwrap(), 1);
+        assert_eq!(netcdf.append_records(1).unwrap(), 3);
+        assert_eq!(netcdf.header().nor, NumberOfRecords::Streaming);
+        netcdf.finish().unwrap();
+        drop(netcdf);
+
+        let netcdf = NetCDF::new(path.to_str().unwrap().to_string()).unwrap();
+        assert_eq!(netcdf.header().nor, NumberOfRecords::NonNegative(4));
+        std::fs::remove_file(path).unwrap();
+    }
 }
@@ -0,0 +1,168 @@
+//! Memory-mapped files
+//!
+//! [`NetCDF::map`] opens a file through a memory map. All reads of a mapped
+//! file are plain memory copies, and slabs of classic files can be borrowed
+//! straight from the map with [`NetCDF::slab_bytes`] and
+//! [`NetCDF::view_slab`]. The same views are available for files held in
+//! memory as `Cursor<Vec<u8>>`. Available with the `mmap` feature.
+use crate::data::NetCDFData;
+use crate::error::HeadScratcherError as HSE;
+use crate::selection::Selection;
+use crate::NetCDF;
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::Cursor;
+
+/// netCDF file read through a memory map
+pub type MappedNetCDF = NetCDF<Cursor<Mmap>>;
+
+impl NetCDF<Cursor<Mmap>> {
+    /// Open a file through a read-only memory map
+    ///
+    /// The file must not be changed while it is mapped; other processes
+    /// truncating it crash the reader.
+    pub fn map(filename: String) -> Result<Self, HSE<String>> {
+        let file = File::open(&filename)?;
+        let map = unsafe { Mmap::map(&file)? };
+        NetCDF::new_from_file(Cursor::new(map))
+    }
+}
+
+impl<B: AsRef<[u8]>> NetCDF<Cursor<B>> {
+    /// Borrow the raw big endian bytes of the slab `start..start + count`
+    ///
+    /// Returns one slice per contiguous run of the slab in the file, in slab
+    /// order; a slab with only the first selected dimension partial, or of a
+    /// single record, is one slice. netCDF-4 variables are not supported.
+    pub fn slab_bytes(
+        &self,
+        variable: String,
+        start: &[usize],
+        count: &[usize],
+    ) -> Result<Vec<&[u8]>, HSE<String>> {
+        if self.storage.contains_key(&variable) {
+            return Err(HSE::NetCDF4NotSupported);
+        }
+        let selection = Selection::new(start.to_vec(), count.to_vec());
+        let bytes = self.file.get_ref().as_ref();
+        self.header
+            .slab_runs(variable, &selection)?
+            .into_iter()
+            .map(|(offset, len)| {
+                let offset = offset as usize;
+                bytes.get(offset..offset + len).ok_or(HSE::InvalidFile)
+            })
+            .collect()
+    }
+
+    /// Values of the slab `start..start + count`, borrowed where possible
+    ///
+    /// Values are borrowed if the slab is a single run, big endian bytes are
+    /// native values and the run is aligned for `T` (see
+    /// [`NetCDFData::view`]), and decoded otherwise. netCDF-4 variables are
+    /// not supported.
+    pub fn view_slab<T: NetCDFData>(
+        &self,
+        variable: String,
+        start: &[usize],
+        count: &[usize],
+    ) -> Result<Cow<'_, [T]>, HSE<String>> {
+        self.check_type::<T>(&variable)?;
+        let runs = self.slab_bytes(variable, start, count)?;
+        if let [bytes] = runs[..] {
+            if let Some(values) = T::view(bytes) {
+                return Ok(Cow::Borrowed(values));
+            }
+        }
+        Ok(Cow::Owned(runs.into_iter().flat_map(T::decode).collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map() {
+        let filename = "assets/sresa1b_ncar_ccsm3-example.nc".to_string();
+        let mut mapped = NetCDF::map(filename.clone()).unwrap();
+        let mut netcdf = NetCDF::new(filename).unwrap();
+        assert_eq!(mapped.header(), netcdf.header());
+
+        let bytes = mapped
+            .slab_bytes("tas".to_string(), &[0, 0, 0], &[1, 1, 1])
+            .unwrap();
+        assert_eq!(bytes, [[67, 87, 228, 188]]);
+
+        // rows out of a 128x256 field
+        let bytes = mapped
+            .slab_bytes("tas".to_string(), &[0, 10, 0], &[1, 3, 256])
+            .unwrap();
+        let mut buffer = vec![0u8; 3 * 256 * 4];
+        netcdf
+            .update_buffer("tas".to_string(), &[0, 10, 0], &mut buffer)
+            .unwrap();
+        assert_eq!(bytes, [&buffer[..]]);
+        let tas = mapped
+            .view_slab::<f32>("tas".to_string(), &[0, 10, 0], &[1, 3, 256])
+            .unwrap();
+        let expected: Vec<f32> = netcdf
+            .read_slab("tas".to_string(), &[0, 10, 0], &[1, 3, 256])
+            .unwrap();
+        assert_eq!(*tas, expected[..]);
+        let tas: Vec<f32> = mapped
+            .read_slab("tas".to_string(), &[0, 10, 0], &[1, 3, 256])
+            .unwrap();
+        assert_eq!(tas, expected);
+
+        // one view per row of a block
+        let bytes = mapped
+            .slab_bytes("tas".to_string(), &[0, 10, 20], &[1, 3, 4])
+            .unwrap();
+        let mut buffer = vec![0u8; 3 * 4 * 4];
+        netcdf
+            .update_buffer_selection(
+                "tas".to_string(),
+                &Selection::new(vec![0, 10, 20], vec![1, 3, 4]),
+                &mut buffer,
+            )
+            .unwrap();
+        assert_eq!(bytes.len(), 3);
+        assert_eq!(bytes.concat(), buffer);
+        let tas = mapped
+            .view_slab::<f32>("tas".to_string(), &[0, 10, 20], &[1, 3, 4])
+            .unwrap();
+        let expected: Vec<f32> = netcdf
+            .read_slab("tas".to_string(), &[0, 10, 20], &[1, 3, 4])
+            .unwrap();
+        assert!(matches!(tas, Cow::Owned(_)));
+        assert_eq!(*tas, expected[..]);
+        let e = mapped
+            .view_slab::<f64>("tas".to_string(), &[0, 0, 0], &[1, 1, 1])
+            .unwrap_err();
+        assert!(matches!(e, HSE::TypeMismatch(_)));
+    }
+
+    #[test]
+    fn test_view_in_memory() {
+        let bytes = std::fs::read("assets/cdf5.nc").unwrap();
+        let netcdf = NetCDF::new_from_file(Cursor::new(bytes)).unwrap();
+        let b = netcdf.view_slab::<u8>("b".to_string(), &[0], &[3]).unwrap();
+        assert!(matches!(b, Cow::Borrowed(_)));
+        assert_eq!(b[2], 202);
+        let v = netcdf
+            .view_slab::<u16>("v".to_string(), &[1, 0], &[1, 3])
+            .unwrap();
+        assert_eq!(*v, [3, 4, 5]);
+        let v = netcdf
+            .view_slab::<u16>("v".to_string(), &[0, 0], &[2, 3])
+            .unwrap();
+        assert_eq!(*v, [60000, 1, 2, 3, 4, 5]);
+
+        let bytes = std::fs::read("assets/sresa1b_ncar_ccsm3-example.4.nc").unwrap();
+        let netcdf = NetCDF::new_from_file(Cursor::new(bytes)).unwrap();
+        let e = netcdf.slab_bytes("lat".to_string(), &[0], &[1]);
+        assert_eq!(e, Err(HSE::NetCDF4NotSupported));
+    }
+}
@@ -0,0 +1,234 @@
+//! Files in object stores
+//!
+//! An [`ObjectStore`] answers range requests for objects in buckets, like the
+//! S3 API. [`S3Store`] talks to S3-compatible servers (with the `http`
+//! feature), [`DirStore`] stands in for one with a local directory. Objects are
+//! read through [`Remote`], so only the header and the requested slabs are
+//! fetched, never the whole object.
+use crate::error::HeadScratcherError as HSE;
+use crate::source::{RangeRequest, Remote};
+use crate::NetCDF;
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+
+/// Store of objects in buckets
+pub trait ObjectStore {
+    /// Size of an object in bytes
+    fn size(&self, bucket: &str, key: &str) -> io::Result<u64>;
+
+    /// `len` bytes from `offset` on of an object
+    fn get_range(&self, bucket: &str, key: &str, offset: u64, len: usize) -> io::Result<Vec<u8>>;
+}
+
+/// Object store in a local directory, buckets are its subdirectories
+#[derive(Debug)]
+pub struct DirStore {
+    root: PathBuf,
+}
+
+impl DirStore {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        DirStore { root: root.into() }
+    }
+
+    fn path(&self, bucket: &str, key: &str) -> PathBuf {
+        self.root.join(bucket).join(key)
+    }
+}
+
+impl ObjectStore for DirStore {
+    fn size(&self, bucket: &str, key: &str) -> io::Result<u64> {
+        Ok(std::fs::metadata(self.path(bucket, key))?.len())
+    }
+
+    fn get_range(&self, bucket: &str, key: &str, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        use std::io::{Read, Seek, SeekFrom};
+        let mut file = File::open(self.path(bucket, key))?;
+        let mut bytes = Vec::with_capacity(len);
+        file.seek(SeekFrom::Start(offset))?;
+        file.take(len as u64).read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+/// S3-compatible object store
+///
+/// Objects are addressed path-style, `<endpoint>/<bucket>/<key>`. Requests
+/// are only signed by a signer given with [`S3Store::with_signer`], without
+/// one buckets have to be readable anonymously.
+#[cfg(feature = "http")]
+pub struct S3Store {
+    agent: ureq::Agent,
+    endpoint: String,
+    sign: Option<Box<Signer>>,
+}
+
+/// Headers to authenticate a GET request of a URL
+#[cfg(feature = "http")]
+pub type Signer = dyn Fn(&str) -> Vec<(String, String)> + Send + Sync;
+
+#[cfg(feature = "http")]
+impl S3Store {
+    pub fn new(endpoint: &str) -> Self {
+        S3Store {
+            agent: ureq::Agent::new(),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            sign: None,
+        }
+    }
+
+    /// Sign every request with the headers returned by `sign`
+    ///
+    /// `sign` is called with the URL of each GET request, e.g. to add the
+    /// `Authorization`, `x-amz-date` and `x-amz-content-sha256` headers of
+    /// AWS Signature Version 4.
+    pub fn with_signer<F>(mut self, sign: F) -> Self
+    where
+        F: Fn(&str) -> Vec<(String, String)> + Send + Sync + 'static,
+    {
+        self.sign = Some(Box::new(sign));
+        self
+    }
+
+    fn url(&self, bucket: &str, key: &str) -> String {
+        format!("{}/{}/{}", self.endpoint, bucket, key)
+    }
+
+    fn headers(&self, url: &str) -> Vec<(String, String)> {
+        self.sign.as_ref().map_or_else(Vec::new, |sign| sign(url))
+    }
+}
+
+#[cfg(feature = "http")]
+impl std::fmt::Debug for S3Store {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Store")
+            .field("endpoint", &self.endpoint)
+            .field("signed", &self.sign.is_some())
+            .finish()
+    }
+}
+
+#[cfg(feature = "http")]
+impl ObjectStore for S3Store {
+    fn size(&self, bucket: &str, key: &str) -> io::Result<u64> {
+        let url = self.url(bucket, key);
+        crate::http::size(&self.agent, &url, &self.headers(&url))
+    }
+
+    fn get_range(&self, bucket: &str, key: &str, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let url = self.url(bucket, key);
+        crate::http::get_range(&self.agent, &url, offset, len, &self.headers(&url))
+    }
+}
+
+/// Object in a store
+#[derive(Debug)]
+pub struct Object<S: ObjectStore> {
+    store: S,
+    bucket: String,
+    key: String,
+}
+
+impl<S: ObjectStore> Object<S> {
+    pub fn new(store: S, bucket: &str, key: &str) -> Self {
+        Object {
+            store,
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+        }
+    }
+}
+
+impl<S: ObjectStore> RangeRequest for Object<S> {
+    fn size(&mut self) -> io::Result<u64> {
+        self.store.size(&self.bucket, &self.key)
+    }
+
+    fn request(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        self.store.get_range(&self.bucket, &self.key, offset, len)
+    }
+}
+
+impl<S: ObjectStore> NetCDF<Remote<Object<S>>> {
+    /// Open an object, fetching its header with range requests
+    pub fn from_object(store: S, bucket: &str, key: &str) -> Result<Self, HSE<String>> {
+        NetCDF::new_from_file(Remote::new(Object::new(store, bucket, key))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dir_store() {
+        let filename = "assets/sresa1b_ncar_ccsm3-example.nc".to_string();
+        let mut local = NetCDF::new(filename).unwrap();
+        let store = DirStore::new(".");
+        let mut object =
+            NetCDF::from_object(store, "assets", "sresa1b_ncar_ccsm3-example.nc").unwrap();
+        assert_eq!(object.header(), local.header());
+        assert_eq!(object.file.requests(), 2);
+
+        let tas: Vec<f32> = object
+            .read_slab("tas".to_string(), &[0, 10, 20], &[1, 3, 4])
+            .unwrap();
+        let expected: Vec<f32> = local
+            .read_slab("tas".to_string(), &[0, 10, 20], &[1, 3, 4])
+            .unwrap();
+        assert_eq!(tas, expected);
+        // the three rows are close enough for one request
+        assert_eq!(object.file.requests(), 3);
+
+        let e = NetCDF::from_object(DirStore::new("."), "assets", "missing.nc");
+        assert!(matches!(e, Err(HSE::IOError(io::ErrorKind::NotFound))));
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_s3_store() {
+        let (url, requests) = crate::http::tests::serve(".");
+        let filename = "assets/sresa1b_ncar_ccsm3-example.4.nc".to_string();
+        let mut local = NetCDF::new(filename).unwrap();
+        let signed = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = signed.clone();
+        let expected_url = format!("{}/assets/sresa1b_ncar_ccsm3-example.4.nc", url);
+        let store = S3Store::new(&format!("{}/", url)).with_signer(move |url| {
+            assert_eq!(url, expected_url);
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            vec![(
+                "Authorization".to_string(),
+                "AWS4-HMAC-SHA256 test".to_string(),
+            )]
+        });
+        let mut object =
+            NetCDF::from_object(store, "assets", "sresa1b_ncar_ccsm3-example.4.nc").unwrap();
+        let selection = crate::selection::Selection::new(vec![0, 0, 0], vec![1, 128, 256]);
+        let tas: Vec<f32> = object
+            .read_selection("tas".to_string(), &selection)
+            .unwrap();
+        let expected: Vec<f32> = local.read_selection("tas".to_string(), &selection).unwrap();
+        assert_eq!(tas, expected);
+        assert_eq!(
+            requests.load(std::sync::atomic::Ordering::SeqCst),
+            object.file.requests()
+        );
+        assert_eq!(
+            signed.load(std::sync::atomic::Ordering::SeqCst),
+            object.file.requests()
+        );
+
+        // empty ranges are not requested
+        let store = S3Store::new(&url);
+        let bytes = store
+            .get_range("assets", "sresa1b_ncar_ccsm3-example.4.nc", 0, 0)
+            .unwrap();
+        assert!(bytes.is_empty());
+        assert_eq!(
+            requests.load(std::sync::atomic::Ordering::SeqCst),
+            object.file.requests()
+        );
+    }
+}
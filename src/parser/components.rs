@@ -5,27 +5,31 @@
 use crate::constants_and_types as csts;
 use crate::error::HeadScratcherError as HSE;
 use crate::parser::HSEResult;
+use indexmap::IndexMap;
 use nom::{
     bytes::streaming::tag,
     number::{
-        complete::{be_f32, be_f64, be_i16, be_i32},
+        complete::{be_f32, be_f64, be_i16, be_i32, be_i64, be_i8, be_u16},
         streaming::{be_u32, be_u64, u8},
     },
 };
-use std::collections::HashMap;
-pub type DimensionHM = HashMap<usize, NetCDFDimension>;
-pub type VariableHM = HashMap<String, NetCDFVariable>;
-pub type AttributeHM = HashMap<String, NetCDFAttribute>;
+use std::convert::TryFrom;
+
+/// Dimensions, variables and attributes are kept in file declaration order
+pub type DimensionHM = IndexMap<usize, NetCDFDimension>;
+pub type VariableHM = IndexMap<String, NetCDFVariable>;
+pub type AttributeHM = IndexMap<String, NetCDFAttribute>;
 
 /// NetCDF Variable
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct NetCDFVariable {
     name: String,
     pub dims: Vec<u32>,
     attributes: Option<AttributeHM>,
     pub nc_type: NetCDFType,
-    vsize: usize,
+    pub(crate) vsize: usize,
     pub begin: u64,
+    pub(crate) record: bool,
 }
 
 impl NetCDFVariable {
@@ -45,45 +49,55 @@ impl NetCDFVariable {
             nc_type,
             vsize,
             begin,
+            record: false,
         }
     }
 
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
     pub fn length(&self) -> usize {
         self.vsize / self.nc_type.extsize()
     }
 
+    /// Size of the variable in bytes (per record for record variables)
+    pub fn vsize(&self) -> usize {
+        self.vsize
+    }
+
+    /// Variable is defined over the unlimited dimension
+    pub fn is_record(&self) -> bool {
+        self.record
+    }
+
     pub fn attributes(&self) -> &Option<AttributeHM> {
         &self.attributes
     }
+
+    /// Attribute of the variable by name
+    pub fn attribute(&self, name: &str) -> Option<&NetCDFAttribute> {
+        self.attributes.as_ref().and_then(|a| a.get(name))
+    }
 }
 
 /// Parse a single NetCDF variable [combined]
 pub fn variable(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], NetCDFVariable> {
-    let (i, name) = name(i)?;
-    let (i, dims) = nom::multi::length_count(nelems, nelems)(i)?;
-    let (mut i, attr_present) = list_type(i)?;
+    let (i, name) = name(i, version)?;
+    let (i, dims) =
+        nom::multi::length_count(|i| nelems(i, version), |i| dimension_id(i, version))(i)?;
+    let (mut i, attr_present) = list_type(i, version)?;
     let attrs = match attr_present {
         ListType::Absent => None,
         _ => {
-            let (k, attrs) = attribute_list(i)?;
+            let (k, attrs) = attribute_list(i, version)?;
             i = k;
             Some(attrs)
         }
     };
     let (i, nc_type) = nc_type(i)?;
-    let (mut i, vsize) = nelems(i)?;
-    let begin = match version {
-        NetCDFVersion::Classic => {
-            let (k, r) = be_u32(i)?;
-            i = k;
-            r as u64
-        }
-        NetCDFVersion::Offset64 => {
-            let (k, r) = be_u64(i)?;
-            i = k;
-            r as u64
-        }
-    };
+    let (i, vsize) = nelems(i, version)?;
+    let (i, begin) = offset(i, version)?;
     let var = NetCDFVariable::new(
         name.to_string(),
         dims,
@@ -97,8 +111,8 @@ pub fn variable(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], NetCDFVari
 
 /// Parse a list of NetCDF variables [combined]
 pub fn variable_list(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], VariableHM> {
-    let (mut i, mut count) = nelems(i)?;
-    let mut result = HashMap::new();
+    let (mut i, mut count) = nelems(i, version)?;
+    let mut result = VariableHM::new();
     while count > 0 {
         let (k, v) = variable(i, version)?;
         result.insert(v.name.clone(), v); // TODO: Implement without cloning
@@ -109,7 +123,7 @@ pub fn variable_list(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], Varia
 }
 
 /// NetCDF Attribute
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct NetCDFAttribute {
     name: String,
     nc_type: NetCDFType,
@@ -119,28 +133,23 @@ pub struct NetCDFAttribute {
 impl NetCDFAttribute {
     /// Create a new NetCDF Attribute
     pub fn new(name: String, nc_type: NetCDFType, data: Vec<u8>) -> Self {
+        let n = data.len() / nc_type.extsize();
+        let i = data.as_slice();
         let value = match nc_type {
             NetCDFType::NC_CHAR => {
                 let text = std::str::from_utf8(&data[..]).unwrap();
                 NetCDFTypeInstance::STRING(text.to_string())
             }
-            NetCDFType::NC_FLOAT => {
-                let (_, v) = float(data.as_slice()).unwrap();
-                NetCDFTypeInstance::FLOAT(v)
-            }
-            NetCDFType::NC_DOUBLE => {
-                let (_, v) = double(data.as_slice()).unwrap();
-                NetCDFTypeInstance::DOUBLE(v)
-            }
-            NetCDFType::NC_INT => {
-                let (_, v) = integer(data.as_slice()).unwrap();
-                NetCDFTypeInstance::INT(v)
-            }
-            NetCDFType::NC_SHORT => {
-                let (_, v) = short(data.as_slice()).unwrap();
-                NetCDFTypeInstance::SHORT(v)
-            }
-            NetCDFType::NC_BYTE => NetCDFTypeInstance::_RAW(data),
+            NetCDFType::NC_BYTE => NetCDFTypeInstance::BYTES(values(byte, i, n)),
+            NetCDFType::NC_SHORT => NetCDFTypeInstance::SHORTS(values(short, i, n)),
+            NetCDFType::NC_INT => NetCDFTypeInstance::INTS(values(integer, i, n)),
+            NetCDFType::NC_FLOAT => NetCDFTypeInstance::FLOATS(values(float, i, n)),
+            NetCDFType::NC_DOUBLE => NetCDFTypeInstance::DOUBLES(values(double, i, n)),
+            NetCDFType::NC_UBYTE => NetCDFTypeInstance::UBYTES(data.clone()),
+            NetCDFType::NC_USHORT => NetCDFTypeInstance::USHORTS(values(ushort, i, n)),
+            NetCDFType::NC_UINT => NetCDFTypeInstance::UINTS(values(uinteger, i, n)),
+            NetCDFType::NC_INT64 => NetCDFTypeInstance::INT64S(values(int64, i, n)),
+            NetCDFType::NC_UINT64 => NetCDFTypeInstance::UINT64S(values(uint64, i, n)),
         };
         NetCDFAttribute {
             name,
@@ -149,31 +158,99 @@ impl NetCDFAttribute {
         }
     }
 
+    /// Create a new NetCDF Attribute from decoded values
+    pub fn from_value(name: String, value: NetCDFTypeInstance) -> Self {
+        NetCDFAttribute {
+            name,
+            nc_type: value.nc_type(),
+            data: value,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn nc_type(&self) -> &NetCDFType {
+        &self.nc_type
+    }
+
+    /// Decoded attribute values
+    pub fn value(&self) -> &NetCDFTypeInstance {
+        &self.data
+    }
+
     pub fn as_string(&self) -> Option<String> {
         match &self.data {
             NetCDFTypeInstance::STRING(content) => Some(content.clone()),
             _ => None,
         }
     }
+
+    /// All values of a numeric attribute converted to f64
+    pub fn as_f64_vec(&self) -> Option<Vec<f64>> {
+        match &self.data {
+            NetCDFTypeInstance::STRING(_) => None,
+            NetCDFTypeInstance::BYTES(v) => Some(v.iter().map(|x| *x as f64).collect()),
+            NetCDFTypeInstance::SHORTS(v) => Some(v.iter().map(|x| *x as f64).collect()),
+            NetCDFTypeInstance::INTS(v) => Some(v.iter().map(|x| *x as f64).collect()),
+            NetCDFTypeInstance::FLOATS(v) => Some(v.iter().map(|x| *x as f64).collect()),
+            NetCDFTypeInstance::DOUBLES(v) => Some(v.clone()),
+            NetCDFTypeInstance::UBYTES(v) => Some(v.iter().map(|x| *x as f64).collect()),
+            NetCDFTypeInstance::USHORTS(v) => Some(v.iter().map(|x| *x as f64).collect()),
+            NetCDFTypeInstance::UINTS(v) => Some(v.iter().map(|x| *x as f64).collect()),
+            NetCDFTypeInstance::INT64S(v) => Some(v.iter().map(|x| *x as f64).collect()),
+            NetCDFTypeInstance::UINT64S(v) => Some(v.iter().map(|x| *x as f64).collect()),
+        }
+    }
+
+    /// All values of an integer attribute converted to i64
+    ///
+    /// Returns `None` for text and floating point attributes, or if a value
+    /// does not fit into an i64.
+    pub fn as_i64_vec(&self) -> Option<Vec<i64>> {
+        match &self.data {
+            NetCDFTypeInstance::BYTES(v) => Some(v.iter().map(|x| *x as i64).collect()),
+            NetCDFTypeInstance::SHORTS(v) => Some(v.iter().map(|x| *x as i64).collect()),
+            NetCDFTypeInstance::INTS(v) => Some(v.iter().map(|x| *x as i64).collect()),
+            NetCDFTypeInstance::UBYTES(v) => Some(v.iter().map(|x| *x as i64).collect()),
+            NetCDFTypeInstance::USHORTS(v) => Some(v.iter().map(|x| *x as i64).collect()),
+            NetCDFTypeInstance::UINTS(v) => Some(v.iter().map(|x| *x as i64).collect()),
+            NetCDFTypeInstance::INT64S(v) => Some(v.clone()),
+            NetCDFTypeInstance::UINT64S(v) => v.iter().map(|x| i64::try_from(*x).ok()).collect(),
+            _ => None,
+        }
+    }
+}
+
+/// Decode `n` consecutive values of an attribute
+fn values<'a, O, F>(f: F, i: &'a [u8], n: usize) -> Vec<O>
+where
+    F: Fn(&'a [u8]) -> HSEResult<&'a [u8], O>,
+{
+    let (_, v) = nom::multi::count(f, n)(i).unwrap();
+    v
 }
 
 /// Parse a single NetCDF attribute [combined]
-pub fn attribute(i: &[u8]) -> HSEResult<&[u8], NetCDFAttribute> {
-    let (i, (name, nc_type, nelems)) = nom::sequence::tuple((name, nc_type, nelems))(i)?;
+pub fn attribute(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], NetCDFAttribute> {
+    let (i, (name, nc_type, nelems)) =
+        nom::sequence::tuple((|i| name(i, version), nc_type, |i| nelems(i, version)))(i)?;
     let (i, data) = nom::bytes::streaming::take(nc_type.extsize() * nelems as usize)(i)?;
     // names are padded to the next 4-byte boundary
     // println!("{:?} {:?} {:?} {:?}", name, nc_type, nelems, data);
-    let drop = padding(nc_type.extsize() as u32 * nelems);
-    let (i, _) = nom::bytes::streaming::take(drop as u8)(i)?;
+    let drop = padding(nc_type.extsize() as u64 * nelems);
+    let (i, _) = nom::bytes::streaming::take(drop)(i)?;
     let result = NetCDFAttribute::new(name.to_string(), nc_type, data.to_vec());
     // println!("{:?}", result);
     Ok((i, result))
 }
 
 /// Parse a list of NetCDF attributes [combined]
-pub fn attribute_list(i: &[u8]) -> HSEResult<&[u8], AttributeHM> {
-    let (i, attrs) = nom::multi::length_count(nelems, attribute)(i)?;
-    let mut result: AttributeHM = HashMap::new();
+pub fn attribute_list(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], AttributeHM> {
+    let (i, attrs) =
+        nom::multi::length_count(|i| nelems(i, version), |i| attribute(i, version))(i)?;
+    let mut result = AttributeHM::new();
     for a in attrs.into_iter() {
         result.insert(a.name.clone(), a);
     }
@@ -181,7 +258,7 @@ pub fn attribute_list(i: &[u8]) -> HSEResult<&[u8], AttributeHM> {
 }
 
 /// NetCDF Dimension
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct NetCDFDimension {
     pub(crate) name: String,
     pub length: usize,
@@ -199,16 +276,17 @@ impl NetCDFDimension {
 }
 
 /// Parse a single NetCDF dimension [combined]
-pub fn dimension(i: &[u8]) -> HSEResult<&[u8], NetCDFDimension> {
-    let (i, (name, dim_length)) = nom::sequence::tuple((name, dim_length))(i)?;
+pub fn dimension(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], NetCDFDimension> {
+    let (i, (name, dim_length)) =
+        nom::sequence::tuple((|i| name(i, version), |i| dim_length(i, version)))(i)?;
     let ncdim = NetCDFDimension::new(name.to_string(), dim_length as usize);
     Ok((i, ncdim))
 }
 
 /// Parse a list of NetCDF dimensions [combined]
-pub fn dimension_list(i: &[u8]) -> HSEResult<&[u8], DimensionHM> {
-    let (i, dims) = nom::multi::length_count(nelems, dimension)(i)?;
-    let mut result: DimensionHM = HashMap::new();
+pub fn dimension_list(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], DimensionHM> {
+    let (i, dims) = nom::multi::length_count(|i| nelems(i, version), |i| dimension(i, version))(i)?;
+    let mut result = DimensionHM::new();
     for (i, d) in dims.into_iter().enumerate() {
         result.insert(i, d);
     }
@@ -216,19 +294,42 @@ pub fn dimension_list(i: &[u8]) -> HSEResult<&[u8], DimensionHM> {
 }
 
 /// NetCDF attribute value
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum NetCDFTypeInstance {
     STRING(String),
-    CHAR(u8),
-    SHORT(i16),
-    INT(i32),
-    FLOAT(f32),
-    DOUBLE(f64),
-    _RAW(Vec<u8>),
+    BYTES(Vec<i8>),
+    SHORTS(Vec<i16>),
+    INTS(Vec<i32>),
+    FLOATS(Vec<f32>),
+    DOUBLES(Vec<f64>),
+    UBYTES(Vec<u8>),
+    USHORTS(Vec<u16>),
+    UINTS(Vec<u32>),
+    INT64S(Vec<i64>),
+    UINT64S(Vec<u64>),
+}
+
+impl NetCDFTypeInstance {
+    /// External type of the values
+    pub fn nc_type(&self) -> NetCDFType {
+        match self {
+            NetCDFTypeInstance::STRING(_) => NetCDFType::NC_CHAR,
+            NetCDFTypeInstance::BYTES(_) => NetCDFType::NC_BYTE,
+            NetCDFTypeInstance::SHORTS(_) => NetCDFType::NC_SHORT,
+            NetCDFTypeInstance::INTS(_) => NetCDFType::NC_INT,
+            NetCDFTypeInstance::FLOATS(_) => NetCDFType::NC_FLOAT,
+            NetCDFTypeInstance::DOUBLES(_) => NetCDFType::NC_DOUBLE,
+            NetCDFTypeInstance::UBYTES(_) => NetCDFType::NC_UBYTE,
+            NetCDFTypeInstance::USHORTS(_) => NetCDFType::NC_USHORT,
+            NetCDFTypeInstance::UINTS(_) => NetCDFType::NC_UINT,
+            NetCDFTypeInstance::INT64S(_) => NetCDFType::NC_INT64,
+            NetCDFTypeInstance::UINT64S(_) => NetCDFType::NC_UINT64,
+        }
+    }
 }
 
 /// NetCDF data format types
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 #[allow(non_camel_case_types)]
 pub enum NetCDFType {
     NC_BYTE,
@@ -237,6 +338,11 @@ pub enum NetCDFType {
     NC_INT,
     NC_FLOAT,
     NC_DOUBLE,
+    NC_UBYTE,
+    NC_USHORT,
+    NC_UINT,
+    NC_INT64,
+    NC_UINT64,
 }
 
 impl NetCDFType {
@@ -249,6 +355,28 @@ impl NetCDFType {
             NetCDFType::NC_INT => 4,
             NetCDFType::NC_FLOAT => 4,
             NetCDFType::NC_DOUBLE => 8,
+            NetCDFType::NC_UBYTE => 1,
+            NetCDFType::NC_USHORT => 2,
+            NetCDFType::NC_UINT => 4,
+            NetCDFType::NC_INT64 => 8,
+            NetCDFType::NC_UINT64 => 8,
+        }
+    }
+
+    /// Get the default fill value of type as big endian bytes
+    pub fn fill_value(&self) -> Vec<u8> {
+        match self {
+            NetCDFType::NC_BYTE => vec![csts::FILL_BYTE],
+            NetCDFType::NC_CHAR => vec![csts::FILL_CHAR],
+            NetCDFType::NC_SHORT => csts::FILL_SHORT.to_be_bytes().to_vec(),
+            NetCDFType::NC_INT => csts::FILL_INT.to_be_bytes().to_vec(),
+            NetCDFType::NC_FLOAT => csts::FILL_FLOAT.to_be_bytes().to_vec(),
+            NetCDFType::NC_DOUBLE => csts::FILL_DOUBLE.to_be_bytes().to_vec(),
+            NetCDFType::NC_UBYTE => vec![csts::FILL_UBYTE],
+            NetCDFType::NC_USHORT => csts::FILL_USHORT.to_be_bytes().to_vec(),
+            NetCDFType::NC_UINT => csts::FILL_UINT.to_be_bytes().to_vec(),
+            NetCDFType::NC_INT64 => csts::FILL_INT64.to_be_bytes().to_vec(),
+            NetCDFType::NC_UINT64 => csts::FILL_UINT64.to_be_bytes().to_vec(),
         }
     }
 }
@@ -263,18 +391,51 @@ pub fn nc_type(i: &[u8]) -> HSEResult<&[u8], NetCDFType> {
         csts::NC_INT => Ok((i, NetCDFType::NC_INT)),
         csts::NC_FLOAT => Ok((i, NetCDFType::NC_FLOAT)),
         csts::NC_DOUBLE => Ok((i, NetCDFType::NC_DOUBLE)),
+        csts::NC_UBYTE => Ok((i, NetCDFType::NC_UBYTE)),
+        csts::NC_USHORT => Ok((i, NetCDFType::NC_USHORT)),
+        csts::NC_UINT => Ok((i, NetCDFType::NC_UINT)),
+        csts::NC_INT64 => Ok((i, NetCDFType::NC_INT64)),
+        csts::NC_UINT64 => Ok((i, NetCDFType::NC_UINT64)),
         _ => Err(nom::Err::Error(HSE::UnknownNetCDFType(o as usize))),
     }
 }
 
 /// Parse number of elements [atomic]
-pub fn nelems(i: &[u8]) -> HSEResult<&[u8], u32> {
-    non_neg(i)
+pub fn nelems(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], u64> {
+    non_neg(i, version)
+}
+
+/// Parse non negative numbers (64-bit for CDF-5) [atomic]
+pub fn non_neg(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], u64> {
+    match version {
+        NetCDFVersion::Data64 => be_u64(i),
+        _ => {
+            let (i, o) = be_u32(i)?;
+            Ok((i, o as u64))
+        }
+    }
+}
+
+/// Parse dimension id of a variable [atomic]
+pub fn dimension_id(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], u32> {
+    let (i, o) = non_neg(i, version)?;
+    Ok((i, o as u32))
+}
+
+/// Parse variable start location (64-bit for 64-bit offset and CDF-5) [atomic]
+pub fn offset(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], u64> {
+    match version {
+        NetCDFVersion::Classic => {
+            let (i, o) = be_u32(i)?;
+            Ok((i, o as u64))
+        }
+        _ => be_u64(i),
+    }
 }
 
-/// Parse non negative numbers [atomic]
-pub fn non_neg(i: &[u8]) -> HSEResult<&[u8], u32> {
-    be_u32(i)
+/// Parse byte [atomic]
+pub fn byte(i: &[u8]) -> HSEResult<&[u8], i8> {
+    be_i8(i)
 }
 
 /// Parse float [atomic]
@@ -297,13 +458,33 @@ pub fn double(i: &[u8]) -> HSEResult<&[u8], f64> {
     be_f64(i)
 }
 
+/// Parse unsigned short [atomic]
+pub fn ushort(i: &[u8]) -> HSEResult<&[u8], u16> {
+    be_u16(i)
+}
+
+/// Parse unsigned integer [atomic]
+pub fn uinteger(i: &[u8]) -> HSEResult<&[u8], u32> {
+    nom::number::complete::be_u32(i)
+}
+
+/// Parse 64-bit integer [atomic]
+pub fn int64(i: &[u8]) -> HSEResult<&[u8], i64> {
+    be_i64(i)
+}
+
+/// Parse unsigned 64-bit integer [atomic]
+pub fn uint64(i: &[u8]) -> HSEResult<&[u8], u64> {
+    nom::number::complete::be_u64(i)
+}
+
 /// Parse dimension length [atomic]
-pub fn dim_length(i: &[u8]) -> HSEResult<&[u8], u32> {
-    non_neg(i)
+pub fn dim_length(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], u64> {
+    non_neg(i, version)
 }
 
 /// Calculate padding to the next 4-byte boundary
-fn padding(count: u32) -> u8 {
+fn padding(count: u64) -> u8 {
     let pad = 4 - (count % 4);
     match pad {
         4 => 0,
@@ -312,13 +493,13 @@ fn padding(count: u32) -> u8 {
 }
 
 /// Parse the name of an element (dimension, variable, or attribute) [combined]
-pub fn name(i: &[u8]) -> HSEResult<&[u8], &str> {
-    let (i, count) = be_u32(i)?;
+pub fn name(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], &str> {
+    let (i, count) = nelems(i, version)?;
     let (i, name) = nom::bytes::streaming::take(count as usize)(i)?;
 
     // names are padded to the next 4-byte boundary
     let drop = padding(count);
-    let (i, _) = nom::bytes::streaming::take(drop as u8)(i)?;
+    let (i, _) = nom::bytes::streaming::take(drop)(i)?;
 
     match std::str::from_utf8(name) {
         Ok(name) => Ok((i, name)),
@@ -345,18 +526,22 @@ pub fn zero(i: &[u8]) -> HSEResult<&[u8], bool> {
 }
 
 /// Parse an absent list [combined]
-pub fn absent(i: &[u8]) -> HSEResult<&[u8], ListType> {
-    let (i, _) = nom::sequence::tuple((zero, zero))(i)?;
-    Ok((i, ListType::Absent))
+pub fn absent(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], ListType> {
+    let (i, _) = zero(i)?;
+    let (i, o) = non_neg(i, version)?;
+    match o {
+        0 => Ok((i, ListType::Absent)),
+        _ => Err(nom::Err::Error(HSE::NonZeroValue(o as u32))),
+    }
 }
 
 /// Parse upcoming list type [atomic]
-pub fn list_type(i: &[u8]) -> HSEResult<&[u8], ListType> {
+pub fn list_type(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], ListType> {
     let (i, o) = be_u32(i)?;
     match o {
         csts::ZERO => {
-            let (i, o) = be_u32(i)?;
-            if o == csts::ZERO {
+            let (i, o) = non_neg(i, version)?;
+            if o == 0 {
                 Ok((i, ListType::Absent))
             } else {
                 Err(nom::Err::Error(HSE::UnsupportedZeroListType))
@@ -370,27 +555,44 @@ pub fn list_type(i: &[u8]) -> HSEResult<&[u8], ListType> {
 }
 
 /// Length of record dimension
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum NumberOfRecords {
     NonNegative(csts::NON_NEG),
     Streaming,
 }
 
 /// Parse length of record dimension [atomic]
-pub fn number_of_records(i: &[u8]) -> HSEResult<&[u8], NumberOfRecords> {
+pub fn number_of_records(i: &[u8], version: NetCDFVersion) -> HSEResult<&[u8], NumberOfRecords> {
     // netCDF3 uses big endian, netCDF4 needs to be checked
-    let (i, o) = be_u32(i)?;
-    match o {
-        csts::STREAMING => Ok((i, NumberOfRecords::Streaming)),
-        _ => Ok((i, NumberOfRecords::NonNegative(o))),
+    match version {
+        NetCDFVersion::Data64 => {
+            let (i, o) = be_u64(i)?;
+            match o {
+                csts::STREAMING64 => Ok((i, NumberOfRecords::Streaming)),
+                _ => Ok((i, NumberOfRecords::NonNegative(o))),
+            }
+        }
+        _ => {
+            let (i, o) = be_u32(i)?;
+            match o {
+                csts::STREAMING => Ok((i, NumberOfRecords::Streaming)),
+                _ => Ok((i, NumberOfRecords::NonNegative(o as csts::NON_NEG))),
+            }
+        }
     }
 }
 
 /// Supported NetCDF versions
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum NetCDFVersion {
+    /// CDF-1
     Classic,
+    /// CDF-2
     Offset64,
+    /// CDF-5
+    Data64,
+    /// netCDF-4, stored as HDF5
+    NetCDF4,
 }
 
 /// Parse a single byte [atomic]
@@ -409,12 +611,23 @@ pub fn nc_version(i: &[u8]) -> HSEResult<&[u8], NetCDFVersion> {
     match o {
         1 => Ok((i, NetCDFVersion::Classic)),
         2 => Ok((i, NetCDFVersion::Offset64)),
+        5 => Ok((i, NetCDFVersion::Data64)),
         _ => Err(nom::Err::Error(HSE::UnsupportedNetCDFVersion)),
     }
 }
 
 /// Parse NetCDF magic bytes [combined]
 pub fn magic(i: &[u8]) -> HSEResult<&[u8], NetCDFVersion> {
+    // the HDF5 signature is longer than the netCDF magic bytes
+    let n = i.len().min(csts::HDF5_SIGNATURE.len());
+    if n > 0 && i[..n] == csts::HDF5_SIGNATURE[..n] {
+        return match n == csts::HDF5_SIGNATURE.len() {
+            true => Err(nom::Err::Failure(HSE::NetCDF4NotSupported)),
+            false => Err(nom::Err::Incomplete(nom::Needed::new(
+                csts::HDF5_SIGNATURE.len() - n,
+            ))),
+        };
+    }
     let (i, _) = initials(i)?;
     let (i, v) = nc_version(i)?;
     Ok((i, v))
@@ -440,15 +653,15 @@ mod tests {
         reader.read_to_end(&mut i).unwrap();
         let (i, o) = initials(&i[..]).unwrap();
         assert_eq!(o, b"CDF");
-        let (i, o) = nc_version(i).unwrap();
-        assert_eq!(o, NetCDFVersion::Classic);
-        let (i, o) = number_of_records(i).unwrap();
+        let (i, v) = nc_version(i).unwrap();
+        assert_eq!(v, NetCDFVersion::Classic);
+        let (i, o) = number_of_records(i, v).unwrap();
         assert_eq!(o, NumberOfRecords::NonNegative(0));
-        let (i, o) = list_type(i).unwrap();
+        let (i, o) = list_type(i, v).unwrap();
         assert_eq!(o, ListType::Absent); // No dim list
-        let (i, o) = list_type(i).unwrap();
+        let (i, o) = list_type(i, v).unwrap();
         assert_eq!(o, ListType::Absent); // No atrr list
-        let (i, o) = list_type(i).unwrap();
+        let (i, o) = list_type(i, v).unwrap();
         assert_eq!(o, ListType::Absent); // No var list
         let (i, o) = eof(i).unwrap();
         assert!(o); // EOF
@@ -462,16 +675,16 @@ mod tests {
         reader.read_to_end(&mut i).unwrap();
         let (i, o) = initials(&i[..]).unwrap();
         assert_eq!(o, b"CDF");
-        let (i, o) = nc_version(i).unwrap();
-        assert_eq!(o, NetCDFVersion::Classic);
-        let (i, o) = number_of_records(i).unwrap();
+        let (i, v) = nc_version(i).unwrap();
+        assert_eq!(v, NetCDFVersion::Classic);
+        let (i, o) = number_of_records(i, v).unwrap();
         assert_eq!(o, NumberOfRecords::NonNegative(0));
-        let (i, o) = list_type(i).unwrap();
+        let (i, o) = list_type(i, v).unwrap();
         assert_eq!(o, ListType::DimensionList);
-        let (i, o) = dimension_list(i).unwrap();
-        let d = vec![NetCDFDimension::new("dim".to_string(), 5)];
+        let (i, o) = dimension_list(i, v).unwrap();
+        let d = [NetCDFDimension::new("dim".to_string(), 5)];
         assert_eq!(o[&0], d[0]);
-        let (i, o) = list_type(i).unwrap();
+        let (i, o) = list_type(i, v).unwrap();
         assert_eq!(o, ListType::Absent);
     }
 
@@ -483,16 +696,16 @@ mod tests {
         reader.read_to_end(&mut i).unwrap();
         let (i, o) = initials(&i[..]).unwrap();
         assert_eq!(o, b"CDF");
-        let (i, o) = nc_version(i).unwrap();
-        assert_eq!(o, NetCDFVersion::Classic);
-        let (i, o) = number_of_records(i).unwrap();
+        let (i, v) = nc_version(i).unwrap();
+        assert_eq!(v, NetCDFVersion::Classic);
+        let (i, o) = number_of_records(i, v).unwrap();
         assert_eq!(o, NumberOfRecords::NonNegative(0));
-        let (i, o) = list_type(i).unwrap();
+        let (i, o) = list_type(i, v).unwrap();
         assert_eq!(o, ListType::DimensionList);
-        let (i, o) = dimension_list(i).unwrap();
-        let d = vec![NetCDFDimension::new("dim1".to_string(), 10_000)];
+        let (i, o) = dimension_list(i, v).unwrap();
+        let d = [NetCDFDimension::new("dim1".to_string(), 10_000)];
         assert_eq!(o[&0], d[0]);
-        let (i, o) = list_type(i).unwrap();
+        let (i, o) = list_type(i, v).unwrap();
         assert_eq!(o, ListType::Absent);
     }
 
@@ -506,12 +719,12 @@ mod tests {
         assert_eq!(o, b"CDF");
         let (i, v) = nc_version(i).unwrap();
         assert_eq!(v, NetCDFVersion::Classic);
-        let (i, o) = number_of_records(i).unwrap();
+        let (i, o) = number_of_records(i, v).unwrap();
         assert_eq!(o, NumberOfRecords::NonNegative(1));
-        let (i, o) = list_type(i).unwrap();
+        let (i, o) = list_type(i, v).unwrap();
         assert_eq!(o, ListType::DimensionList);
-        let (i, o) = dimension_list(i).unwrap();
-        let d = vec![
+        let (i, o) = dimension_list(i, v).unwrap();
+        let d = [
             NetCDFDimension::new("lat".to_string(), 128),
             NetCDFDimension::new("lon".to_string(), 256),
             NetCDFDimension::new("bnds".to_string(), 2),
@@ -521,9 +734,9 @@ mod tests {
         for i in 0..5 {
             assert_eq!(o[&i], d[i]);
         }
-        let (i, o) = list_type(i).unwrap();
+        let (i, o) = list_type(i, v).unwrap();
         assert_eq!(o, ListType::AttributeList);
-        let (i, o) = attribute_list(i).unwrap();
+        let (i, o) = attribute_list(i, v).unwrap();
         assert_eq!(o.len(), 18);
         let a = NetCDFAttribute::new(
             "CVS_Id".to_string(),
@@ -531,7 +744,7 @@ mod tests {
             vec![36, 73, 100, 36],
         );
         assert_eq!(o["CVS_Id"], a);
-        let (i, o) = list_type(i).unwrap();
+        let (i, o) = list_type(i, v).unwrap();
         assert_eq!(o, ListType::VariableList);
         let (i, o) = variable_list(i, v).unwrap();
         assert_eq!(o["area"].name, "area");
@@ -552,12 +765,12 @@ mod tests {
         assert_eq!(o, b"CDF");
         let (i, v) = nc_version(i).unwrap();
         assert_eq!(v, NetCDFVersion::Offset64);
-        let (i, o) = number_of_records(i).unwrap();
+        let (i, o) = number_of_records(i, v).unwrap();
         assert_eq!(o, NumberOfRecords::NonNegative(1));
-        let (i, o) = list_type(i).unwrap();
+        let (i, o) = list_type(i, v).unwrap();
         assert_eq!(o, ListType::DimensionList);
-        let (i, o) = dimension_list(i).unwrap();
-        let d = vec![
+        let (i, o) = dimension_list(i, v).unwrap();
+        let d = [
             NetCDFDimension::new("time".to_string(), 0), // TODO Should this be the length in NoR?
             NetCDFDimension::new("lat".to_string(), 128),
             NetCDFDimension::new("lon".to_string(), 256),
@@ -567,9 +780,9 @@ mod tests {
         for i in 0..5 {
             assert_eq!(o[&i], d[i]);
         }
-        let (i, o) = list_type(i).unwrap();
+        let (i, o) = list_type(i, v).unwrap();
         assert_eq!(o, ListType::AttributeList);
-        let (i, o) = attribute_list(i).unwrap();
+        let (i, o) = attribute_list(i, v).unwrap();
         assert_eq!(o.len(), 18);
         let a = NetCDFAttribute::new(
             "CVS_Id".to_string(),
@@ -577,19 +790,81 @@ mod tests {
             vec![36, 73, 100, 36],
         );
         assert_eq!(o["CVS_Id"], a);
-        let (i, o) = list_type(i).unwrap();
+        let (i, o) = list_type(i, v).unwrap();
         assert_eq!(o, ListType::VariableList);
         let (i, o) = variable_list(i, v).unwrap();
         // TODO: Read about fill values
     }
 
+    #[test]
+    fn file_cdf5() {
+        let file = File::open("assets/cdf5.nc").unwrap();
+        let mut reader = BufReader::new(file);
+        let mut i = Vec::new();
+        reader.read_to_end(&mut i).unwrap();
+        let (i, v) = magic(&i[..]).unwrap();
+        assert_eq!(v, NetCDFVersion::Data64);
+        let (i, o) = number_of_records(i, v).unwrap();
+        assert_eq!(o, NumberOfRecords::NonNegative(2));
+        let (i, o) = list_type(i, v).unwrap();
+        assert_eq!(o, ListType::DimensionList);
+        let (i, o) = dimension_list(i, v).unwrap();
+        assert_eq!(o[&0], NetCDFDimension::new("time".to_string(), 0));
+        assert_eq!(o[&1], NetCDFDimension::new("x".to_string(), 3));
+        let (i, o) = list_type(i, v).unwrap();
+        assert_eq!(o, ListType::AttributeList);
+        let (i, o) = attribute_list(i, v).unwrap();
+        assert_eq!(o["title"].as_string(), Some("CDF-5 example".to_string()));
+        let (i, o) = list_type(i, v).unwrap();
+        assert_eq!(o, ListType::VariableList);
+        let (i, o) = variable_list(i, v).unwrap();
+        assert_eq!(o.len(), 5);
+        assert_eq!(o["x"].attributes().as_ref().unwrap().len(), 1);
+        assert_eq!(o["b"].nc_type, NetCDFType::NC_UBYTE);
+        assert_eq!(o["big"].nc_type, NetCDFType::NC_INT64);
+        assert_eq!(o["big"].begin, 500);
+        assert_eq!(o["v"].nc_type, NetCDFType::NC_USHORT);
+        assert_eq!(o["v"].dims, vec![0, 1]);
+        assert_eq!(o["v"].vsize, 8);
+        assert_eq!(o["v"].begin, 532);
+        assert_eq!(i.len(), 84); // data section
+    }
+
+    #[test]
+    fn test_multi_valued_attributes() {
+        let data = [0x3f, 0x80, 0, 0, 0x40, 0, 0, 0, 0x40, 0x40, 0, 0];
+        let a = NetCDFAttribute::new(
+            "valid_range".to_string(),
+            NetCDFType::NC_FLOAT,
+            data.to_vec(),
+        );
+        assert_eq!(a.value(), &NetCDFTypeInstance::FLOATS(vec![1.0, 2.0, 3.0]));
+        assert_eq!(a.as_f64_vec(), Some(vec![1.0, 2.0, 3.0]));
+        assert_eq!(a.as_i64_vec(), None);
+        let a = NetCDFAttribute::new(
+            "flag_values".to_string(),
+            NetCDFType::NC_BYTE,
+            vec![0xFF, 1],
+        );
+        assert_eq!(a.value(), &NetCDFTypeInstance::BYTES(vec![-1, 1]));
+        assert_eq!(a.as_i64_vec(), Some(vec![-1, 1]));
+        let a = NetCDFAttribute::new("units".to_string(), NetCDFType::NC_CHAR, b"K".to_vec());
+        assert_eq!(a.as_f64_vec(), None);
+    }
+
     #[test]
     fn test_size() {
         let data = [0x0, 0x0, 0x0, 0xAu8];
-        let (_, o) = number_of_records(&data).unwrap();
+        let (_, o) = number_of_records(&data, NetCDFVersion::Classic).unwrap();
         assert_eq!(o, NumberOfRecords::NonNegative(10));
         let data = [0xFF, 0xFF, 0xFF, 0xFFu8];
-        let (_, o) = number_of_records(&data).unwrap();
+        let (_, o) = number_of_records(&data, NetCDFVersion::Classic).unwrap();
+        assert_eq!(o, NumberOfRecords::Streaming);
+        let data = [0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0xAu8];
+        let (_, o) = number_of_records(&data, NetCDFVersion::Data64).unwrap();
+        assert_eq!(o, NumberOfRecords::NonNegative(10));
+        let data = [0xFFu8; 8];
+        let (_, o) = number_of_records(&data, NetCDFVersion::Data64).unwrap();
         assert_eq!(o, NumberOfRecords::Streaming)
     }
 
@@ -622,15 +897,18 @@ mod tests {
         assert_eq!(o, NetCDFVersion::Classic);
         let (_, o) = nc_version(&[2u8]).unwrap();
         assert_eq!(o, NetCDFVersion::Offset64);
+        let (_, o) = nc_version(&[5u8]).unwrap();
+        assert_eq!(o, NetCDFVersion::Data64);
     }
 
     #[test]
     fn test_nctypes() {
-        let types: [u8; 44] = [
+        let types: [u8; 64] = [
             0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 2, 0,
-            0, 0, 5, 0, 0, 0, 6, 0, 0, 0, 4, 0, 0, 0, 4,
+            0, 0, 5, 0, 0, 0, 6, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 7, 0, 0, 0, 8, 0, 0, 0, 9, 0, 0,
+            0, 10, 0, 0, 0, 11,
         ];
-        let expected: [NetCDFType; 11] = [
+        let expected: [NetCDFType; 16] = [
             NetCDFType::NC_SHORT,
             NetCDFType::NC_CHAR,
             NetCDFType::NC_BYTE,
@@ -642,6 +920,11 @@ mod tests {
             NetCDFType::NC_DOUBLE,
             NetCDFType::NC_INT,
             NetCDFType::NC_INT,
+            NetCDFType::NC_UBYTE,
+            NetCDFType::NC_USHORT,
+            NetCDFType::NC_UINT,
+            NetCDFType::NC_INT64,
+            NetCDFType::NC_UINT64,
         ];
         for (factor, exp) in expected.iter().enumerate() {
             let (_, o) = nc_type(&types[(factor * 4)..]).unwrap();
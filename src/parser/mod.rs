@@ -2,8 +2,11 @@
 //!
 //! # Parser
 //! Main parsing module
+use crate::constants_and_types as csts;
 use crate::error::HeadScratcherError as HSE;
-use crate::utils::{calc_seek, product_vector};
+use crate::selection::Selection;
+use crate::utils::{calc_runs, calc_seek, product_vector};
+use crate::writer;
 use components::{
     self as cp, AttributeHM, DimensionHM, ListType, NetCDFVersion, NumberOfRecords, VariableHM,
 };
@@ -11,12 +14,12 @@ use nom::IResult;
 use std::collections::HashMap;
 use std::io::{Read, Seek, SeekFrom};
 pub mod components;
+pub mod stream;
 pub type HSEResult<I, O> = IResult<I, O, HSE<I>>;
 pub type SeeksHM = HashMap<String, Vec<usize>>;
-const BUFFER: usize = 4096; // bytes
 
 /// NetCDF file format
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct NetCDFHeader {
     pub version: NetCDFVersion,
     pub nor: NumberOfRecords,
@@ -24,6 +27,8 @@ pub struct NetCDFHeader {
     pub dims: Option<DimensionHM>,
     pub vars: Option<VariableHM>,
     pub seeks: Option<SeeksHM>,
+    /// Size of one record in bytes (summed over all record variables)
+    pub recsize: usize,
 }
 
 impl NetCDFHeader {
@@ -34,6 +39,7 @@ impl NetCDFHeader {
         dims: Option<DimensionHM>,
         vars: Option<VariableHM>,
         seeks: Option<SeeksHM>,
+        recsize: usize,
     ) -> Self {
         NetCDFHeader {
             version,
@@ -42,6 +48,7 @@ impl NetCDFHeader {
             dims,
             vars,
             seeks,
+            recsize,
         }
     }
     pub fn update_buffer<F: Seek + Read>(
@@ -52,69 +59,269 @@ impl NetCDFHeader {
         buffer: &mut [u8],
     ) -> Result<(), std::io::Error> {
         let seek_pos = match (&self.vars, &self.seeks) {
-            (Some(v), Some(s)) => calc_seek(v, s, var, start),
+            (Some(v), Some(s)) => calc_seek(v, s, self.recsize, var, start),
             (_, _) => None,
         };
         file.seek(SeekFrom::Start(seek_pos.unwrap()))?;
         file.read_exact(buffer)
     }
 
-    pub fn from_file<F: Read>(file: &mut F) -> Result<NetCDFHeader, HSE<String>> {
-        let mut buf: Vec<u8> = vec![0; BUFFER];
-        let mut head: Vec<u8> = Vec::new();
-        loop {
-            let count = file.read(&mut buf)?;
-            head.append(&mut buf[..count].to_vec());
-            match header(&head) {
-                Ok((_, h)) => return Ok(h),
-                Err(nom::Err::Incomplete(nom::Needed::Size(_))) => continue,
-                Err(nom::Err::Failure(err)) => {
-                    return match err {
-                        HSE::NomError(_, _) => Err(HSE::InvalidFile),
-                        e => Err(e.cast().unwrap()) // cannot be None as the only case this can happen is covered above
-                    }
-                },
-                Err(nom::Err::Error(err)) => {
-                    return match err {
-                        HSE::NomError(_, _) => Err(HSE::InvalidFile),
-                        e => Err(e.cast().unwrap()) // cannot be None as the only case this can happen is covered above
-                    }
-                },
-                Err(err) => panic!("Other error: {:?}", err),
+    /// Contiguous byte runs `(offset, length)` covering a selection of a variable
+    pub fn slab_runs(
+        &self,
+        var: String,
+        selection: &Selection,
+    ) -> Result<Vec<(u64, usize)>, HSE<String>> {
+        self.check_selection(&var, selection)?;
+        let (v, s) = match (&self.vars, &self.seeks) {
+            (Some(v), Some(s)) => (v, s),
+            (_, _) => return Err(HSE::NoVariablesInFile),
+        };
+        calc_runs(v, s, self.recsize, var.clone(), selection).ok_or(HSE::VariableNotFound(var))
+    }
+
+    /// Check a selection against the dimensions of a variable
+    pub fn check_selection(&self, var: &str, selection: &Selection) -> Result<(), HSE<String>> {
+        selection.validate(&self.lengths(self.variable(var)?))
+    }
+
+    /// Bounds of the indices of a variable's dimensions, using the number of
+    /// records for the unlimited dimension
+    ///
+    /// Record indices of a streaming file are not bounded by the header.
+    fn lengths(&self, var: &cp::NetCDFVariable) -> Vec<usize> {
+        let dims = match &self.dims {
+            Some(d) => d,
+            None => return Vec::new(),
+        };
+        var.dims
+            .iter()
+            .map(|d| self.length(&dims[&(*d as usize)]).unwrap_or(usize::MAX))
+            .collect()
+    }
+
+    /// Length of a dimension, the number of records for the unlimited one
+    ///
+    /// The number of records of a streaming file is unknown to the header.
+    fn length(&self, dim: &cp::NetCDFDimension) -> Option<usize> {
+        match (dim.length, &self.nor) {
+            (0, NumberOfRecords::NonNegative(n)) => Some(*n as usize),
+            (0, NumberOfRecords::Streaming) => None,
+            (length, _) => Some(length),
+        }
+    }
+
+    /// Length of a dimension, failing for the record dimension of a streaming
+    /// file
+    fn known_length(&self, dim: &cp::NetCDFDimension) -> Result<usize, HSE<String>> {
+        self.length(dim)
+            .ok_or_else(|| HSE::UnknownNumberOfRecords(dim.name()))
+    }
+
+    /// Variable by name
+    pub fn variable(&self, name: &str) -> Result<&cp::NetCDFVariable, HSE<String>> {
+        match &self.vars {
+            Some(v) => v
+                .get(name)
+                .ok_or_else(|| HSE::VariableNotFound(name.to_string())),
+            None => Err(HSE::NoVariablesInFile),
+        }
+    }
+
+    /// Id of a dimension by name
+    pub fn dimension_id(&self, name: &str) -> Result<usize, HSE<String>> {
+        let dims = match &self.dims {
+            Some(d) => d,
+            None => return Err(HSE::NoDimensionsInFile),
+        };
+        match dims.iter().find(|(_, d)| d.name == name) {
+            Some((k, _)) => Ok(*k),
+            None => Err(HSE::CouldNotFindDimension(name.to_string())),
+        }
+    }
+
+    /// Dimension by name
+    pub fn dimension(&self, name: &str) -> Result<&cp::NetCDFDimension, HSE<String>> {
+        let id = self.dimension_id(name)?;
+        Ok(&self.dims.as_ref().unwrap()[&id])
+    }
+
+    /// Length of a dimension by name, the number of records for the unlimited one
+    ///
+    /// Fails with [`HSE::UnknownNumberOfRecords`] for the record dimension of a
+    /// streaming file.
+    pub fn dimension_length(&self, name: &str) -> Result<usize, HSE<String>> {
+        self.known_length(self.dimension(name)?)
+    }
+
+    /// Whether a dimension is the unlimited (record) dimension
+    pub fn is_unlimited(&self, name: &str) -> Result<bool, HSE<String>> {
+        Ok(self.dimension(name)?.length == 0)
+    }
+
+    /// Dimension names and lengths of a variable
+    ///
+    /// Fails like [`NetCDFHeader::dimension_length`] for record variables of a
+    /// streaming file.
+    pub fn variable_shape(&self, var: &str) -> Result<Vec<(String, usize)>, HSE<String>> {
+        let va = match &self.vars {
+            Some(v) => match v.get(var) {
+                Some(va) => va,
+                None => return Err(HSE::VariableNotFound(var.to_string())),
+            },
+            None => return Err(HSE::NoVariablesInFile),
+        };
+        let dims = match &self.dims {
+            Some(d) => d,
+            None => return Ok(Vec::new()),
+        };
+        va.dims
+            .iter()
+            .map(|d| {
+                let dim = &dims[&(*d as usize)];
+                Ok((dim.name(), self.known_length(dim)?))
+            })
+            .collect()
+    }
+
+    /// Serialize the header
+    pub fn to_bytes(&self) -> Result<Vec<u8>, HSE<String>> {
+        writer::header(self)
+    }
+
+    /// Work out the data layout of all variables
+    ///
+    /// Sets `vsize` and `begin` of every variable: fixed size variables follow
+    /// the header in declaration order, record variables follow them.
+    /// Record flags, record size and seek table are updated accordingly.
+    ///
+    /// As in netCDF, CDF-1 and CDF-2 only allow the last variable of the file
+    /// to be larger than 4 GiB, and CDF-1 offsets have to fit into 32 bits.
+    /// Other layouts fail with [`HSE::ExceedsFormat`], netCDF-4 headers with
+    /// [`HSE::NetCDF4NotSupported`].
+    pub fn layout(&mut self) -> Result<(), HSE<String>> {
+        let version = self.version;
+        if version == NetCDFVersion::NetCDF4 {
+            return Err(HSE::NetCDF4NotSupported);
+        }
+        let (vars, dims) = match (&mut self.vars, &self.dims) {
+            (Some(v), Some(d)) => (v, d),
+            (Some(v), None) => (v, &DimensionHM::new()),
+            (None, _) => return Ok(()),
+        };
+        mark_record_variables(vars, dims);
+        let mut sizes = HashMap::new();
+        for (n, v) in vars.iter_mut() {
+            let skip = if v.is_record() { 1 } else { 0 };
+            let len: usize = v.dims[skip..]
+                .iter()
+                .map(|d| dims[&(*d as usize)].length)
+                .product();
+            let vsize = (len * v.nc_type.extsize()).div_ceil(4) * 4;
+            // CDF-1 and CDF-2 store the size of very large variables as 2^32 - 1
+            v.vsize = match version {
+                NetCDFVersion::Data64 => vsize,
+                _ => vsize.min(u32::MAX as usize),
+            };
+            sizes.insert(n.clone(), vsize as u64);
+        }
+        let recsize = record_size(vars, dims);
+        let seeks = clc(vars, dims);
+
+        let mut begin = writer::header(self)?.len() as u64;
+        let vars = self.vars.as_mut().unwrap();
+        let mut order: Vec<String> = Vec::new();
+        for record in [false, true] {
+            order.extend(
+                vars.iter()
+                    .filter(|(_, v)| v.is_record() == record)
+                    .map(|(n, _)| n.clone()),
+            );
+        }
+        for (k, n) in order.iter().enumerate() {
+            let vsize = sizes[n];
+            if version != NetCDFVersion::Data64 && vsize > u32::MAX as u64 && k + 1 != order.len() {
+                let msg = format!("{} ({} bytes) is not the last variable", n, vsize);
+                return Err(HSE::ExceedsFormat(msg));
             }
+            if version == NetCDFVersion::Classic && begin > u32::MAX as u64 {
+                let msg = format!("{} starts at {}, beyond 32-bit offsets", n, begin);
+                return Err(HSE::ExceedsFormat(msg));
+            }
+            vars.get_mut(n).unwrap().begin = begin;
+            begin += vsize;
         }
+        self.recsize = recsize;
+        self.seeks = Some(seeks);
+        Ok(())
+    }
+
+    /// Parse the header at the start of `file`, reading nothing beyond it
+    ///
+    /// See [`stream::header`].
+    pub fn from_file<F: Read>(file: &mut F) -> Result<NetCDFHeader, HSE<String>> {
+        stream::header(file).map(|(h, _)| h)
+    }
+}
+
+/// Format of a file, told apart by its first bytes
+pub fn format(i: &[u8]) -> Result<NetCDFVersion, HSE<String>> {
+    if i.starts_with(csts::HDF5_SIGNATURE) {
+        return Ok(NetCDFVersion::NetCDF4);
+    }
+    match cp::magic(i) {
+        Ok((_, version)) => Ok(version),
+        Err(nom::Err::Error(HSE::UnsupportedNetCDFVersion)) => Err(HSE::UnsupportedNetCDFVersion),
+        Err(_) => Err(HSE::InvalidFile),
+    }
+}
+
+/// Error of a parser that failed on complete input
+pub(crate) fn failure(e: HSE<&[u8]>) -> HSE<String> {
+    match e {
+        HSE::NomError(_, _) => HSE::InvalidFile,
+        e => e.cast().unwrap(), // only NomError cannot be cast
     }
 }
 
+/// Format of a file, the file is rewound afterwards
+pub fn file_format<F: Read + Seek>(file: &mut F) -> Result<NetCDFVersion, HSE<String>> {
+    let mut magic = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.by_ref().take(8).read_to_end(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+    format(&magic)
+}
+
 pub fn header(i: &[u8]) -> HSEResult<&[u8], NetCDFHeader> {
     // Organisational
-    let (i, (_, version, kind)) =
-        nom::sequence::tuple((cp::initials, cp::nc_version, cp::number_of_records))(i)?;
+    let (i, version) = cp::magic(i)?;
+    let (i, kind) = cp::number_of_records(i, version)?;
 
     // Dimension list
-    let (i, d) = cp::list_type(i)?;
+    let (i, d) = cp::list_type(i, version)?;
     let (i, dims) = match d {
         ListType::Absent => (i, None),
         ListType::DimensionList => {
-            let (i, d) = cp::dimension_list(i)?;
+            let (i, d) = cp::dimension_list(i, version)?;
             (i, Some(d))
         }
         _ => Err(nom::Err::Error(HSE::EmptyError))?,
     };
 
     // Attribute list
-    let (i, d) = cp::list_type(i)?;
+    let (i, d) = cp::list_type(i, version)?;
     let (i, attrs) = match d {
         ListType::Absent => (i, None),
         ListType::AttributeList => {
-            let (i, d) = cp::attribute_list(i)?;
+            let (i, d) = cp::attribute_list(i, version)?;
             (i, Some(d))
         }
         _ => Err(nom::Err::Error(HSE::EmptyError))?,
     };
 
     // Variable list
-    let (i, d) = cp::list_type(i)?;
+    let (i, d) = cp::list_type(i, version)?;
     let (i, vars) = match d {
         ListType::Absent => (i, None),
         ListType::VariableList => {
@@ -124,14 +331,63 @@ pub fn header(i: &[u8]) -> HSEResult<&[u8], NetCDFHeader> {
         _ => Err(nom::Err::Error(HSE::EmptyError))?,
     };
 
+    Ok((i, assemble(version, kind, attrs, dims, vars)))
+}
+
+/// Header of the parsed lists, with record variables and seeks worked out
+fn assemble(
+    version: NetCDFVersion,
+    nor: NumberOfRecords,
+    attrs: Option<AttributeHM>,
+    dims: Option<DimensionHM>,
+    mut vars: Option<VariableHM>,
+) -> NetCDFHeader {
+    // Record variables
+    let recsize = match (&mut vars, &dims) {
+        (Some(v), Some(d)) => {
+            mark_record_variables(v, d);
+            record_size(v, d)
+        }
+        (_, _) => 0,
+    };
+
     // Seek calculation
     let seeks = calculate_seeks(&vars, &dims);
-    let result = NetCDFHeader::new(version, kind, attrs, dims, vars, seeks);
+    NetCDFHeader::new(version, nor, attrs, dims, vars, seeks, recsize)
+}
+
+/// Flag all variables whose leading dimension is the unlimited one
+fn mark_record_variables(vars: &mut VariableHM, dims: &DimensionHM) {
+    for v in vars.values_mut() {
+        v.record = match v.dims.first() {
+            Some(d) => dims[&(*d as usize)].length == 0,
+            None => false,
+        };
+    }
+}
 
-    Ok((i, result))
+/// Size of one record in bytes
+///
+/// Record variables are interleaved, one slab per variable and record. If
+/// there is only one record variable its slabs are not padded.
+fn record_size(vars: &VariableHM, dims: &DimensionHM) -> usize {
+    let records: Vec<&cp::NetCDFVariable> = vars.values().filter(|v| v.is_record()).collect();
+    match records.as_slice() {
+        [v] => {
+            let len: usize = v.dims[1..]
+                .iter()
+                .map(|d| dims[&(*d as usize)].length)
+                .product();
+            len * v.nc_type.extsize()
+        }
+        _ => records.iter().map(|v| v.vsize()).sum(),
+    }
 }
 
-fn calculate_seeks(vars: &Option<VariableHM>, dims: &Option<DimensionHM>) -> Option<SeeksHM> {
+pub(crate) fn calculate_seeks(
+    vars: &Option<VariableHM>,
+    dims: &Option<DimensionHM>,
+) -> Option<SeeksHM> {
     match (vars, dims) {
         (Some(v), Some(d)) => Some(clc(v, d)),
         (_, _) => None,
@@ -145,7 +401,7 @@ fn clc(vars: &VariableHM, dims: &DimensionHM) -> SeeksHM {
         for d in v.dims.iter() {
             seeks.push(dims[&(*d as usize)].length)
         }
-        let mut seeks = product_vector(&seeks, false);
+        let mut seeks = product_vector(&seeks, v.is_record());
         seeks.push(1);
         seeks.remove(0);
         result.insert(k.clone(), seeks);
@@ -192,6 +448,258 @@ mod tests {
         let h = NetCDFHeader::from_file(&mut file).unwrap();
     }
 
+    #[test]
+    fn file_cdf5() {
+        let filename = "assets/cdf5.nc".to_string();
+        let mut file = File::open(filename).unwrap();
+        let h = NetCDFHeader::from_file(&mut file).unwrap();
+        assert_eq!(h.version, NetCDFVersion::Data64);
+        assert_eq!(h.nor, NumberOfRecords::NonNegative(2));
+        assert_eq!(h.seeks.unwrap()["v"], vec![3, 1]);
+    }
+
+    #[test]
+    fn test_format() {
+        use NetCDFVersion::*;
+        let formats = [
+            ("sresa1b_ncar_ccsm3-example.nc", Ok(Classic)),
+            ("sresa1b_ncar_ccsm3-example.3_nc64.nc", Ok(Offset64)),
+            ("cdf5.nc", Ok(Data64)),
+            ("sresa1b_ncar_ccsm3-example.4.nc", Ok(NetCDF4)),
+            ("test_hgroups.nc", Ok(NetCDF4)),
+            ("README.md", Err(HSE::InvalidFile)),
+        ];
+        for (filename, expected) in formats {
+            let mut i = vec![0u8; 8];
+            let mut file = File::open(format!("assets/{}", filename)).unwrap();
+            file.read_exact(&mut i).unwrap();
+            assert_eq!(format(&i), expected);
+        }
+        assert_eq!(format(b"CDF\x03"), Err(HSE::UnsupportedNetCDFVersion));
+
+        let mut file = File::open("assets/test_hgroups.nc").unwrap();
+        let e = NetCDFHeader::from_file(&mut file).unwrap_err();
+        assert_eq!(e, HSE::NetCDF4NotSupported);
+    }
+
+    #[test]
+    fn test_dimension_lookup() {
+        let mut file = File::open("assets/cdf5.nc").unwrap();
+        let h = NetCDFHeader::from_file(&mut file).unwrap();
+        assert_eq!(h.dimension_id("x").unwrap(), 1);
+        assert_eq!(h.dimension("x").unwrap().length, 3);
+        assert_eq!(h.dimension_length("time").unwrap(), 2);
+        assert!(h.is_unlimited("time").unwrap());
+        assert!(!h.is_unlimited("x").unwrap());
+        let shape = h.variable_shape("v").unwrap();
+        assert_eq!(shape, vec![("time".to_string(), 2), ("x".to_string(), 3)]);
+        assert_eq!(h.variable_shape("b").unwrap(), vec![("x".to_string(), 3)]);
+        assert!(matches!(
+            h.dimension("y"),
+            Err(HSE::CouldNotFindDimension(_))
+        ));
+        assert!(matches!(
+            h.variable_shape("y"),
+            Err(HSE::VariableNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_declaration_order() {
+        let mut file = File::open("assets/cdf5.nc").unwrap();
+        let h = NetCDFHeader::from_file(&mut file).unwrap();
+        let names: Vec<&String> = h.vars.as_ref().unwrap().keys().collect();
+        assert_eq!(names, vec!["x", "b", "big", "t", "v"]);
+        let dims: Vec<String> = h.dims.unwrap().values().map(|d| d.name()).collect();
+        assert_eq!(dims, vec!["time", "x"]);
+
+        // variables are laid out in declaration order, record variables last
+        let mut file = File::open("assets/sresa1b_ncar_ccsm3-example.nc").unwrap();
+        let h = NetCDFHeader::from_file(&mut file).unwrap();
+        for record in [false, true] {
+            let begins: Vec<u64> = h
+                .vars
+                .as_ref()
+                .unwrap()
+                .values()
+                .filter(|v| v.is_record() == record)
+                .map(|v| v.begin)
+                .collect();
+            assert!(begins.windows(2).all(|w| w[0] < w[1]));
+        }
+    }
+
+    #[test]
+    fn test_record_variables() {
+        let filename = "assets/cdf5.nc".to_string();
+        let mut file = File::open(filename).unwrap();
+        let h = NetCDFHeader::from_file(&mut file).unwrap();
+        let vars = h.vars.as_ref().unwrap();
+        assert!(vars["t"].is_record());
+        assert!(vars["v"].is_record());
+        assert!(!vars["x"].is_record());
+        assert_eq!(h.recsize, 16);
+        let mut buffer = vec![0u8; 2];
+        h.update_buffer("v".to_string(), &[1, 2], &mut file, &mut buffer)
+            .unwrap();
+        assert_eq!(buffer, vec![0, 5]);
+        let mut buffer = vec![0u8; 8];
+        h.update_buffer("t".to_string(), &[1], &mut file, &mut buffer)
+            .unwrap();
+        assert_eq!(buffer, vec![0, 0, 0, 0, 0, 0, 0, 20]);
+    }
+
+    #[test]
+    fn test_single_record_variable() {
+        let mut dims = DimensionHM::new();
+        dims.insert(0, cp::NetCDFDimension::new("time".to_string(), 0));
+        dims.insert(1, cp::NetCDFDimension::new("x".to_string(), 3));
+        let mut vars = VariableHM::new();
+        let var = cp::NetCDFVariable::new(
+            "v".to_string(),
+            vec![0, 1],
+            None,
+            cp::NetCDFType::NC_SHORT,
+            8,
+            0,
+        );
+        vars.insert("v".to_string(), var);
+        mark_record_variables(&mut vars, &dims);
+        assert_eq!(record_size(&vars, &dims), 6);
+    }
+
+    #[test]
+    fn test_layout() {
+        let filename = "assets/sresa1b_ncar_ccsm3-example.nc".to_string();
+        let h = NetCDFHeader::from_file(&mut File::open(&filename).unwrap()).unwrap();
+        let mut layout = NetCDFHeader::from_file(&mut File::open(&filename).unwrap()).unwrap();
+        for v in layout.vars.as_mut().unwrap().values_mut() {
+            v.vsize = 0;
+        }
+        layout.layout().unwrap();
+        assert_eq!(layout, h);
+
+        let mut dims = DimensionHM::new();
+        dims.insert(0, cp::NetCDFDimension::new("time".to_string(), 0));
+        dims.insert(1, cp::NetCDFDimension::new("x".to_string(), 3));
+        let mut vars = VariableHM::new();
+        for (n, d, t) in [
+            ("a", vec![0, 1], cp::NetCDFType::NC_SHORT),
+            ("b", vec![1], cp::NetCDFType::NC_BYTE),
+            ("c", vec![0], cp::NetCDFType::NC_DOUBLE),
+            ("d", vec![], cp::NetCDFType::NC_INT),
+        ] {
+            let var = cp::NetCDFVariable::new(n.to_string(), d, None, t, 0, 0);
+            vars.insert(n.to_string(), var);
+        }
+        let nor = NumberOfRecords::NonNegative(0);
+        let version = NetCDFVersion::Offset64;
+        let mut h = NetCDFHeader::new(version, nor, None, Some(dims), Some(vars), None, 0);
+        h.layout().unwrap();
+        let size = h.to_bytes().unwrap().len() as u64;
+        let vars = h.vars.as_ref().unwrap();
+        assert_eq!((vars["b"].begin, vars["b"].vsize()), (size, 4));
+        assert_eq!((vars["d"].begin, vars["d"].vsize()), (size + 4, 4));
+        assert_eq!((vars["a"].begin, vars["a"].vsize()), (size + 8, 8));
+        assert_eq!((vars["c"].begin, vars["c"].vsize()), (size + 16, 8));
+        assert_eq!(h.recsize, 16);
+        let (_, parsed) = header(&h.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed, h);
+    }
+
+    #[test]
+    fn test_layout_too_large() {
+        let large = |version, length, names: &[&str]| {
+            let mut dims = DimensionHM::new();
+            dims.insert(0, cp::NetCDFDimension::new("x".to_string(), length));
+            let mut vars = VariableHM::new();
+            for n in names {
+                let var = cp::NetCDFVariable::new(
+                    n.to_string(),
+                    vec![0],
+                    None,
+                    cp::NetCDFType::NC_DOUBLE,
+                    0,
+                    0,
+                );
+                vars.insert(n.to_string(), var);
+            }
+            let nor = NumberOfRecords::NonNegative(0);
+            NetCDFHeader::new(version, nor, None, Some(dims), Some(vars), None, 0)
+        };
+        // only the last variable may exceed 4 GiB
+        let mut h = large(NetCDFVersion::Offset64, 1 << 30, &["a"]);
+        h.layout().unwrap();
+        assert_eq!(h.vars.as_ref().unwrap()["a"].vsize(), u32::MAX as usize);
+        let mut h = large(NetCDFVersion::Offset64, 1 << 30, &["a", "b"]);
+        let e = h.layout().unwrap_err();
+        assert!(matches!(e, HSE::ExceedsFormat(_)));
+        // 64-bit sizes in CDF-5
+        let mut h = large(NetCDFVersion::Data64, 1 << 30, &["a", "b"]);
+        h.layout().unwrap();
+        let vars = h.vars.as_ref().unwrap();
+        assert_eq!(vars["b"].begin - vars["a"].begin, 8 << 30);
+        // only 32-bit offsets in CDF-1
+        let mut h = large(NetCDFVersion::Classic, 1 << 28, &["a", "b", "c"]);
+        let e = h.layout().unwrap_err();
+        assert!(matches!(e, HSE::ExceedsFormat(_)));
+    }
+
+    #[test]
+    fn test_slab_runs() {
+        let filename = "assets/sresa1b_ncar_ccsm3-example.nc".to_string();
+        let mut file = File::open(filename).unwrap();
+        let h = NetCDFHeader::from_file(&mut file).unwrap();
+        let begin = h.vars.as_ref().unwrap()["tas"].begin;
+        let selection = Selection::new(vec![0, 10, 20], vec![1, 3, 4]);
+        let runs = h.slab_runs("tas".to_string(), &selection).unwrap();
+        let expected: Vec<(u64, usize)> = (10..13)
+            .map(|row| (begin + (row * 256 + 20) * 4, 16))
+            .collect();
+        assert_eq!(runs, expected);
+        let selection = Selection::new(vec![0, 3, 0, 0], vec![1, 2, 128, 256]);
+        let runs = h.slab_runs("ua".to_string(), &selection).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].1, 2 * 128 * 256 * 4);
+        // every 4th longitude
+        let selection = Selection::new(vec![0, 0, 0], vec![1, 2, 64]).with_stride(vec![1, 1, 4]);
+        let runs = h.slab_runs("tas".to_string(), &selection).unwrap();
+        assert_eq!(runs.len(), 128);
+        assert_eq!(runs[1], (begin + 16, 4));
+        assert_eq!(runs[64], (begin + 256 * 4, 4));
+        // every 2nd latitude, all longitudes
+        let selection = Selection::new(vec![0, 0, 0], vec![1, 64, 256]).with_stride(vec![1, 2, 1]);
+        let runs = h.slab_runs("tas".to_string(), &selection).unwrap();
+        assert_eq!(runs.len(), 64);
+        assert_eq!(runs[1], (begin + 2 * 256 * 4, 256 * 4));
+        let selection = Selection::new(vec![1, 0, 0], vec![1, 1, 1]);
+        let e = h.slab_runs("tas".to_string(), &selection).unwrap_err();
+        assert!(matches!(e, HSE::InvalidSelection(_)));
+
+        // records of the only record variable are contiguous
+        let mut builder = crate::builder::NetCDFBuilder::new(NetCDFVersion::Classic);
+        builder.add_unlimited_dimension("time").unwrap();
+        builder.add_dimension("x", 3).unwrap();
+        builder
+            .add_variable("x", &["x"], cp::NetCDFType::NC_SHORT)
+            .unwrap()
+            .add_variable("t", &["time"], cp::NetCDFType::NC_DOUBLE)
+            .unwrap();
+        let mut h = builder.header().unwrap();
+        h.nor = NumberOfRecords::NonNegative(4);
+        let begin = h.vars.as_ref().unwrap()["t"].begin;
+        let selection = Selection::new(vec![1], vec![3]);
+        let runs = h.slab_runs("t".to_string(), &selection).unwrap();
+        assert_eq!(runs, vec![(begin + 8, 3 * 8)]);
+        builder
+            .add_variable("v", &["time", "x"], cp::NetCDFType::NC_SHORT)
+            .unwrap();
+        let mut h = builder.header().unwrap();
+        h.nor = NumberOfRecords::NonNegative(4);
+        let runs = h.slab_runs("t".to_string(), &selection).unwrap();
+        assert_eq!(runs.len(), 3);
+    }
+
     #[test]
     fn test_seeks() {
         let filename = "assets/sresa1b_ncar_ccsm3-example.nc".to_string();
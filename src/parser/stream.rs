@@ -0,0 +1,271 @@
+//! Streaming header parser
+//!
+//! # Stream
+//! Parses the header one dimension, attribute or variable at a time. Input is
+//! read as the parsers ask for it, so every header byte is read exactly once
+//! and nothing after the header is read. Unbuffered readers see many small
+//! reads; wrap them in a `BufReader` if that matters more than reading past
+//! the header. The async reader drives the same parser.
+use super::components::{
+    self as cp, AttributeHM, DimensionHM, ListType, NetCDFVersion, NumberOfRecords, VariableHM,
+};
+use super::{assemble, failure, HSEResult, NetCDFHeader};
+use crate::error::HeadScratcherError as HSE;
+use std::io::Read;
+
+/// Bytes to read when a parser does not know how many it is missing
+///
+/// All header items are aligned to 4 bytes, so this never reads past the header.
+const ALIGNMENT: usize = 4;
+
+/// Next item of the header, with the number of list elements left
+#[derive(Debug, Clone, Copy)]
+enum Item {
+    Magic,
+    Records,
+    Dimensions,
+    Dimension(u64),
+    Attributes,
+    Attribute(u64),
+    Variables,
+    Variable(u64),
+    Done,
+}
+
+/// Resumable header parser, fed with input by its caller
+///
+/// [`Stream::advance`] parses the items the input holds and tells how many
+/// bytes to read next, so the same parser serves blocking and async readers.
+#[derive(Debug)]
+pub(crate) struct Stream {
+    /// Bytes read but not yet parsed
+    buffer: Vec<u8>,
+    /// Bytes parsed so far
+    consumed: u64,
+    /// Length of the input when more was asked for
+    asked: Option<usize>,
+    /// Bytes asked for
+    missing: usize,
+    next: Item,
+    version: NetCDFVersion,
+    nor: NumberOfRecords,
+    dims: Option<DimensionHM>,
+    attrs: Option<AttributeHM>,
+    vars: Option<VariableHM>,
+}
+
+impl Stream {
+    pub(crate) fn new() -> Self {
+        Stream {
+            buffer: Vec::new(),
+            consumed: 0,
+            asked: None,
+            missing: 0,
+            next: Item::Magic,
+            version: NetCDFVersion::Classic,
+            nor: NumberOfRecords::NonNegative(0),
+            dims: None,
+            attrs: None,
+            vars: None,
+        }
+    }
+
+    /// Input to append the bytes asked for by [`Stream::advance`] to
+    pub(crate) fn input(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+
+    /// Parse the items of the input
+    ///
+    /// Returns the number of bytes to read before the next call, `None` once
+    /// the header is complete. Fails if nothing was read since the last call.
+    pub(crate) fn advance(&mut self) -> Result<Option<usize>, HSE<String>> {
+        // the header ends early
+        if self.asked == Some(self.buffer.len()) {
+            return Err(HSE::InvalidFile);
+        }
+        loop {
+            let version = self.version;
+            let next = match self.next {
+                Item::Magic => self.parse(cp::magic)?.map(|v| {
+                    self.version = v;
+                    Item::Records
+                }),
+                Item::Records => self
+                    .parse(|i| cp::number_of_records(i, version))?
+                    .map(|nor| {
+                        self.nor = nor;
+                        Item::Dimensions
+                    }),
+                Item::Dimensions => self.list(ListType::DimensionList)?.map(|n| match n {
+                    Some(n) => {
+                        self.dims = Some(DimensionHM::new());
+                        Item::Dimension(n)
+                    }
+                    None => Item::Attributes,
+                }),
+                Item::Dimension(0) => Some(Item::Attributes),
+                Item::Dimension(n) => self.parse(|i| cp::dimension(i, version))?.map(|d| {
+                    let dims = self.dims.get_or_insert_with(DimensionHM::new);
+                    dims.insert(dims.len(), d);
+                    Item::Dimension(n - 1)
+                }),
+                Item::Attributes => self.list(ListType::AttributeList)?.map(|n| match n {
+                    Some(n) => {
+                        self.attrs = Some(AttributeHM::new());
+                        Item::Attribute(n)
+                    }
+                    None => Item::Variables,
+                }),
+                Item::Attribute(0) => Some(Item::Variables),
+                Item::Attribute(n) => self.parse(|i| cp::attribute(i, version))?.map(|a| {
+                    let attrs = self.attrs.get_or_insert_with(AttributeHM::new);
+                    attrs.insert(a.name(), a);
+                    Item::Attribute(n - 1)
+                }),
+                Item::Variables => self.list(ListType::VariableList)?.map(|n| match n {
+                    Some(n) => {
+                        self.vars = Some(VariableHM::new());
+                        Item::Variable(n)
+                    }
+                    None => Item::Done,
+                }),
+                Item::Variable(0) => Some(Item::Done),
+                Item::Variable(n) => self.parse(|i| cp::variable(i, version))?.map(|v| {
+                    let vars = self.vars.get_or_insert_with(VariableHM::new);
+                    vars.insert(v.name(), v);
+                    Item::Variable(n - 1)
+                }),
+                Item::Done => return Ok(None),
+            };
+            match next {
+                Some(next) => self.next = next,
+                None => return Ok(Some(self.missing)),
+            }
+        }
+    }
+
+    /// Header and its size in bytes, once [`Stream::advance`] completed it
+    pub(crate) fn finish(self) -> (NetCDFHeader, u64) {
+        let header = assemble(self.version, self.nor, self.attrs, self.dims, self.vars);
+        (header, self.consumed)
+    }
+
+    /// Run `parser` on the unparsed input, `None` if it is incomplete
+    fn parse<O, P>(&mut self, parser: P) -> Result<Option<O>, HSE<String>>
+    where
+        P: Fn(&[u8]) -> HSEResult<&[u8], O>,
+    {
+        let missing = match parser(&self.buffer) {
+            Ok((rest, o)) => {
+                let used = self.buffer.len() - rest.len();
+                self.buffer.drain(..used);
+                self.consumed += used as u64;
+                self.asked = None;
+                return Ok(Some(o));
+            }
+            Err(nom::Err::Incomplete(nom::Needed::Size(n))) => n.get(),
+            Err(nom::Err::Incomplete(nom::Needed::Unknown)) => ALIGNMENT,
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => return Err(failure(e)),
+        };
+        self.asked = Some(self.buffer.len());
+        self.missing = missing;
+        Ok(None)
+    }
+
+    /// Number of elements of a list, `Some(None)` if the list is absent
+    fn list(&mut self, expected: ListType) -> Result<Option<Option<u64>>, HSE<String>> {
+        let version = self.version;
+        self.parse(|i| {
+            let (i, t) = cp::list_type(i, version)?;
+            match t {
+                ListType::Absent => Ok((i, None)),
+                t if t == expected => cp::nelems(i, version).map(|(i, n)| (i, Some(n))),
+                _ => Err(nom::Err::Error(HSE::EmptyError)),
+            }
+        })
+    }
+}
+
+/// Parse the header at the start of `reader`
+///
+/// Returns the header and its size in bytes. The reader is left right after
+/// the header.
+pub fn header<R: Read>(reader: &mut R) -> Result<(NetCDFHeader, u64), HSE<String>> {
+    let mut s = Stream::new();
+    while let Some(missing) = s.advance()? {
+        reader
+            .by_ref()
+            .take(missing as u64)
+            .read_to_end(s.input())?;
+    }
+    Ok(s.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    /// Reader handing out at most `step` bytes per read, counting them
+    struct Trickle<R> {
+        inner: R,
+        step: usize,
+        read: usize,
+    }
+
+    impl<R: Read> Read for Trickle<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(self.step);
+            let n = self.inner.read(&mut buf[..len])?;
+            self.read += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_stream_matches_slice_parser() {
+        let files = [
+            "assets/empty.nc",
+            "assets/small.nc",
+            "assets/testrh.nc",
+            "assets/cdf5.nc",
+            "assets/sresa1b_ncar_ccsm3-example.nc",
+            "assets/sresa1b_ncar_ccsm3-example.3_nc64.nc",
+        ];
+        for filename in files {
+            let bytes = std::fs::read(filename).unwrap();
+            let (rest, expected) = super::super::header(&bytes).unwrap();
+            let size = (bytes.len() - rest.len()) as u64;
+            for step in [1, 3, 4096] {
+                let mut reader = Trickle {
+                    inner: File::open(filename).unwrap(),
+                    step,
+                    read: 0,
+                };
+                let (h, consumed) = header(&mut reader).unwrap();
+                assert_eq!(h.version, expected.version);
+                assert_eq!(h.nor, expected.nor);
+                assert_eq!(h.attrs, expected.attrs);
+                assert_eq!(h.dims, expected.dims);
+                assert_eq!(h.seeks, expected.seeks);
+                assert_eq!(h.recsize, expected.recsize);
+                // NaN fill values compare unequal, their debug output does not
+                assert_eq!(format!("{:?}", h.vars), format!("{:?}", expected.vars));
+                assert_eq!(consumed, size);
+                assert_eq!(reader.read as u64, size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stream_errors() {
+        let bytes = std::fs::read("assets/sresa1b_ncar_ccsm3-example.nc").unwrap();
+        let e = header(&mut &bytes[..1000]).unwrap_err();
+        assert_eq!(e, HSE::InvalidFile);
+        let e = header(&mut &b"CDF\x07\x00\x00\x00\x00"[..]).unwrap_err();
+        assert_eq!(e, HSE::UnsupportedNetCDFVersion);
+        let e = header(&mut &b""[..]).unwrap_err();
+        assert_eq!(e, HSE::InvalidFile);
+    }
+}
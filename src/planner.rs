@@ -0,0 +1,114 @@
+//! Read planning
+//!
+//! A [`ReadPlan`] reads a batch of slabs at once. The byte ranges of all slabs
+//! are sorted and ranges closer than a gap threshold are merged, so a batch of
+//! small neighbouring slabs turns into a few large reads.
+use crate::error::HeadScratcherError as HSE;
+use crate::parser::NetCDFHeader;
+use crate::selection::Selection;
+use crate::source::{coalesce, ByteRangeSource};
+
+/// Slab of a variable: name, start and count
+pub type SlabRequest = (String, Vec<usize>, Vec<usize>);
+
+/// Merged byte ranges of a batch of slabs
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadPlan {
+    /// Byte ranges `(offset, length)` to read, sorted and merged
+    pub ranges: Vec<(u64, usize)>,
+    /// Byte runs of every slab, in request order
+    runs: Vec<Vec<(u64, usize)>>,
+}
+
+impl ReadPlan {
+    /// Plan the slabs of classic variables, merging ranges at most `gap` bytes apart
+    pub fn new(
+        header: &NetCDFHeader,
+        requests: &[SlabRequest],
+        gap: u64,
+    ) -> Result<Self, HSE<String>> {
+        let runs = requests
+            .iter()
+            .map(|(variable, start, count)| {
+                let selection = Selection::new(start.clone(), count.clone());
+                header.slab_runs(variable.clone(), &selection)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let all: Vec<(u64, usize)> = runs.iter().flatten().copied().collect();
+        Ok(ReadPlan {
+            ranges: coalesce(&all, gap),
+            runs,
+        })
+    }
+
+    /// Number of bytes the plan reads
+    pub fn bytes(&self) -> usize {
+        self.ranges.iter().map(|(_, len)| len).sum()
+    }
+
+    /// Read the planned ranges, returning the raw bytes of every slab
+    pub fn execute<S: ByteRangeSource>(&self, source: &mut S) -> Result<Vec<Vec<u8>>, HSE<String>> {
+        let mut bytes = vec![0u8; self.bytes()];
+        source.read_ranges(&self.ranges, &mut bytes)?;
+        // position of every range in `bytes`
+        let positions: Vec<usize> = self
+            .ranges
+            .iter()
+            .scan(0, |pos, (_, len)| {
+                *pos += len;
+                Some(*pos - len)
+            })
+            .collect();
+        let slabs = self
+            .runs
+            .iter()
+            .map(|runs| {
+                let mut slab = Vec::with_capacity(runs.iter().map(|(_, len)| len).sum());
+                for (offset, len) in runs {
+                    let k = self.ranges.partition_point(|(o, _)| o <= offset) - 1;
+                    let start = positions[k] + (offset - self.ranges[k].0) as usize;
+                    slab.extend_from_slice(&bytes[start..start + len]);
+                }
+                slab
+            })
+            .collect();
+        Ok(slabs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NetCDF;
+
+    #[test]
+    fn test_read_plan() {
+        let filename = "assets/sresa1b_ncar_ccsm3-example.nc".to_string();
+        let mut netcdf = NetCDF::new(filename).unwrap();
+        // a few points along a row and a 2x2 box
+        let mut requests: Vec<SlabRequest> = (0..4)
+            .map(|k| ("tas".to_string(), vec![0, 30, 100 + 3 * k], vec![1, 1, 1]))
+            .collect();
+        requests.push(("tas".to_string(), vec![0, 50, 7], vec![1, 2, 2]));
+        requests.push(("lat".to_string(), vec![0], vec![128]));
+
+        let plan = ReadPlan::new(netcdf.header(), &requests, 0).unwrap();
+        assert_eq!(plan.ranges.len(), 4 + 2 + 1);
+        let plan = ReadPlan::new(netcdf.header(), &requests, 64).unwrap();
+        assert_eq!(plan.ranges.len(), 1 + 2 + 1);
+        assert_eq!(plan.bytes(), 40 + 2 * 8 + 4 * 128);
+        let slabs = netcdf.read_batch(&requests, 64).unwrap();
+        for ((variable, start, count), slab) in requests.iter().zip(slabs) {
+            let selection = Selection::new(start.clone(), count.clone());
+            let mut expected = vec![0u8; selection.len().unwrap() * 4];
+            netcdf
+                .update_buffer_selection(variable.clone(), &selection, &mut expected)
+                .unwrap();
+            assert_eq!(slab, expected);
+        }
+
+        requests.push(("missing".to_string(), vec![0], vec![1]));
+        let e = ReadPlan::new(netcdf.header(), &requests, 0).unwrap_err();
+        assert_eq!(e, HSE::VariableNotFound("missing".to_string()));
+    }
+}
@@ -0,0 +1,127 @@
+//! Hyperslab selection
+//!
+//! A selection picks `count` elements per dimension, beginning at `start` and
+//! advancing by `stride`. A stride of one selects a contiguous box.
+use crate::error::HeadScratcherError as HSE;
+
+/// Hyperslab with start, count and stride per dimension
+#[derive(Debug, PartialEq, Clone)]
+pub struct Selection {
+    pub start: Vec<usize>,
+    pub count: Vec<usize>,
+    pub stride: Vec<usize>,
+}
+
+impl Selection {
+    /// Contiguous selection of `count` elements from `start`
+    pub fn new(start: Vec<usize>, count: Vec<usize>) -> Self {
+        let stride = vec![1; start.len()];
+        Selection {
+            start,
+            count,
+            stride,
+        }
+    }
+
+    /// Selection of every element of a variable with the given shape
+    pub fn all(shape: &[usize]) -> Self {
+        Selection::new(vec![0; shape.len()], shape.to_vec())
+    }
+
+    /// Set the stride per dimension
+    pub fn with_stride(mut self, stride: Vec<usize>) -> Self {
+        self.stride = stride;
+        self
+    }
+
+    /// Number of selected elements, failing if it overflows `usize`
+    pub fn len(&self) -> Result<usize, HSE<String>> {
+        self.count
+            .iter()
+            .try_fold(1usize, |n, c| n.checked_mul(*c))
+            .ok_or_else(|| {
+                let msg = format!("Number of elements of {:?} overflows", self.count);
+                HSE::InvalidSelection(msg)
+            })
+    }
+
+    /// Number of bytes of the selected values of `size` bytes each
+    pub fn bytes(&self, size: usize) -> Result<usize, HSE<String>> {
+        self.len()?.checked_mul(size).ok_or_else(|| {
+            let msg = format!("Bytes of {:?} values overflow", self.count);
+            HSE::InvalidSelection(msg)
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count.contains(&0)
+    }
+
+    /// Check the selection against the lengths of the variable's dimensions
+    pub fn validate(&self, shape: &[usize]) -> Result<(), HSE<String>> {
+        let ndims = shape.len();
+        if self.start.len() != ndims || self.count.len() != ndims || self.stride.len() != ndims {
+            let msg = format!("Variable has {} dimensions", ndims);
+            return Err(HSE::InvalidSelection(msg));
+        }
+        for (d, length) in shape.iter().enumerate() {
+            if self.stride[d] == 0 {
+                let msg = format!("Stride of dimension {} is zero", d);
+                return Err(HSE::InvalidSelection(msg));
+            }
+            if self.count[d] == 0 {
+                continue;
+            }
+            let last = (self.count[d] - 1)
+                .checked_mul(self.stride[d])
+                .and_then(|o| o.checked_add(self.start[d]));
+            match last {
+                Some(last) if last < *length => {}
+                Some(last) => {
+                    let msg = format!(
+                        "Index {} exceeds length {} of dimension {}",
+                        last, length, d
+                    );
+                    return Err(HSE::InvalidSelection(msg));
+                }
+                None => {
+                    let msg = format!("Last index of dimension {} overflows", d);
+                    return Err(HSE::InvalidSelection(msg));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let shape = [1, 128, 256];
+        let s = Selection::new(vec![0, 0, 0], vec![1, 128, 256]);
+        assert!(s.validate(&shape).is_ok());
+        let s = Selection::new(vec![0, 0, 0], vec![1, 128, 64]).with_stride(vec![1, 1, 4]);
+        assert!(s.validate(&shape).is_ok());
+        assert_eq!(s.len().unwrap(), 128 * 64);
+        let s = Selection::new(vec![0, 0, 1], vec![1, 128, 64]).with_stride(vec![1, 1, 4]);
+        assert!(s.validate(&shape).is_ok());
+        let s = Selection::new(vec![0, 0, 4], vec![1, 128, 64]).with_stride(vec![1, 1, 4]);
+        assert!(s.validate(&shape).is_err());
+        let s = Selection::new(vec![0, 0, 0], vec![1, 129, 1]);
+        assert!(s.validate(&shape).is_err());
+        let s = Selection::new(vec![0, 0, 0], vec![1, 1, 1]).with_stride(vec![1, 0, 1]);
+        assert!(s.validate(&shape).is_err());
+        let s = Selection::new(vec![0, 0], vec![1, 1]);
+        assert!(s.validate(&shape).is_err());
+        let s = Selection::new(vec![0, usize::MAX, 0], vec![1, 2, 1]);
+        assert!(matches!(s.validate(&shape), Err(HSE::InvalidSelection(_))));
+        let s = Selection::new(vec![0, 0, 0], vec![1, 2, 1]).with_stride(vec![1, usize::MAX, 1]);
+        assert!(matches!(s.validate(&shape), Err(HSE::InvalidSelection(_))));
+        let s = Selection::new(vec![0, 0, 0], vec![usize::MAX, 2, 1]);
+        assert!(matches!(s.len(), Err(HSE::InvalidSelection(_))));
+        assert!(!s.is_empty());
+    }
+}
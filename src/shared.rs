@@ -0,0 +1,230 @@
+//! Reader shared between threads
+//!
+//! [`SharedNetCDF`] reads with positional reads (`pread` on unix) instead of
+//! moving a file cursor, so all its methods take `&self` and one open file
+//! and parsed header serve any number of threads at once. Available on unix
+//! and windows, the platforms with positional reads.
+use crate::data::NetCDFData;
+use crate::error::HeadScratcherError as HSE;
+use crate::parser::NetCDFHeader;
+use crate::selection::Selection;
+use crate::{hdf5, NetCDF};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+/// netCDF file that can be read from many threads at once
+#[derive(Debug)]
+pub struct SharedNetCDF {
+    file: File,
+    header: NetCDFHeader,
+    /// Storage of netCDF-4 variables, empty for classic files
+    storage: HashMap<String, hdf5::Storage>,
+}
+
+impl SharedNetCDF {
+    pub fn new(filename: String) -> Result<Self, HSE<String>> {
+        Ok(NetCDF::new(filename)?.into())
+    }
+
+    pub fn header(&self) -> &NetCDFHeader {
+        &self.header
+    }
+
+    /// Fill `buffer` with the raw bytes of a variable from `start` on
+    ///
+    /// See [`NetCDF::update_buffer`].
+    pub fn update_buffer(
+        &self,
+        variable: String,
+        start: &[usize],
+        buffer: &mut [u8],
+    ) -> Result<(), HSE<String>> {
+        let mut file = At::new(&self.file);
+        crate::read_range(
+            &mut file,
+            &self.header,
+            &self.storage,
+            variable,
+            start,
+            buffer,
+        )
+    }
+
+    /// Fill `buffer` with the raw bytes of a selection, in selection order
+    pub fn update_buffer_selection(
+        &self,
+        variable: String,
+        selection: &Selection,
+        buffer: &mut [u8],
+    ) -> Result<(), HSE<String>> {
+        let mut file = At::new(&self.file);
+        crate::read_runs(
+            &mut file,
+            &self.header,
+            &self.storage,
+            variable,
+            selection,
+            buffer,
+        )
+    }
+
+    /// Read the slab `start..start + count` of a variable as native values
+    pub fn read_slab<T: NetCDFData>(
+        &self,
+        variable: String,
+        start: &[usize],
+        count: &[usize],
+    ) -> Result<Vec<T>, HSE<String>> {
+        let selection = Selection::new(start.to_vec(), count.to_vec());
+        self.read_selection(variable, &selection)
+    }
+
+    /// Read a (strided) selection of a variable as native values
+    pub fn read_selection<T: NetCDFData>(
+        &self,
+        variable: String,
+        selection: &Selection,
+    ) -> Result<Vec<T>, HSE<String>> {
+        crate::check_type::<T>(&self.header, &variable)?;
+        let size = T::NC_TYPE.extsize();
+        let mut buffer =
+            crate::selection_buffer(&self.header, &self.storage, &variable, selection, size)?;
+        self.update_buffer_selection(variable, selection, &mut buffer)?;
+        Ok(T::decode(&buffer))
+    }
+}
+
+impl From<NetCDF<File>> for SharedNetCDF {
+    fn from(netcdf: NetCDF<File>) -> Self {
+        SharedNetCDF {
+            file: netcdf.file,
+            header: netcdf.header,
+            storage: netcdf.storage,
+        }
+    }
+}
+
+/// Cursor of its own over a shared file, reading with positional reads
+struct At<'f> {
+    file: &'f File,
+    pos: u64,
+}
+
+impl<'f> At<'f> {
+    fn new(file: &'f File) -> Self {
+        At { file, pos: 0 }
+    }
+}
+
+impl Read for At<'_> {
+    #[cfg(unix)]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use std::os::unix::fs::FileExt;
+        let n = self.file.read_at(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    #[cfg(windows)]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use std::os::windows::fs::FileExt;
+        let n = self.file.seek_read(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for At<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        // the length is only needed for seeks from the end
+        let len = match pos {
+            SeekFrom::End(_) => self.file.metadata()?.len(),
+            _ => 0,
+        };
+        self.pos = crate::source::seek(self.pos, len, pos)?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_threads() {
+        let filename = "assets/sresa1b_ncar_ccsm3-example.nc".to_string();
+        let shared = SharedNetCDF::new(filename.clone()).unwrap();
+        let mut netcdf = NetCDF::new(filename).unwrap();
+        assert_eq!(shared.header(), netcdf.header());
+
+        let names = ["tas", "pr", "ua", "area"];
+        let expected: Vec<Vec<f32>> = names
+            .iter()
+            .map(|name| {
+                let shape = netcdf.header().variable_shape(name).unwrap();
+                let shape: Vec<usize> = shape.into_iter().map(|(_, l)| l).collect();
+                netcdf
+                    .read_selection(name.to_string(), &Selection::all(&shape))
+                    .unwrap()
+            })
+            .collect();
+        std::thread::scope(|s| {
+            for (name, expected) in names.iter().zip(&expected) {
+                let shared = &shared;
+                s.spawn(move || {
+                    let shape = shared.header().variable_shape(name).unwrap();
+                    let shape: Vec<usize> = shape.into_iter().map(|(_, l)| l).collect();
+                    let values: Vec<f32> = shared
+                        .read_selection(name.to_string(), &Selection::all(&shape))
+                        .unwrap();
+                    assert_eq!(&values, expected, "{}", name);
+                });
+            }
+        });
+
+        let mut buffer = vec![0u8; 4];
+        shared
+            .update_buffer("tas".to_string(), &[0, 0, 0], &mut buffer)
+            .unwrap();
+        assert_eq!(buffer, vec![67, 87, 228, 188]);
+        let e = shared
+            .read_slab::<f64>("tas".to_string(), &[0, 0, 0], &[1, 1, 1])
+            .unwrap_err();
+        assert!(matches!(e, HSE::TypeMismatch(_)));
+    }
+
+    #[test]
+    fn test_at_seek() {
+        let file = File::open("assets/sresa1b_ncar_ccsm3-example.nc").unwrap();
+        let len = file.metadata().unwrap().len();
+        let mut at = At::new(&file);
+        assert_eq!(at.seek(SeekFrom::End(-4)).unwrap(), len - 4);
+        assert_eq!(at.seek(SeekFrom::Current(-4)).unwrap(), len - 8);
+        assert!(at.seek(SeekFrom::Current(-(len as i64))).is_err());
+        let mut magic = [0u8; 3];
+        at.seek(SeekFrom::Start(0)).unwrap();
+        at.read_exact(&mut magic).unwrap();
+        assert_eq!(&magic, b"CDF");
+    }
+
+    #[test]
+    fn test_shared_netcdf4() {
+        let classic = "assets/sresa1b_ncar_ccsm3-example.nc".to_string();
+        let mut classic = NetCDF::new(classic).unwrap();
+        let filename = "assets/sresa1b_ncar_ccsm3-example.4.nc".to_string();
+        let shared = SharedNetCDF::new(filename).unwrap();
+        let selection =
+            Selection::new(vec![0, 2, 5, 7], vec![1, 3, 4, 5]).with_stride(vec![1, 5, 3, 2]);
+        let expected: Vec<f32> = classic
+            .read_selection("ua".to_string(), &selection)
+            .unwrap();
+        let values: Vec<f32> = std::thread::scope(|s| {
+            s.spawn(|| shared.read_selection("ua".to_string(), &selection))
+                .join()
+                .unwrap()
+        })
+        .unwrap();
+        assert_eq!(values, expected);
+    }
+}
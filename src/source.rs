@@ -0,0 +1,351 @@
+//! Sources of file bytes
+//!
+//! [`NetCDF`](crate::NetCDF) reads from any [`ByteRangeSource`]. Everything
+//! that is `Read + Seek`, such as local files and in-memory buffers wrapped in
+//! a `Cursor`, is a source. Remote files are read through [`Remote`], which
+//! turns the reads into as few [`RangeRequest`]s as possible.
+use crate::error::HeadScratcherError as HSE;
+use crate::selection::Selection;
+use crate::NetCDF;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Bytes requested at least for small reads of a remote file
+const BLOCK: usize = 16384;
+
+/// Largest gap in bytes between two ranges requested together
+const GAP: u64 = 4096;
+
+/// Random access to the bytes of a file
+pub trait ByteRangeSource {
+    /// Fill `buf` with the bytes from `offset` on
+    fn read_range(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+
+    /// Size of the file in bytes
+    fn size(&mut self) -> io::Result<u64>;
+
+    /// Fill `buffer` with byte ranges `(offset, length)`, one after another
+    ///
+    /// Sources with expensive requests merge nearby ranges.
+    fn read_ranges(&mut self, ranges: &[(u64, usize)], buffer: &mut [u8]) -> io::Result<()> {
+        let mut pos = 0;
+        for (offset, len) in ranges {
+            self.read_range(*offset, &mut buffer[pos..pos + len])?;
+            pos += len;
+        }
+        Ok(())
+    }
+}
+
+impl<F: Read + Seek> ByteRangeSource for F {
+    fn read_range(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.read_exact(buf)
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        self.seek(SeekFrom::End(0))
+    }
+}
+
+/// `Read + Seek` view of a source, for the header parsers
+pub(crate) struct Reader<'s, S: ByteRangeSource> {
+    source: &'s mut S,
+    pos: u64,
+    len: u64,
+}
+
+impl<'s, S: ByteRangeSource> Reader<'s, S> {
+    pub(crate) fn new(source: &'s mut S) -> io::Result<Self> {
+        let len = source.size()?;
+        Ok(Reader {
+            source,
+            pos: 0,
+            len,
+        })
+    }
+}
+
+impl<S: ByteRangeSource> Read for Reader<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.len.saturating_sub(self.pos) as usize);
+        self.source.read_range(self.pos, &mut buf[..n])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<S: ByteRangeSource> Seek for Reader<'_, S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = seek(self.pos, self.len, pos)?;
+        Ok(self.pos)
+    }
+}
+
+/// Position after a seek from `pos` in a file of `len` bytes
+pub(crate) fn seek(pos: u64, len: u64, to: SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match to {
+        SeekFrom::Start(p) => (0, p as i64),
+        SeekFrom::Current(o) => (pos, o),
+        SeekFrom::End(o) => (len, o),
+    };
+    base.checked_add_signed(offset)
+        .ok_or_else(|| io::ErrorKind::InvalidInput.into())
+}
+
+/// Backend answering single byte range requests, e.g. an HTTP server
+pub trait RangeRequest {
+    /// Size of the file in bytes
+    fn size(&mut self) -> io::Result<u64>;
+
+    /// `len` bytes from `offset` on
+    fn request(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>>;
+}
+
+/// Remote file, read with as few range requests as possible
+///
+/// Small reads, such as the ones of the incremental header parser, are
+/// served from a read-ahead block, so a header takes a few requests. Ranges
+/// of a selection that are close to each other are requested together.
+#[derive(Debug)]
+pub struct Remote<R: RangeRequest> {
+    inner: R,
+    len: u64,
+    block: usize,
+    gap: u64,
+    /// Read-ahead block
+    ahead: (u64, Vec<u8>),
+    /// Ranges fetched by [`Remote::prefetch`], by offset
+    fetched: Vec<(u64, Vec<u8>)>,
+    requests: usize,
+}
+
+impl<R: RangeRequest> Remote<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let len = inner.size()?;
+        Ok(Remote {
+            inner,
+            len,
+            block: BLOCK,
+            gap: GAP,
+            ahead: (0, Vec::new()),
+            fetched: Vec::new(),
+            requests: 1,
+        })
+    }
+
+    /// Request at least `block` bytes for small reads
+    pub fn with_block(mut self, block: usize) -> Self {
+        self.block = block.max(1);
+        self
+    }
+
+    /// Request ranges at most `gap` bytes apart together
+    pub fn with_gap(mut self, gap: u64) -> Self {
+        self.gap = gap;
+        self
+    }
+
+    /// Size of the file in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of requests sent so far, including the one for the size
+    pub fn requests(&self) -> usize {
+        self.requests
+    }
+
+    /// Fetch byte ranges `(offset, length)` for the following reads
+    ///
+    /// Ranges at most `gap` bytes apart are fetched in one request. Previously
+    /// prefetched ranges are dropped.
+    pub fn prefetch(&mut self, ranges: &[(u64, usize)]) -> io::Result<()> {
+        let mut fetched = Vec::new();
+        for (offset, len) in coalesce(ranges, self.gap) {
+            fetched.push((offset, self.request(offset, len)?));
+        }
+        self.fetched = fetched;
+        Ok(())
+    }
+
+    /// `len` bytes from `offset` on, if they are held in memory
+    fn cached(&self, offset: u64, len: usize) -> Option<&[u8]> {
+        std::iter::once(&self.ahead)
+            .chain(&self.fetched)
+            .find(|(start, bytes)| {
+                offset >= *start && offset + len as u64 <= start + bytes.len() as u64
+            })
+            .map(|(start, bytes)| {
+                let k = (offset - start) as usize;
+                &bytes[k..k + len]
+            })
+    }
+
+    /// `len` bytes from `offset` on with one request
+    fn request(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        self.requests += 1;
+        let bytes = self.inner.request(offset, len)?;
+        if bytes.len() != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(bytes)
+    }
+}
+
+impl<R: RangeRequest> ByteRangeSource for Remote<R> {
+    fn read_range(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        if let Some(bytes) = self.cached(offset, buf.len()) {
+            buf.copy_from_slice(bytes);
+        } else if buf.len() >= self.block {
+            buf.copy_from_slice(&self.request(offset, buf.len())?);
+        } else {
+            let len = self.block.min(self.len.saturating_sub(offset) as usize);
+            self.ahead = (offset, self.request(offset, len.max(buf.len()))?);
+            buf.copy_from_slice(&self.ahead.1[..buf.len()]);
+        }
+        Ok(())
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self.len)
+    }
+
+    fn read_ranges(&mut self, ranges: &[(u64, usize)], buffer: &mut [u8]) -> io::Result<()> {
+        // prefetched selections are served from memory
+        if ranges.iter().all(|(o, l)| self.cached(*o, *l).is_some()) {
+            let mut pos = 0;
+            for (offset, len) in ranges {
+                buffer[pos..pos + len].copy_from_slice(self.cached(*offset, *len).unwrap());
+                pos += len;
+            }
+            return Ok(());
+        }
+        let mut requested = Vec::new();
+        for (offset, len) in coalesce(ranges, self.gap) {
+            requested.push((offset, self.request(offset, len)?));
+        }
+        let mut pos = 0;
+        for (offset, len) in ranges.iter().filter(|(_, len)| *len > 0) {
+            let k = requested.partition_point(|(o, _)| o <= offset) - 1;
+            let (start, bytes) = &requested[k];
+            let k = (offset - start) as usize;
+            buffer[pos..pos + len].copy_from_slice(&bytes[k..k + len]);
+            pos += len;
+        }
+        Ok(())
+    }
+}
+
+impl<R: RangeRequest> NetCDF<Remote<R>> {
+    /// Fetch the byte ranges of a selection of a classic variable
+    ///
+    /// Reads of the selection are then served from memory. Ranges of
+    /// netCDF-4 variables are only known while reading, so nothing is fetched
+    /// for them.
+    pub fn prefetch(&mut self, variable: String, selection: &Selection) -> Result<(), HSE<String>> {
+        if self.storage.contains_key(&variable) {
+            return Ok(());
+        }
+        let runs = self.header.slab_runs(variable, selection)?;
+        self.file.prefetch(&runs)?;
+        Ok(())
+    }
+}
+
+/// Sort ranges and merge the ones at most `gap` bytes apart
+pub(crate) fn coalesce(ranges: &[(u64, usize)], gap: u64) -> Vec<(u64, usize)> {
+    let mut ranges: Vec<(u64, u64)> = ranges
+        .iter()
+        .filter(|(_, len)| *len > 0)
+        .map(|(offset, len)| (*offset, offset + *len as u64))
+        .collect();
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 + gap => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+        .into_iter()
+        .map(|(start, end)| (start, (end - start) as usize))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// In-memory file answering range requests
+    struct Memory(Vec<u8>);
+
+    impl RangeRequest for Memory {
+        fn size(&mut self) -> io::Result<u64> {
+            Ok(self.0.len() as u64)
+        }
+
+        fn request(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+            let offset = offset as usize;
+            Ok(self.0[offset..(offset + len).min(self.0.len())].to_vec())
+        }
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let ranges = [(100, 10), (0, 10), (14, 2), (200, 0), (105, 20)];
+        assert_eq!(coalesce(&ranges, 4), vec![(0, 16), (100, 25)]);
+        assert_eq!(coalesce(&ranges, 0), vec![(0, 10), (14, 2), (100, 25)]);
+        assert_eq!(coalesce(&ranges, 100), vec![(0, 125)]);
+    }
+
+    #[test]
+    fn test_remote() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let mut remote = Remote::new(Memory(bytes.clone()))
+            .unwrap()
+            .with_block(64)
+            .with_gap(8);
+        let mut buf = [0u8; 4];
+        remote.read_range(10, &mut buf).unwrap();
+        remote.read_range(70, &mut buf).unwrap();
+        assert_eq!(buf, [70, 71, 72, 73]);
+        assert_eq!(remote.requests(), 2);
+        // beyond the read-ahead block
+        remote.read_range(72, &mut buf).unwrap();
+        assert_eq!(remote.requests(), 3);
+        let mut buf = [0u8; 100];
+        remote.read_range(100, &mut buf).unwrap();
+        assert_eq!(buf[..], bytes[100..200]);
+        assert_eq!(remote.requests(), 4);
+        let e = remote.read_range(250, &mut buf).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+
+        // out of order, overlapping ranges
+        let ranges = [(200, 2), (0, 3), (2, 2), (210, 1)];
+        let mut buffer = [0u8; 8];
+        let requests = remote.requests();
+        remote.read_ranges(&ranges, &mut buffer).unwrap();
+        assert_eq!(buffer, [200, 201, 0, 1, 2, 2, 3, 210]);
+        assert_eq!(remote.requests(), requests + 2);
+        let mut expected = [0u8; 8];
+        Cursor::new(bytes)
+            .read_ranges(&ranges, &mut expected)
+            .unwrap();
+        assert_eq!(buffer, expected);
+
+        let mut reader = Reader::new(&mut remote).unwrap();
+        let mut rest = Vec::new();
+        reader.seek(SeekFrom::End(-3)).unwrap();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, [253, 254, 255]);
+    }
+}
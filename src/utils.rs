@@ -1,10 +1,11 @@
 use crate::{
     error::HeadScratcherError as HSE,
-    parser::components::{DimensionHM, VariableHM},
-    parser::SeeksHM,
+    parser::components::VariableHM,
+    parser::{NetCDFHeader, SeeksHM},
+    selection::Selection,
 };
 
-pub(crate) fn product_vector(vecs: &[usize], _record: bool) -> Vec<usize> {
+pub(crate) fn product_vector(vecs: &[usize], record: bool) -> Vec<usize> {
     // https://cluster.earlham.edu/bccd-ng/testing/mobeen/GALAXSEEHPC/netcdf-4.1.3/man4/netcdf.html#Computing-Offsets
     let mut prod = 1usize;
     let mut result: Vec<usize> = Vec::new();
@@ -12,34 +13,112 @@ pub(crate) fn product_vector(vecs: &[usize], _record: bool) -> Vec<usize> {
         prod *= v;
         result.insert(0, prod);
     }
+    // records of a variable are not contiguous, the record dimension spans nothing
+    if record {
+        if let Some(first) = result.first_mut() {
+            *first = 0;
+        }
+    }
     result
 }
 
-pub(crate) fn calc_seek(v: &VariableHM, s: &SeeksHM, name: String, start: &[usize]) -> Option<u64> {
+/// Calculate the file offset of `start` within variable `name`
+///
+/// Records are interleaved over all record variables, so the record index of a
+/// record variable advances by `recsize` bytes instead of by the seek table.
+pub(crate) fn calc_seek(
+    v: &VariableHM,
+    s: &SeeksHM,
+    recsize: usize,
+    name: String,
+    start: &[usize],
+) -> Option<u64> {
     match (v.get(&name), s.get(&name)) {
         (Some(va), Some(se)) => {
             assert!(va.dims.len() == start.len(), "Lengths are different");
+            let (record, start, se) = if va.is_record() {
+                (start[0], &start[1..], &se[1..])
+            } else {
+                (0, start, &se[..])
+            };
             let offset: usize = start.iter().zip(se).map(|(a, b)| a * b).sum();
-            let result = offset as u64 * va.nc_type.extsize() as u64 + va.begin;
+            let result = offset as u64 * va.nc_type.extsize() as u64
+                + record as u64 * recsize as u64
+                + va.begin;
             Some(result)
         }
         (_, _) => None,
     }
 }
 
+/// Split a selection of variable `name` into contiguous byte runs
+/// `(offset, length)`, in the order the selected values are stored
+///
+/// Trailing dimensions that are read completely with a stride of one are
+/// merged into a single run. The record dimension is only merged for the
+/// only record variable, as records are interleaved otherwise.
+pub(crate) fn calc_runs(
+    v: &VariableHM,
+    s: &SeeksHM,
+    recsize: usize,
+    name: String,
+    selection: &Selection,
+) -> Option<Vec<(u64, usize)>> {
+    let (va, se) = (v.get(&name)?, s.get(&name)?);
+    let (start, count, stride) = (&selection.start, &selection.count, &selection.stride);
+    let size = va.nc_type.extsize();
+    if count.contains(&0) {
+        return Some(Vec::new());
+    }
+
+    // records are contiguous if a record holds nothing but this variable
+    let interleaved = va.is_record() && recsize != se[0] * size;
+    // dimensions split.. form a single run
+    let first = if interleaved { 1 } else { 0 };
+    let mut split = start.len();
+    let mut run = 1;
+    while split > first && stride[split - 1] == 1 {
+        split -= 1;
+        run *= count[split];
+        if split == 0 || count[split] * se[split] != se[split - 1] {
+            break;
+        }
+    }
+
+    let mut runs = Vec::new();
+    let mut index = start.to_vec();
+    loop {
+        let offset = calc_seek(v, s, recsize, name.clone(), &index)?;
+        runs.push((offset, run * size));
+        // advance the index over the dimensions outside of a run
+        let mut d = split;
+        loop {
+            if d == 0 {
+                return Some(runs);
+            }
+            d -= 1;
+            index[d] += stride[d];
+            if index[d] < start[d] + count[d] * stride[d] {
+                break;
+            }
+            index[d] = start[d];
+        }
+    }
+}
+
+/// Id of the first dimension named like one of `candidates`, in order of
+/// preference
 pub(crate) fn get_coordinate_dim_id(
-    dims: &DimensionHM,
+    header: &NetCDFHeader,
     candidates: &[&str],
 ) -> Result<usize, HSE<String>> {
-    for (k, d) in dims.iter() {
-        if candidates.contains(&&*d.name) {
-            return Ok(*k);
-        } else {
-            continue;
+    match candidates.iter().find_map(|c| header.dimension_id(c).ok()) {
+        Some(id) => Ok(id),
+        None => {
+            let msg = format!("Candidate space: {:?}", candidates);
+            Err(HSE::CouldNotFindDimension(msg))
         }
     }
-    let msg = format!("Candidate space: {:?}", candidates);
-    Err(HSE::CouldNotFindDimension(msg))
 }
 
 #[cfg(test)]
@@ -62,5 +141,18 @@ mod tests {
         let expected: Vec<usize> = vec![0, 72, 36, 4];
         let result = product_vector(&vecs, record);
         assert_eq!(result, expected);
+        let vecs: Vec<usize> = vec![3, 2, 9, 4];
+        let result = product_vector(&vecs, record);
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_get_coordinate_dim_id() {
+        let mut file = std::fs::File::open("assets/sresa1b_ncar_ccsm3-example.nc").unwrap();
+        let h = NetCDFHeader::from_file(&mut file).unwrap();
+        let lon = get_coordinate_dim_id(&h, &["longitude", "lon"]).unwrap();
+        assert_eq!(lon, h.dimension_id("lon").unwrap());
+        let e = get_coordinate_dim_id(&h, &["ncells"]).unwrap_err();
+        assert!(matches!(e, HSE::CouldNotFindDimension(_)));
     }
 }
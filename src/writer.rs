@@ -0,0 +1,293 @@
+//! Main serialization module
+//!
+//! # Writer
+//! Counterpart of the parser: turns a [`NetCDFHeader`] back into the bytes of
+//! a classic, 64-bit offset or CDF-5 header. Lists are written in declaration
+//! order, so parsing and writing a header reproduces it byte for byte.
+//! netCDF-4 headers cannot be serialized.
+use crate::constants_and_types as csts;
+use crate::error::HeadScratcherError as HSE;
+use crate::parser::components::{
+    AttributeHM, DimensionHM, NetCDFAttribute, NetCDFDimension, NetCDFType, NetCDFTypeInstance,
+    NetCDFVariable, NetCDFVersion, NumberOfRecords, VariableHM,
+};
+use crate::parser::NetCDFHeader;
+use std::convert::TryFrom;
+
+/// Result of the writer functions
+type WResult = Result<(), HSE<String>>;
+
+/// Serialize a complete NetCDF header [combined]
+pub fn header(h: &NetCDFHeader) -> Result<Vec<u8>, HSE<String>> {
+    let mut o = Vec::new();
+    magic(&mut o, h.version)?;
+    number_of_records(&mut o, &h.nor, h.version)?;
+    match &h.dims {
+        Some(dims) => dimension_list(&mut o, dims, h.version)?,
+        None => absent(&mut o, h.version)?,
+    }
+    match &h.attrs {
+        Some(attrs) => attribute_list(&mut o, attrs, h.version)?,
+        None => absent(&mut o, h.version)?,
+    }
+    match &h.vars {
+        Some(vars) => variable_list(&mut o, vars, h.version)?,
+        None => absent(&mut o, h.version)?,
+    }
+    Ok(o)
+}
+
+/// Write a list of NetCDF variables [combined]
+pub fn variable_list(o: &mut Vec<u8>, vars: &VariableHM, version: NetCDFVersion) -> WResult {
+    o.extend_from_slice(&csts::NC_VARIABLE.to_be_bytes());
+    non_neg(o, vars.len() as u64, version)?;
+    for (n, v) in vars.iter() {
+        variable(o, n, v, version)?;
+    }
+    Ok(())
+}
+
+/// Write a single NetCDF variable [combined]
+pub fn variable(o: &mut Vec<u8>, n: &str, v: &NetCDFVariable, version: NetCDFVersion) -> WResult {
+    name(o, n, version)?;
+    non_neg(o, v.dims.len() as u64, version)?;
+    for d in v.dims.iter() {
+        non_neg(o, *d as u64, version)?;
+    }
+    match v.attributes() {
+        Some(attrs) => attribute_list(o, attrs, version)?,
+        None => absent(o, version)?,
+    }
+    nc_type(o, &v.nc_type);
+    non_neg(o, v.vsize() as u64, version)?;
+    offset(o, v.begin, version)
+}
+
+/// Write a list of NetCDF attributes [combined]
+pub fn attribute_list(o: &mut Vec<u8>, attrs: &AttributeHM, version: NetCDFVersion) -> WResult {
+    o.extend_from_slice(&csts::NC_ATTRIBUTE.to_be_bytes());
+    non_neg(o, attrs.len() as u64, version)?;
+    for a in attrs.values() {
+        attribute(o, a, version)?;
+    }
+    Ok(())
+}
+
+/// Write a single NetCDF attribute [combined]
+pub fn attribute(o: &mut Vec<u8>, a: &NetCDFAttribute, version: NetCDFVersion) -> WResult {
+    name(o, &a.name(), version)?;
+    nc_type(o, a.nc_type());
+    let data = values(a.value());
+    non_neg(o, (data.len() / a.nc_type().extsize()) as u64, version)?;
+    o.extend_from_slice(&data);
+    padding(o, data.len());
+    Ok(())
+}
+
+/// Big endian bytes of attribute values [atomic]
+pub fn values(value: &NetCDFTypeInstance) -> Vec<u8> {
+    fn be<T, const N: usize>(v: &[T], f: fn(&T) -> [u8; N]) -> Vec<u8> {
+        v.iter().flat_map(f).collect()
+    }
+    match value {
+        NetCDFTypeInstance::STRING(s) => s.as_bytes().to_vec(),
+        NetCDFTypeInstance::BYTES(v) => be(v, |x| x.to_be_bytes()),
+        NetCDFTypeInstance::SHORTS(v) => be(v, |x| x.to_be_bytes()),
+        NetCDFTypeInstance::INTS(v) => be(v, |x| x.to_be_bytes()),
+        NetCDFTypeInstance::FLOATS(v) => be(v, |x| x.to_be_bytes()),
+        NetCDFTypeInstance::DOUBLES(v) => be(v, |x| x.to_be_bytes()),
+        NetCDFTypeInstance::UBYTES(v) => v.clone(),
+        NetCDFTypeInstance::USHORTS(v) => be(v, |x| x.to_be_bytes()),
+        NetCDFTypeInstance::UINTS(v) => be(v, |x| x.to_be_bytes()),
+        NetCDFTypeInstance::INT64S(v) => be(v, |x| x.to_be_bytes()),
+        NetCDFTypeInstance::UINT64S(v) => be(v, |x| x.to_be_bytes()),
+    }
+}
+
+/// Write a list of NetCDF dimensions [combined]
+pub fn dimension_list(o: &mut Vec<u8>, dims: &DimensionHM, version: NetCDFVersion) -> WResult {
+    o.extend_from_slice(&csts::NC_DIMENSION.to_be_bytes());
+    non_neg(o, dims.len() as u64, version)?;
+    for d in dims.values() {
+        dimension(o, d, version)?;
+    }
+    Ok(())
+}
+
+/// Write a single NetCDF dimension [combined]
+pub fn dimension(o: &mut Vec<u8>, d: &NetCDFDimension, version: NetCDFVersion) -> WResult {
+    name(o, &d.name, version)?;
+    non_neg(o, d.length as u64, version)
+}
+
+/// Write NetCDF data format type [atomic]
+pub fn nc_type(o: &mut Vec<u8>, t: &NetCDFType) {
+    let tag = match t {
+        NetCDFType::NC_BYTE => csts::NC_BYTE,
+        NetCDFType::NC_CHAR => csts::NC_CHAR,
+        NetCDFType::NC_SHORT => csts::NC_SHORT,
+        NetCDFType::NC_INT => csts::NC_INT,
+        NetCDFType::NC_FLOAT => csts::NC_FLOAT,
+        NetCDFType::NC_DOUBLE => csts::NC_DOUBLE,
+        NetCDFType::NC_UBYTE => csts::NC_UBYTE,
+        NetCDFType::NC_USHORT => csts::NC_USHORT,
+        NetCDFType::NC_UINT => csts::NC_UINT,
+        NetCDFType::NC_INT64 => csts::NC_INT64,
+        NetCDFType::NC_UINT64 => csts::NC_UINT64,
+    };
+    o.extend_from_slice(&tag.to_be_bytes());
+}
+
+/// Write the name of an element (dimension, variable, or attribute) [combined]
+pub fn name(o: &mut Vec<u8>, n: &str, version: NetCDFVersion) -> WResult {
+    non_neg(o, n.len() as u64, version)?;
+    o.extend_from_slice(n.as_bytes());
+    padding(o, n.len());
+    Ok(())
+}
+
+/// Write an absent list [combined]
+pub fn absent(o: &mut Vec<u8>, version: NetCDFVersion) -> WResult {
+    o.extend_from_slice(&csts::ZERO.to_be_bytes());
+    non_neg(o, 0, version)
+}
+
+/// Write length of record dimension [atomic]
+pub fn number_of_records(
+    o: &mut Vec<u8>,
+    nor: &NumberOfRecords,
+    version: NetCDFVersion,
+) -> WResult {
+    match (nor, version) {
+        (_, NetCDFVersion::NetCDF4) => return Err(HSE::NetCDF4NotSupported),
+        (NumberOfRecords::Streaming, NetCDFVersion::Data64) => {
+            o.extend_from_slice(&csts::STREAMING64.to_be_bytes())
+        }
+        (NumberOfRecords::Streaming, _) => o.extend_from_slice(&csts::STREAMING.to_be_bytes()),
+        (NumberOfRecords::NonNegative(n), _) => non_neg(o, *n, version)?,
+    }
+    Ok(())
+}
+
+/// Write NetCDF magic bytes [combined]
+///
+/// netCDF-4 headers are HDF5 files and fail with
+/// [`HSE::NetCDF4NotSupported`].
+pub fn magic(o: &mut Vec<u8>, version: NetCDFVersion) -> WResult {
+    let v = match version {
+        NetCDFVersion::Classic => 1,
+        NetCDFVersion::Offset64 => 2,
+        NetCDFVersion::Data64 => 5,
+        NetCDFVersion::NetCDF4 => return Err(HSE::NetCDF4NotSupported),
+    };
+    o.extend_from_slice(b"CDF");
+    o.push(v);
+    Ok(())
+}
+
+/// Write non negative numbers (64-bit for CDF-5) [atomic]
+pub fn non_neg(o: &mut Vec<u8>, value: u64, version: NetCDFVersion) -> WResult {
+    match version {
+        NetCDFVersion::Classic | NetCDFVersion::Offset64 => {
+            o.extend_from_slice(&narrow(value, version)?.to_be_bytes())
+        }
+        NetCDFVersion::Data64 => o.extend_from_slice(&value.to_be_bytes()),
+        NetCDFVersion::NetCDF4 => return Err(HSE::NetCDF4NotSupported),
+    }
+    Ok(())
+}
+
+/// Write variable start location (64-bit for 64-bit offset and CDF-5) [atomic]
+pub fn offset(o: &mut Vec<u8>, value: u64, version: NetCDFVersion) -> WResult {
+    match version {
+        NetCDFVersion::Classic => o.extend_from_slice(&narrow(value, version)?.to_be_bytes()),
+        NetCDFVersion::Offset64 | NetCDFVersion::Data64 => {
+            o.extend_from_slice(&value.to_be_bytes())
+        }
+        NetCDFVersion::NetCDF4 => return Err(HSE::NetCDF4NotSupported),
+    }
+    Ok(())
+}
+
+/// 32-bit representation of a value, if it fits [atomic]
+fn narrow(value: u64, version: NetCDFVersion) -> Result<u32, HSE<String>> {
+    u32::try_from(value).map_err(|_| {
+        let msg = format!("{} does not fit into 32 bits of {:?}", value, version);
+        HSE::ExceedsFormat(msg)
+    })
+}
+
+/// Pad `count` written bytes to the next 4-byte boundary [atomic]
+fn padding(o: &mut Vec<u8>, count: usize) {
+    let pad = (4 - count % 4) % 4;
+    o.extend(std::iter::repeat_n(0u8, pad));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use std::fs::File;
+    use std::io::Read;
+
+    fn file_bytes(filename: &str) -> Vec<u8> {
+        let mut i = Vec::new();
+        File::open(filename).unwrap().read_to_end(&mut i).unwrap();
+        i
+    }
+
+    #[test]
+    fn file_example_empty() {
+        let i = file_bytes("assets/empty.nc");
+        let (_, h) = parser::header(&i).unwrap();
+        assert_eq!(header(&h).unwrap(), i);
+    }
+
+    #[test]
+    fn file_example_small() {
+        let i = file_bytes("assets/small.nc");
+        let (rest, h) = parser::header(&i).unwrap();
+        let size = i.len() - rest.len();
+        assert_eq!(header(&h).unwrap(), &i[..size]);
+    }
+
+    #[test]
+    fn round_trip() {
+        for filename in [
+            "assets/sresa1b_ncar_ccsm3-example.nc",
+            "assets/sresa1b_ncar_ccsm3-example.3_nc64.nc",
+            "assets/testrh.nc",
+            "assets/cdf5.nc",
+        ] {
+            let i = file_bytes(filename);
+            let (rest, h) = parser::header(&i).unwrap();
+            let size = i.len() - rest.len();
+            assert_eq!(header(&h).unwrap(), &i[..size]);
+        }
+    }
+
+    #[test]
+    fn too_large() {
+        let mut o = Vec::new();
+        let e = non_neg(&mut o, 1 << 32, NetCDFVersion::Offset64).unwrap_err();
+        assert!(matches!(e, HSE::ExceedsFormat(_)));
+        non_neg(&mut o, 1 << 32, NetCDFVersion::Data64).unwrap();
+        let e = offset(&mut o, 1 << 32, NetCDFVersion::Classic).unwrap_err();
+        assert!(matches!(e, HSE::ExceedsFormat(_)));
+        offset(&mut o, 1 << 32, NetCDFVersion::Offset64).unwrap();
+        assert_eq!(o.len(), 16);
+    }
+
+    #[test]
+    fn netcdf4() {
+        let nor = NumberOfRecords::NonNegative(0);
+        let h = NetCDFHeader::new(NetCDFVersion::NetCDF4, nor, None, None, None, None, 0);
+        assert_eq!(header(&h).unwrap_err(), HSE::NetCDF4NotSupported);
+        let mut o = Vec::new();
+        let e = non_neg(&mut o, 0, NetCDFVersion::NetCDF4).unwrap_err();
+        assert_eq!(e, HSE::NetCDF4NotSupported);
+        let e = offset(&mut o, 0, NetCDFVersion::NetCDF4).unwrap_err();
+        assert_eq!(e, HSE::NetCDF4NotSupported);
+        assert!(o.is_empty());
+    }
+}